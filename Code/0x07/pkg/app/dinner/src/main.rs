@@ -5,7 +5,7 @@ extern crate alloc;
 extern crate lib;
 
 use lib::*;
-use rand::prelude::*;
+use ::rand::prelude::*;
 use rand_chacha::ChaCha20Rng;
 
 // 定义常量
@@ -18,7 +18,7 @@ static START_SEM: Semaphore = Semaphore::new(5);
 
 
 fn philosopher(id: usize) -> ! {
-    let mut rng = ChaCha20Rng::seed_from_u64(sys_get_pid() as u64);
+    let mut rng = ChaCha20Rng::from_seed(lib::rand::seed());
     let mut meal_count = 0;  // 记录就餐次数
     let left = id;
     let right = (id + 1) % PHILOSOPHER_COUNT;
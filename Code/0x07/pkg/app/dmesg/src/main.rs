@@ -0,0 +1,14 @@
+#![no_std]
+#![no_main]
+
+use lib::*;
+
+extern crate lib;
+
+fn main() -> isize {
+    print_dmesg(LOG_TRACE);
+
+    0
+}
+
+entry!(main);
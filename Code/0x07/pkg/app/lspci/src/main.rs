@@ -0,0 +1,14 @@
+#![no_std]
+#![no_main]
+
+use lib::*;
+
+extern crate lib;
+
+fn main() -> isize {
+    sys_list_pci();
+
+    0
+}
+
+entry!(main);
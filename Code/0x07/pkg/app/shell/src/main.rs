@@ -1,8 +1,9 @@
 #![no_std]
 #![no_main]
 
-use lib::{entry, print, println, stdin, sys_list_app, sys_stat, sys_spawn, sys_wait_pid, sys_list_dir, sys_open, sys_close, sys_read};
+use lib::{entry, print, println, stdin, sys_ioctl, sys_list_app, sys_list_blk, sys_list_pci, sys_stat, sys_sync, sys_spawn, sys_wait_pid, sys_list_dir, sys_open, sys_close, sys_read, tcgetattr, tcsetattr, log_level, print_dmesg, TTY_CONTINUE, WAIT_STOPPED, PARITY_EVEN, PARITY_ODD};
 
+use lib::alloc::format;
 use lib::alloc::vec::Vec;
 
 // 学号，请将它替换为您的实际学号
@@ -59,8 +60,15 @@ fn process_command(command: &str, args: &[&str]) {
             println!("  ls [路径]      列出目录内容（默认为根目录）");
             println!("  cat <文件>     显示文件内容");
             println!("  apps           列出所有可用的应用程序");
+            println!("  lsblk          列出所有块设备");
+            println!("  lspci          列出所有 PCI 设备");
+            println!("  dmesg [级别]   显示内核日志（error/warn/info/debug/trace，默认全部）");
+            println!("  sync           将磁盘缓存写回存储介质");
             println!("  ps             列出当前运行的所有进程");
+            println!("  date           显示当前时间（UTC）和开机时长");
             println!("  run <程序>     运行指定的程序（支持文件路径，如 /factorial）");
+            println!("  fg             继续运行被 Ctrl-Z 暂停的程序");
+            println!("  stty [波特率]  显示串口终端设置，或修改波特率");
             println!("  clear          清空屏幕");
             println!("  exit           退出Shell");
             println!("学号: {}", STUDENT_ID);
@@ -82,6 +90,29 @@ fn process_command(command: &str, args: &[&str]) {
             println!("可用的应用程序列表：");
             sys_list_app();
         },
+        "lsblk" => {
+            sys_list_blk();
+        },
+        "lspci" => {
+            sys_list_pci();
+        },
+        "dmesg" => {
+            let level = args.first().copied().unwrap_or("trace");
+            match log_level(level) {
+                Some(level) => print_dmesg(level),
+                None => println!("错误: 未知的日志级别 '{}'", level),
+            }
+        },
+        "sync" => {
+            if !sys_sync() {
+                println!("错误: 无法写回磁盘缓存");
+            }
+        },
+        "date" => {
+            let uptime = lib::uptime().num_seconds();
+            println!("{}", lib::now());
+            println!("已开机 {}:{:02}:{:02}", uptime / 3600, uptime / 60 % 60, uptime % 60);
+        },
         "ps" => {
             println!("当前运行的进程列表：");
             sys_stat();
@@ -100,12 +131,52 @@ fn process_command(command: &str, args: &[&str]) {
                     println!("错误: 无法运行程序 '{}'", program);
                 } else {
                     println!("进程ID: {}", pid);
+                    wait_job(program, pid);
+                }
+            }
+        },
+        "fg" => {
+            match sys_ioctl(0, TTY_CONTINUE, 0) {
+                Some(0) | None => println!("错误: 没有被暂停的程序"),
+                Some(pid) => {
+                    let pid = pid as u16;
+                    println!("继续运行进程: {}", pid);
+                    wait_job(&format!("#{}", pid), pid);
+                }
+            }
+        },
+        "stty" => {
+            let Some(mut attr) = tcgetattr(0) else {
+                println!("错误: 标准输入不是串口终端");
+                return;
+            };
 
-                    // 等待进程结束
-                    let exit_code = sys_wait_pid(pid);
-                    println!("程序 '{}' 已退出，返回值: {}", program, exit_code);
+            if let Some(baud) = args.first() {
+                let Ok(baud) = baud.parse() else {
+                    println!("错误: 无效的波特率 '{}'", baud);
+                    return;
+                };
+                attr.baud_rate = baud;
+                if !tcsetattr(0, &attr) {
+                    println!("错误: 不支持的波特率 {}（须整除 115200）", baud);
+                    return;
                 }
             }
+
+            let parity = match attr.parity {
+                PARITY_ODD => 'o',
+                PARITY_EVEN => 'e',
+                _ => 'n',
+            };
+            println!(
+                "{} {}{}{} fifo{} {}rtscts",
+                attr.baud_rate,
+                attr.data_bits,
+                parity,
+                attr.stop_bits,
+                attr.fifo_trigger,
+                if attr.flow_control { "" } else { "-" }
+            );
         },
         "clear" => {
             // 通过打印ANSI转义序列清空屏幕
@@ -136,9 +207,17 @@ fn execute_factorial_test() {
     
     println!("阶乘测试进程ID: {}", pid);
     
-    // 等待进程结束
+    wait_job("factorial", pid);
+}
+
+// 等待前台程序退出，或被 Ctrl-Z 暂停
+fn wait_job(program: &str, pid: u16) {
     let exit_code = sys_wait_pid(pid);
-    println!("阶乘测试程序已退出，返回值: {}", exit_code);
+    if exit_code == WAIT_STOPPED {
+        println!("程序 '{}' 已暂停，输入 fg 继续运行", program);
+    } else {
+        println!("程序 '{}' 已退出，返回值: {}", program, exit_code);
+    }
 }
 
 fn cat_file(filename: &str) {
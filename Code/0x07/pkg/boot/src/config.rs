@@ -19,6 +19,18 @@ pub struct Config<'a> {
     pub load_apps: bool,
     /// Log level for kernel logger
     pub log_level: &'a str,
+    /// Log level of the records printed to the console
+    pub console_loglevel: &'a str,
+    /// The block device holding the root filesystem, e.g. `hda1`
+    pub root_device: &'a str,
+    /// The layout of the PS/2 keyboard, e.g. `us`
+    pub keyboard_layout: &'a str,
+    /// What COM2 is used for: `tty`, `shell` or `log`
+    pub com2: &'a str,
+    /// Line settings of the serial ports, e.g. `115200,8n1,fifo8,rtscts`
+    pub serial: &'a str,
+    /// Frequency of the scheduler tick, in Hz
+    pub timer_hz: u64,
 }
 
 const DEFAULT_CONFIG: Config = Config {
@@ -30,6 +42,12 @@ const DEFAULT_CONFIG: Config = Config {
     cmdline: "",
     load_apps: false,
     log_level: "info",
+    console_loglevel: "info",
+    root_device: "hda1",
+    keyboard_layout: "us",
+    com2: "tty",
+    serial: "38400,8n1",
+    timer_hz: 100,
 };
 
 impl<'a> Config<'a> {
@@ -75,6 +93,12 @@ impl<'a> Config<'a> {
                 }
             },
             "log_level" => self.log_level = value,
+            "console_loglevel" => self.console_loglevel = value,
+            "root_device" => self.root_device = value,
+            "keyboard_layout" => self.keyboard_layout = value,
+            "com2" => self.com2 = value,
+            "serial" => self.serial = value,
+            "timer_hz" => self.timer_hz = r10,
             _ => warn!("undefined config key: {}", key),
         }
     }
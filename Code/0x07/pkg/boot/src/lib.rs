@@ -5,7 +5,7 @@ extern crate alloc;
 pub use uefi::boot::{MemoryAttribute, MemoryDescriptor, MemoryType};
 pub use uefi::data_types::chars::*;
 pub use uefi::data_types::*;
-pub use uefi::proto::console::gop::{GraphicsOutput, ModeInfo};
+pub use uefi::proto::console::gop::{GraphicsOutput, ModeInfo, PixelFormat};
 pub use uefi::Status;
 
 use arrayvec::{ArrayString, ArrayVec};
@@ -48,6 +48,17 @@ pub type AppList = ArrayVec<App, MAX_APP_NUM>;
 /// A reference to the list of loaded apps.
 pub type AppListRef<'a> = Option<&'a AppList>;
 
+/// The graphics mode and framebuffer left by the bootloader
+#[derive(Debug, Clone, Copy)]
+pub struct GraphicInfo {
+    /// The GOP mode, with the resolution, stride and pixel format
+    pub mode: ModeInfo,
+    /// Physical address of the framebuffer
+    pub fb_addr: u64,
+    /// Size of the framebuffer in bytes
+    pub fb_size: u64,
+}
+
 /// This structure represents the information that the bootloader passes to the kernel.
 pub struct BootInfo {
     /// The memory map
@@ -61,6 +72,27 @@ pub struct BootInfo {
     
     /// The log level for kernel logger
     pub log_level: &'static str,
+
+    /// The log level of the console
+    pub console_loglevel: &'static str,
+
+    /// The block device holding the root filesystem
+    pub root_device: &'static str,
+
+    /// The layout of the PS/2 keyboard
+    pub keyboard_layout: &'static str,
+
+    /// What COM2 is used for
+    pub com2: &'static str,
+
+    /// Line settings of the serial ports
+    pub serial: &'static str,
+
+    /// Frequency of the scheduler tick, in Hz
+    pub timer_hz: u64,
+
+    /// The framebuffer, None if there is no graphics output
+    pub graphic_info: Option<GraphicInfo>,
     
     /// Loaded apps
     pub loaded_apps: Option<AppList>,
@@ -69,6 +101,27 @@ pub struct BootInfo {
     pub kernel_pages: KernelPages,
 }
 
+/// Get the current mode and framebuffer of the graphics output
+///
+/// Returns None without a GOP or if the framebuffer cannot be written directly.
+pub fn graphic_info() -> Option<GraphicInfo> {
+    let handle = uefi::boot::get_handle_for_protocol::<GraphicsOutput>().ok()?;
+    let mut gop = uefi::boot::open_protocol_exclusive::<GraphicsOutput>(handle).ok()?;
+
+    let mode = gop.current_mode_info();
+    if mode.pixel_format() == PixelFormat::BltOnly {
+        return None;
+    }
+
+    let mut fb = gop.frame_buffer();
+
+    Some(GraphicInfo {
+        mode,
+        fb_addr: fb.as_mut_ptr() as u64,
+        fb_size: fb.size() as u64,
+    })
+}
+
 /// Get current page table from CR3
 pub fn current_page_table() -> OffsetPageTable<'static> {
     let p4_table_addr = Cr3::read().0.start_address().as_u64();
@@ -132,6 +132,18 @@ fn efi_main() -> Status {
     let system_table = ptr.cast::<core::ffi::c_void>();
 
 
+    // the framebuffer stays usable after exiting boot services
+    let graphic_info = graphic_info();
+    match &graphic_info {
+        Some(info) => info!(
+            "Framebuffer at {:#x}, {}x{}",
+            info.fb_addr,
+            info.mode.resolution().0,
+            info.mode.resolution().1
+        ),
+        None => info!("No framebuffer available"),
+    }
+
     // 6. Exit boot and jump to ELF entry
     info!("Exiting boot services...");
 
@@ -144,6 +156,13 @@ fn efi_main() -> Status {
         physical_memory_offset: config.physical_memory_offset,
         system_table,
         log_level: config.log_level,
+        console_loglevel: config.console_loglevel,
+        root_device: config.root_device,
+        keyboard_layout: config.keyboard_layout,
+        com2: config.com2,
+        serial: config.serial,
+        timer_hz: config.timer_hz,
+        graphic_info,
         loaded_apps: apps,
         kernel_pages,
     };
@@ -0,0 +1,119 @@
+//! Fixed ACPI Description Table and High Precision Event Timer table
+//!
+//! Only the timers are read: the ACPI PM timer described by the FADT and
+//! the HPET described by its own table.
+//!
+//! reference: https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#fixed-acpi-description-table-fadt
+//! reference: https://wiki.osdev.org/HPET
+
+use super::Sdt;
+
+/// Frequency of the ACPI PM timer
+pub const PM_TIMER_HZ: u64 = 3_579_545;
+
+/// FADT flag, the PM timer counts on 32 bits instead of 24
+const TMR_VAL_EXT: u32 = 1 << 8;
+
+/// Address space of a generic address structure
+const GAS_SYSTEM_MEMORY: u8 = 0;
+const GAS_SYSTEM_IO: u8 = 1;
+
+/// Where a register is, a generic address structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiAddress {
+    Memory(u64),
+    Io(u16),
+}
+
+impl AcpiAddress {
+    /// Decode the generic address structure at `offset`
+    fn parse(table: &Sdt, offset: usize) -> Option<Self> {
+        let addr = table.u64_at(offset + 4);
+        match table.u8_at(offset) {
+            _ if addr == 0 => None,
+            GAS_SYSTEM_MEMORY => Some(Self::Memory(addr)),
+            GAS_SYSTEM_IO => Some(Self::Io(addr as u16)),
+            space => {
+                warn!("ACPI: unsupported address space {}", space);
+                None
+            }
+        }
+    }
+}
+
+/// The ACPI power management timer, counting up at `PM_TIMER_HZ`
+#[derive(Debug, Clone, Copy)]
+pub struct PmTimer {
+    pub addr: AcpiAddress,
+    /// The counter has 32 bits, 24 otherwise
+    pub extended: bool,
+}
+
+impl PmTimer {
+    pub(super) fn parse(fadt: &Sdt) -> Option<Self> {
+        const PM_TMR_BLK: usize = 76;
+        const PM_TMR_LEN: usize = 91;
+        const FLAGS: usize = 112;
+        const X_PM_TMR_BLK: usize = 208;
+
+        if fadt.len() < FLAGS + 4 || fadt.u8_at(PM_TMR_LEN) < 4 {
+            return None;
+        }
+
+        // the 64-bit address of ACPI 2.0 comes first if present
+        let addr = if fadt.len() >= X_PM_TMR_BLK + 12 {
+            AcpiAddress::parse(fadt, X_PM_TMR_BLK)
+        } else {
+            None
+        };
+        let addr = addr.or_else(|| {
+            let port = fadt.u32_at(PM_TMR_BLK);
+            (port != 0).then_some(AcpiAddress::Io(port as u16))
+        })?;
+
+        Some(Self {
+            addr,
+            extended: fadt.u32_at(FLAGS) & TMR_VAL_EXT != 0,
+        })
+    }
+}
+
+/// The high precision event timer block
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    /// Physical address of the registers
+    pub addr: u64,
+    pub number: u8,
+    pub comparators: u8,
+    /// The main counter has 64 bits, 32 otherwise
+    pub counter_64bit: bool,
+    /// The minimal period of a periodic comparator, in counter ticks
+    pub min_tick: u16,
+}
+
+impl Hpet {
+    pub(super) fn parse(table: &Sdt) -> Option<Self> {
+        const BLOCK_ID: usize = 36;
+        const BASE_ADDRESS: usize = 40;
+        const NUMBER: usize = 52;
+        const MIN_TICK: usize = 53;
+
+        if table.len() < MIN_TICK + 2 {
+            return None;
+        }
+
+        let AcpiAddress::Memory(addr) = AcpiAddress::parse(table, BASE_ADDRESS)? else {
+            warn!("ACPI: HPET not in memory space");
+            return None;
+        };
+
+        let block_id = table.u32_at(BLOCK_ID);
+        Some(Self {
+            addr,
+            number: table.u8_at(NUMBER),
+            comparators: ((block_id >> 8) & 0x1F) as u8 + 1,
+            counter_64bit: block_id & (1 << 13) != 0,
+            min_tick: table.u16_at(MIN_TICK),
+        })
+    }
+}
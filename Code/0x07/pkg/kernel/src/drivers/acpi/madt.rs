@@ -0,0 +1,161 @@
+//! Multiple APIC Description Table
+//!
+//! reference: https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#multiple-apic-description-table-madt
+//! reference: https://wiki.osdev.org/MADT
+
+use super::Sdt;
+use alloc::vec::Vec;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_ADDRESS: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+/// The CPU can be used now
+const CPU_ENABLED: u32 = 1 << 0;
+/// The CPU can be brought online later
+const CPU_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// Polarity and trigger mode of an interrupt source override
+const POLARITY_MASK: u16 = 0b11;
+const POLARITY_ACTIVE_LOW: u16 = 0b11;
+const TRIGGER_MASK: u16 = 0b11 << 2;
+const TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+/// A CPU and its local APIC
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub processor_id: u32,
+    pub apic_id: u32,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub addr: u64,
+    /// The first global system interrupt it handles
+    pub gsi_base: u32,
+}
+
+/// How an ISA IRQ is wired to the IOAPICs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsaIrq {
+    pub irq: u8,
+    /// Global system interrupt
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+#[derive(Debug)]
+pub struct Madt {
+    pub local_apic_addr: u64,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicEntry>,
+    /// The ISA IRQs not wired to the GSI of the same number
+    pub overrides: Vec<IsaIrq>,
+}
+
+impl Madt {
+    pub(super) fn parse(table: &Sdt) -> Option<Self> {
+        // the header, the LAPIC address and the flags
+        const ENTRIES: usize = 44;
+
+        if table.len() < ENTRIES {
+            warn!("ACPI: truncated MADT");
+            return None;
+        }
+
+        let mut madt = Self {
+            local_apic_addr: table.u32_at(36) as u64,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+        };
+
+        // the entries follow the LAPIC address and the flags
+        let mut entries = &table.body()[8..];
+        while let [kind, len, ..] = *entries {
+            let len = len as usize;
+            if len < 2 || len > entries.len() {
+                warn!("ACPI: truncated MADT entry of type {}", kind);
+                break;
+            }
+            madt.parse_entry(kind, &entries[..len]);
+            entries = &entries[len..];
+        }
+
+        Some(madt)
+    }
+
+    fn parse_entry(&mut self, kind: u8, entry: &[u8]) {
+        let u16_at = |offset: usize| u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_le_bytes(entry[offset..offset + 4].try_into().unwrap());
+
+        match (kind, entry.len()) {
+            (ENTRY_LOCAL_APIC, 8..) => self.push_processor(entry[2] as u32, entry[3] as u32, u32_at(4)),
+            (ENTRY_LOCAL_X2APIC, 16..) => self.push_processor(u32_at(12), u32_at(4), u32_at(8)),
+            (ENTRY_IO_APIC, 12..) => self.io_apics.push(IoApicEntry {
+                id: entry[2],
+                addr: u32_at(4) as u64,
+                gsi_base: u32_at(8),
+            }),
+            (ENTRY_INTERRUPT_OVERRIDE, 10..) => {
+                let flags = u16_at(8);
+                self.overrides.push(IsaIrq {
+                    irq: entry[3],
+                    gsi: u32_at(4),
+                    active_low: flags & POLARITY_MASK == POLARITY_ACTIVE_LOW,
+                    level_triggered: flags & TRIGGER_MASK == TRIGGER_LEVEL,
+                });
+            }
+            (ENTRY_LOCAL_APIC_ADDRESS, 12..) => {
+                self.local_apic_addr = u64::from_le_bytes(entry[4..12].try_into().unwrap());
+            }
+            _ => trace!("ACPI: skip MADT entry of type {}", kind),
+        }
+    }
+
+    fn push_processor(&mut self, processor_id: u32, apic_id: u32, flags: u32) {
+        // neither enabled nor online capable, the CPU is not there
+        if flags & (CPU_ENABLED | CPU_ONLINE_CAPABLE) == 0 {
+            return;
+        }
+
+        self.processors.push(Processor {
+            processor_id,
+            apic_id,
+            enabled: flags & CPU_ENABLED != 0,
+        });
+    }
+
+    /// How ISA `irq` is wired, to the GSI of the same number, active high
+    /// and edge triggered unless overridden
+    pub fn isa_irq(&self, irq: u8) -> IsaIrq {
+        self.overrides
+            .iter()
+            .find(|entry| entry.irq == irq)
+            .copied()
+            .unwrap_or(IsaIrq {
+                irq,
+                gsi: irq as u32,
+                active_low: false,
+                level_triggered: false,
+            })
+    }
+
+    /// The IOAPIC handling `gsi`, the one with the highest base below it
+    pub fn io_apic_for(&self, gsi: u32) -> Option<&IoApicEntry> {
+        self.io_apics
+            .iter()
+            .filter(|io_apic| io_apic.gsi_base <= gsi)
+            .max_by_key(|io_apic| io_apic.gsi_base)
+    }
+
+    /// The highest APIC ID of the CPUs, they are indexed by it
+    pub fn max_apic_id(&self) -> Option<u32> {
+        self.processors.iter().map(|cpu| cpu.apic_id).max()
+    }
+}
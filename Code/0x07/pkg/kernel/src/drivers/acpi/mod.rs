@@ -0,0 +1,195 @@
+//! ACPI tables
+//!
+//! The RSDP is found through the UEFI configuration table, the RSDT or XSDT
+//! it points to lists the other tables. Only what the kernel uses is kept:
+//! the CPUs, IOAPICs and interrupt source overrides of the MADT, the PM
+//! timer of the FADT and the HPET.
+//!
+//! reference: https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html
+//! reference: https://wiki.osdev.org/RSDP
+//! reference: https://wiki.osdev.org/RSDT
+
+mod fadt;
+mod madt;
+
+pub use fadt::*;
+pub use madt::*;
+
+use crate::memory::physical_to_virtual;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Once;
+
+const RSDP_SIGNATURE: &[u8] = b"RSD PTR ";
+/// Length of the RSDP of ACPI 1.0, covered by the first checksum
+const RSDP_V1_LENGTH: usize = 20;
+/// Length of the RSDP of ACPI 2.0, with the XSDT
+const RSDP_V2_LENGTH: usize = 36;
+/// Length of the header of every other table
+const SDT_HEADER_LENGTH: usize = 36;
+
+/// What the ACPI tables say about the machine
+#[derive(Debug)]
+pub struct AcpiInfo {
+    pub revision: u8,
+    pub oem_id: String,
+    pub madt: Option<Madt>,
+    pub pm_timer: Option<PmTimer>,
+    pub hpet: Option<Hpet>,
+}
+
+static ACPI: Once<AcpiInfo> = Once::new();
+
+/// The tables parsed by `init`, None if they were not found
+pub fn info() -> Option<&'static AcpiInfo> {
+    ACPI.get()
+}
+
+/// A table in physical memory, read as bytes
+#[derive(Clone, Copy)]
+pub(crate) struct Sdt {
+    data: &'static [u8],
+}
+
+impl Sdt {
+    /// Map the table at `addr` if its header and checksum are valid
+    unsafe fn at(addr: u64) -> Option<Self> {
+        let ptr = physical_to_virtual(addr) as *const u8;
+        let header = unsafe { core::slice::from_raw_parts(ptr, SDT_HEADER_LENGTH) };
+        let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        if length < SDT_HEADER_LENGTH {
+            return None;
+        }
+
+        let data = unsafe { core::slice::from_raw_parts(ptr, length) };
+        if checksum(data) != 0 {
+            warn!("ACPI: bad checksum of table {}", String::from_utf8_lossy(&data[..4]));
+            return None;
+        }
+
+        Some(Self { data })
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.data[..4]
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn u8_at(&self, offset: usize) -> u8 {
+        self.data[offset]
+    }
+
+    pub fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.data[offset..offset + 2].try_into().unwrap())
+    }
+
+    pub fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    pub fn u64_at(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.data[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// The bytes following the header
+    pub fn body(&self) -> &'static [u8] {
+        &self.data[SDT_HEADER_LENGTH..]
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Physical address of the RSDP, the ACPI 2.0 one if there is one
+fn find_rsdp() -> Option<u64> {
+    use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID};
+
+    uefi::system::with_config_table(|entries| {
+        let find = |guid| entries.iter().find(|entry| entry.guid == guid);
+        find(ACPI2_GUID)
+            .or_else(|| find(ACPI_GUID))
+            .map(|entry| entry.address as u64)
+    })
+}
+
+/// The tables listed by the RSDP at `addr`, with its revision and OEM
+fn root_tables(addr: u64) -> Option<(u8, String, Vec<Sdt>)> {
+    let ptr = physical_to_virtual(addr) as *const u8;
+    let rsdp = unsafe { core::slice::from_raw_parts(ptr, RSDP_V1_LENGTH) };
+    if &rsdp[..8] != RSDP_SIGNATURE || checksum(rsdp) != 0 {
+        warn!("ACPI: invalid RSDP at {:#x}", addr);
+        return None;
+    }
+
+    let revision = rsdp[15];
+    let oem_id = String::from_utf8_lossy(&rsdp[9..15]).trim_end().into();
+    let rsdt_addr = u32::from_le_bytes(rsdp[16..20].try_into().unwrap()) as u64;
+
+    // the XSDT of ACPI 2.0 holds 64-bit addresses
+    let xsdt_addr = if revision >= 2 {
+        let rsdp = unsafe { core::slice::from_raw_parts(ptr, RSDP_V2_LENGTH) };
+        Some(u64::from_le_bytes(rsdp[24..32].try_into().unwrap())).filter(|addr| *addr != 0)
+    } else {
+        None
+    };
+
+    let (root, entry_size) = match xsdt_addr {
+        Some(addr) => (unsafe { Sdt::at(addr) }?, 8),
+        None => (unsafe { Sdt::at(rsdt_addr) }?, 4),
+    };
+
+    let tables = root
+        .body()
+        .chunks_exact(entry_size)
+        .map(|entry| match entry_size {
+            8 => u64::from_le_bytes(entry.try_into().unwrap()),
+            _ => u32::from_le_bytes(entry.try_into().unwrap()) as u64,
+        })
+        .filter_map(|addr| unsafe { Sdt::at(addr) })
+        .collect();
+
+    Some((revision, oem_id, tables))
+}
+
+/// Find and parse the ACPI tables, the UEFI system table must be set
+pub fn init() {
+    let Some(rsdp) = find_rsdp() else {
+        warn!("ACPI: no RSDP in the UEFI configuration table");
+        return;
+    };
+
+    let Some((revision, oem_id, tables)) = root_tables(rsdp) else {
+        return;
+    };
+
+    let find = |signature: &[u8]| tables.iter().find(|table| table.signature() == signature);
+
+    let info = AcpiInfo {
+        revision,
+        oem_id,
+        madt: find(b"APIC").and_then(Madt::parse),
+        pm_timer: find(b"FACP").and_then(PmTimer::parse),
+        hpet: find(b"HPET").and_then(Hpet::parse),
+    };
+
+    info!(
+        "ACPI {} ({}): {} tables, {} CPUs, {} IOAPICs",
+        if revision >= 2 { "2.0+" } else { "1.0" },
+        info.oem_id,
+        tables.len(),
+        info.madt.as_ref().map_or(0, |madt| madt.processors.len()),
+        info.madt.as_ref().map_or(0, |madt| madt.io_apics.len()),
+    );
+    if let Some(pm_timer) = &info.pm_timer {
+        debug!("ACPI: {:x?}", pm_timer);
+    }
+    if let Some(hpet) = &info.hpet {
+        debug!("ACPI: {:x?}", hpet);
+    }
+
+    ACPI.call_once(|| info);
+}
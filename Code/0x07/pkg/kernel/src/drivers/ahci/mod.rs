@@ -0,0 +1,301 @@
+//! AHCI SATA Controller
+//!
+//! Drives on the ports of the HBA are accessed with READ/WRITE DMA EXT,
+//! one command at a time through a bounce buffer, see `blkdev::dma`.
+//!
+//! reference: https://wiki.osdev.org/AHCI
+//! reference: Serial ATA AHCI 1.3.1 Specification
+
+mod port;
+
+use crate::drivers::blkdev::dma::{self, DeviceLock, DmaDisk, WaitQueue, SECTOR_SIZE};
+use crate::drivers::{ata, blkdev, pci};
+use crate::interrupt::{self, Irq};
+use crate::memory::physical_to_virtual;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU8, Ordering};
+use port::{Port, BUFFER_SIZE, SIG_ATA};
+use storage::{Block512, BlockDevice};
+
+// HBA registers
+const HBA_CAP: usize = 0x00;
+const HBA_GHC: usize = 0x04;
+const HBA_IS: usize = 0x08;
+const HBA_PI: usize = 0x0C;
+const HBA_VS: usize = 0x10;
+
+/// CAP bit 31: 64-bit addressing is supported
+const CAP_S64A: u32 = 1 << 31;
+
+/// GHC bit 31: AHCI enable, bit 1: interrupt enable
+const GHC_AE: u32 = 1 << 31;
+const GHC_IE: u32 = 1 << 1;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AtaCommand {
+    ReadDmaExt = 0x25,
+    WriteDmaExt = 0x35,
+    CacheFlushExt = 0xEA,
+    IdentifyDevice = 0xEC,
+}
+
+/// Processes waiting for a command to complete or for a port to be released
+static WAIT_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Drives are named sda, sdb... in the order they are found
+static NEXT_INDEX: AtomicU8 = AtomicU8::new(0);
+
+static DRIVER: pci::PciDriver = pci::PciDriver {
+    name: "ahci",
+    // mass storage controller, SATA, AHCI 1.0
+    matches: &[pci::PciMatch::ClassProgIf(0x01, 0x06, 0x01)],
+    probe,
+};
+
+pub fn init() {
+    pci::register_driver(&DRIVER);
+}
+
+/// Handle the HBA interrupt, shared by all controllers
+///
+/// The port status is checked and cleared by the woken process.
+pub fn handle_irq() {
+    WAIT_QUEUE.wake_up_all();
+}
+
+fn read_hba(abar: u64, reg: usize) -> u32 {
+    unsafe { ((abar as usize + reg) as *const u32).read_volatile() }
+}
+
+fn write_hba(abar: u64, reg: usize, value: u32) {
+    unsafe { ((abar as usize + reg) as *mut u32).write_volatile(value) }
+}
+
+fn probe(dev: &pci::PciDevice) -> bool {
+    // ABAR, the HBA memory registers are in BAR5
+    let Some((addr, size)) = dev.bars[5].and_then(|bar| bar.memory()) else {
+        warn!("AHCI {}: no ABAR", dev.addr);
+        return false;
+    };
+
+    if addr + size > u32::MAX as u64 + 1 {
+        warn!("AHCI {}: ABAR at {:#x} is not mapped", dev.addr, addr);
+        return false;
+    }
+
+    dev.addr.enable_bus_master();
+
+    let interrupt = match dev.msi().zip(interrupt::msi_target(Irq::Ahci)) {
+        Some((msi, (apic_id, vector))) => {
+            msi.enable(apic_id, vector);
+            true
+        }
+        None => false,
+    };
+
+    let abar = physical_to_virtual(addr);
+    write_hba(abar, HBA_GHC, read_hba(abar, HBA_GHC) | GHC_AE);
+
+    let cap = read_hba(abar, HBA_CAP);
+    let ports = read_hba(abar, HBA_PI);
+    let version = read_hba(abar, HBA_VS);
+
+    info!(
+        "AHCI {} version {}.{}, {} ports, ports implemented {:#x}",
+        dev.addr,
+        version >> 16,
+        (version >> 8) & 0xFF,
+        (cap & 0x1F) + 1,
+        ports
+    );
+
+    // clear the pending interrupts before enabling them
+    write_hba(abar, HBA_IS, u32::MAX);
+    if interrupt {
+        write_hba(abar, HBA_GHC, read_hba(abar, HBA_GHC) | GHC_IE);
+    }
+
+    for index in (0..32).filter(|i| ports & (1 << i) != 0) {
+        let regs = Port::registers(abar, index);
+
+        if !Port::is_present(regs) {
+            continue;
+        }
+
+        if Port::signature(regs) != SIG_ATA {
+            debug!(
+                "AHCI port {}: signature {:#x} not supported",
+                index,
+                Port::signature(regs)
+            );
+            continue;
+        }
+
+        match AhciDrive::new(abar, index, cap & CAP_S64A != 0, interrupt) {
+            Ok(drive) => {
+                let drive_index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+                let name = format!("sd{}", (b'a' + drive_index) as char);
+
+                info!(
+                    "AHCI port {}: {} {}, {} sectors",
+                    index, name, drive.model, drive.blocks
+                );
+
+                let model = drive.model.clone();
+                blkdev::register_disk(&name, &model, Arc::new(drive));
+            }
+            Err(err) => warn!("AHCI port {}: {}", index, err),
+        }
+    }
+
+    true
+}
+
+/// A SATA drive on a port of the HBA
+#[derive(Clone)]
+pub struct AhciDrive {
+    port: Arc<DeviceLock<Port>>,
+    blocks: u64,
+    model: Box<str>,
+    serial: Box<str>,
+    /// Completion interrupts are delivered, otherwise the port is polled
+    interrupt: bool,
+}
+
+impl AhciDrive {
+    fn new(abar: u64, index: u8, dma64: bool, interrupt: bool) -> Result<Self, &'static str> {
+        let mut drive = Self {
+            port: Arc::new(DeviceLock::new(
+                Port::new(abar, index, dma64, interrupt)?,
+                &WAIT_QUEUE,
+            )),
+            blocks: 0,
+            model: Box::from(""),
+            serial: Box::from(""),
+            interrupt,
+        };
+
+        let mut res = [0u16; 256];
+        {
+            let mut port = drive.port.lock();
+            drive.command(&mut port, AtaCommand::IdentifyDevice, 0, 0, SECTOR_SIZE)?;
+
+            for (word, bytes) in res.iter_mut().zip(port.buffer(SECTOR_SIZE).chunks(2)) {
+                *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+            }
+        }
+
+        // Word 83 bit 10: 48-bit LBA is supported, which the commands require
+        if res[83] & (1 << 10) == 0 {
+            return Err("48-bit LBA is not supported");
+        }
+
+        let (model, serial) = ata::identify_strings(&res);
+        drive.model = model;
+        drive.serial = serial;
+        drive.blocks = res[100..104]
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &word| (acc << 16) | word as u64);
+
+        Ok(drive)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// Issue a command transferring `len` bytes of the buffer and wait for it
+    fn command(
+        &self,
+        port: &mut Port,
+        command: AtaCommand,
+        lba: u64,
+        count: u16,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let write = command == AtaCommand::WriteDmaExt;
+        port.issue(command as u8, lba, count, write, len);
+
+        WAIT_QUEUE.wait(self.interrupt, || port.is_done());
+        port.finish()
+    }
+
+    /// Write the cached data of the drive to the medium
+    pub fn flush(&self) -> Result<(), &'static str> {
+        let mut port = self.port.lock();
+        self.command(&mut port, AtaCommand::CacheFlushExt, 0, 0, 0)
+    }
+}
+
+impl DmaDisk for AhciDrive {
+    type Device = Port;
+
+    fn device(&self) -> &DeviceLock<Port> {
+        &self.port
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    fn max_transfer(&self) -> usize {
+        BUFFER_SIZE
+    }
+
+    fn bounce_buffer(port: &mut Port, len: usize) -> &mut [u8] {
+        port.buffer_mut(len)
+    }
+
+    fn transfer(
+        &self,
+        port: &mut Port,
+        block: u64,
+        count: usize,
+        write: bool,
+    ) -> Result<(), &'static str> {
+        let command = if write {
+            AtaCommand::WriteDmaExt
+        } else {
+            AtaCommand::ReadDmaExt
+        };
+        self.command(port, command, block, count as u16, count * SECTOR_SIZE)
+    }
+}
+
+impl BlockDevice<Block512> for AhciDrive {
+    fn block_count(&self) -> storage::FsResult<usize> {
+        dma::block_count(self)
+    }
+
+    fn read_block(&self, offset: usize, block: &mut Block512) -> storage::FsResult {
+        dma::read_block(self, offset, block)
+    }
+
+    fn write_block(&self, offset: usize, block: &Block512) -> storage::FsResult {
+        dma::write_block(self, offset, block)
+    }
+
+    fn read_blocks(&self, offset: usize, blocks: &mut [Block512]) -> storage::FsResult {
+        dma::read_blocks(self, offset, blocks)
+    }
+
+    fn write_blocks(&self, offset: usize, blocks: &[Block512]) -> storage::FsResult {
+        dma::write_blocks(self, offset, blocks)
+    }
+
+    fn flush(&self) -> storage::FsResult {
+        AhciDrive::flush(self).map_err(|_| storage::DeviceError::WriteError.into())
+    }
+}
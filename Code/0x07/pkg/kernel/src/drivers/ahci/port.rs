@@ -0,0 +1,331 @@
+//! AHCI port
+//!
+//! Each port gets one frame holding its command list, its received FIS
+//! area and the command table of slot 0, the only slot used.
+
+use crate::memory::{get_frame_alloc_for_sure, physical_to_virtual, FRAME_SIZE};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+
+/// Frames of the bounce buffer of each port
+const BUFFER_FRAMES: usize = 16;
+
+/// Size of the bounce buffer in bytes
+pub(super) const BUFFER_SIZE: usize = BUFFER_FRAMES * FRAME_SIZE as usize;
+
+// port registers
+const PORT_CLB: usize = 0x00;
+const PORT_CLBU: usize = 0x04;
+const PORT_FB: usize = 0x08;
+const PORT_FBU: usize = 0x0C;
+const PORT_IS: usize = 0x10;
+const PORT_IE: usize = 0x14;
+const PORT_CMD: usize = 0x18;
+const PORT_TFD: usize = 0x20;
+const PORT_SIG: usize = 0x24;
+const PORT_SSTS: usize = 0x28;
+const PORT_SERR: usize = 0x30;
+const PORT_CI: usize = 0x38;
+
+// layout of the port frame
+const CMD_LIST_OFFSET: u64 = 0;
+const FIS_OFFSET: u64 = 0x400;
+const CMD_TABLE_OFFSET: u64 = 0x800;
+/// The PRD table follows the command FIS, the ATAPI command and a reserved area
+const PRDT_OFFSET: u64 = CMD_TABLE_OFFSET + 0x80;
+
+/// Signature of a SATA drive, ATAPI drives and port multipliers differ
+pub(super) const SIG_ATA: u32 = 0x0000_0101;
+
+/// Register FIS, host to device
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+
+bitflags! {
+    /// Port command and status register
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct PortCommand: u32 {
+        const START             = 1 << 0;
+        const SPIN_UP           = 1 << 1;
+        const POWER_ON          = 1 << 2;
+        const FIS_RECEIVE       = 1 << 4;
+        const FIS_RUNNING       = 1 << 14;
+        const COMMAND_RUNNING   = 1 << 15;
+    }
+}
+
+bitflags! {
+    /// Port interrupt status and enable registers
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct PortInterrupt: u32 {
+        const D2H_REGISTER      = 1 << 0;
+        const PIO_SETUP         = 1 << 1;
+        const DMA_SETUP         = 1 << 2;
+        const SET_DEVICE_BITS   = 1 << 3;
+        const DESCRIPTOR_DONE   = 1 << 5;
+        const TASK_FILE_ERROR   = 1 << 30;
+    }
+}
+
+/// Task file status bits
+const TFD_ERROR: u32 = 0x01;
+const TFD_DRQ: u32 = 0x08;
+const TFD_BUSY: u32 = 0x80;
+
+/// A port of the HBA with a drive attached
+#[derive(Debug)]
+pub(crate) struct Port {
+    /// Virtual address of the HBA registers
+    abar: u64,
+    index: u8,
+    /// Virtual address of the port registers
+    regs: u64,
+    /// Physical address of the port frame
+    mem: u64,
+    /// Physical address of the bounce buffer
+    buffer: u64,
+}
+
+impl Port {
+    /// The registers of port `index` of the HBA at the virtual address `abar`
+    pub fn registers(abar: u64, index: u8) -> u64 {
+        abar + 0x100 + index as u64 * 0x80
+    }
+
+    fn read(regs: u64, reg: usize) -> u32 {
+        unsafe { ((regs as usize + reg) as *const u32).read_volatile() }
+    }
+
+    fn write(regs: u64, reg: usize, value: u32) {
+        unsafe { ((regs as usize + reg) as *mut u32).write_volatile(value) }
+    }
+
+    /// Returns true if a device is present and the link is up
+    pub fn is_present(regs: u64) -> bool {
+        let ssts = Self::read(regs, PORT_SSTS);
+        // DET = 3: device present and communication established,
+        // IPM = 1: interface in active state
+        ssts & 0xF == 3 && (ssts >> 8) & 0xF == 1
+    }
+
+    pub fn signature(regs: u64) -> u32 {
+        Self::read(regs, PORT_SIG)
+    }
+
+    /// Set up the memory of port `index` and start it
+    ///
+    /// `dma64` tells if the HBA can address memory above 4 GiB.
+    pub fn new(abar: u64, index: u8, dma64: bool, interrupt: bool) -> Result<Self, &'static str> {
+        let regs = Self::registers(abar, index);
+
+        let (mem, buffer) = {
+            let mut alloc = get_frame_alloc_for_sure();
+            let mem = alloc.allocate_frame().ok_or("Out of memory")?;
+            let Some(buffer) = alloc.allocate_contiguous(BUFFER_FRAMES) else {
+                unsafe { alloc.deallocate_frame(mem) };
+                return Err("Out of memory");
+            };
+
+            let end = mem.start_address().max(buffer.start_address()) + BUFFER_SIZE as u64;
+            if !dma64 && end.as_u64() > u32::MAX as u64 {
+                // give the frames back, the HBA cannot reach them
+                unsafe {
+                    alloc.deallocate_frame(mem);
+                    for frame in PhysFrame::range(buffer, buffer + BUFFER_FRAMES as u64) {
+                        alloc.deallocate_frame(frame);
+                    }
+                }
+                return Err("DMA memory is above 4 GiB");
+            }
+
+            (mem.start_address().as_u64(), buffer.start_address().as_u64())
+        };
+
+        // the frames are not zeroed by the allocator
+        unsafe {
+            core::ptr::write_bytes(physical_to_virtual(mem) as *mut u8, 0, FRAME_SIZE as usize);
+        }
+
+        let mut port = Self {
+            abar,
+            index,
+            regs,
+            mem,
+            buffer,
+        };
+
+        port.stop()?;
+
+        let cmd_list = mem + CMD_LIST_OFFSET;
+        let fis = mem + FIS_OFFSET;
+        Self::write(regs, PORT_CLB, cmd_list as u32);
+        Self::write(regs, PORT_CLBU, (cmd_list >> 32) as u32);
+        Self::write(regs, PORT_FB, fis as u32);
+        Self::write(regs, PORT_FBU, (fis >> 32) as u32);
+
+        // command header of slot 0 points to the command table
+        let table = mem + CMD_TABLE_OFFSET;
+        let header = physical_to_virtual(cmd_list) as *mut u32;
+        unsafe {
+            header.add(2).write_volatile(table as u32);
+            header.add(3).write_volatile((table >> 32) as u32);
+        }
+
+        // both are write 1 to clear
+        Self::write(regs, PORT_SERR, u32::MAX);
+        Self::write(regs, PORT_IS, u32::MAX);
+
+        let ie = if interrupt {
+            PortInterrupt::D2H_REGISTER
+                | PortInterrupt::PIO_SETUP
+                | PortInterrupt::DMA_SETUP
+                | PortInterrupt::SET_DEVICE_BITS
+                | PortInterrupt::DESCRIPTOR_DONE
+                | PortInterrupt::TASK_FILE_ERROR
+        } else {
+            PortInterrupt::empty()
+        };
+        Self::write(regs, PORT_IE, ie.bits());
+
+        port.start()?;
+
+        Ok(port)
+    }
+
+    fn command(&self) -> PortCommand {
+        PortCommand::from_bits_retain(Self::read(self.regs, PORT_CMD))
+    }
+
+    fn set_command(&mut self, command: PortCommand) {
+        Self::write(self.regs, PORT_CMD, command.bits());
+    }
+
+    /// Stop processing the command list and receiving FISes
+    fn stop(&mut self) -> Result<(), &'static str> {
+        self.set_command(self.command() - (PortCommand::START | PortCommand::FIS_RECEIVE));
+
+        // the HBA must stop within 500ms
+        for _ in 0..1_000_000 {
+            if !self
+                .command()
+                .intersects(PortCommand::COMMAND_RUNNING | PortCommand::FIS_RUNNING)
+            {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+
+        Err("Port does not stop")
+    }
+
+    fn start(&mut self) -> Result<(), &'static str> {
+        self.set_command(
+            self.command() | PortCommand::SPIN_UP | PortCommand::POWER_ON | PortCommand::FIS_RECEIVE,
+        );
+
+        // the drive must be idle before the command list is processed
+        for _ in 0..1_000_000 {
+            if Self::read(self.regs, PORT_TFD) & (TFD_BUSY | TFD_DRQ) == 0 {
+                self.set_command(self.command() | PortCommand::START);
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+
+        Err("Drive is busy")
+    }
+
+    pub fn buffer(&self, len: usize) -> &[u8] {
+        let ptr = physical_to_virtual(self.buffer) as *const u8;
+        unsafe { core::slice::from_raw_parts(ptr, len.min(BUFFER_SIZE)) }
+    }
+
+    pub fn buffer_mut(&mut self, len: usize) -> &mut [u8] {
+        let ptr = physical_to_virtual(self.buffer) as *mut u8;
+        unsafe { core::slice::from_raw_parts_mut(ptr, len.min(BUFFER_SIZE)) }
+    }
+
+    /// Issue an ATA command in slot 0 transferring `len` bytes of the buffer
+    pub fn issue(&mut self, command: u8, lba: u64, count: u16, write: bool, len: usize) {
+        let len = len.min(BUFFER_SIZE);
+
+        // command FIS, the LBA is split in 3 + 3 bytes
+        let fis = physical_to_virtual(self.mem + CMD_TABLE_OFFSET) as *mut u8;
+        let lba = lba.to_le_bytes();
+        let bytes = [
+            FIS_TYPE_REG_H2D,
+            // bit 7: the FIS holds a command
+            0x80,
+            command,
+            0,
+            lba[0],
+            lba[1],
+            lba[2],
+            // bit 6: LBA mode
+            1 << 6,
+            lba[3],
+            lba[4],
+            lba[5],
+            0,
+            count as u8,
+            (count >> 8) as u8,
+            0,
+            0,
+        ];
+
+        unsafe {
+            for (i, &byte) in bytes.iter().enumerate() {
+                fis.add(i).write_volatile(byte);
+            }
+        }
+
+        // a single PRD entry, as the buffer is contiguous
+        let prd = physical_to_virtual(self.mem + PRDT_OFFSET) as *mut u32;
+        let prdt_length = if len > 0 { 1 } else { 0 };
+        unsafe {
+            prd.write_volatile(self.buffer as u32);
+            prd.add(1).write_volatile((self.buffer >> 32) as u32);
+            prd.add(2).write_volatile(0);
+            // byte count minus 1, bit 31 requests an interrupt
+            prd.add(3).write_volatile((len as u32).saturating_sub(1) | 1 << 31);
+        }
+
+        // command header: FIS length in dwords, write bit, PRDT length
+        let header = physical_to_virtual(self.mem + CMD_LIST_OFFSET) as *mut u32;
+        let flags = 5 | if write { 1 << 6 } else { 0 } | prdt_length << 16;
+        unsafe {
+            header.write_volatile(flags);
+            // bytes transferred, updated by the HBA
+            header.add(1).write_volatile(0);
+        }
+
+        Self::write(self.regs, PORT_IS, u32::MAX);
+        Self::write(self.regs, PORT_CI, 1);
+    }
+
+    /// Returns true if the command in slot 0 completed or failed
+    pub fn is_done(&self) -> bool {
+        Self::read(self.regs, PORT_CI) & 1 == 0
+            || Self::read(self.regs, PORT_IS) & PortInterrupt::TASK_FILE_ERROR.bits() != 0
+    }
+
+    /// Check the result of the completed command
+    pub fn finish(&mut self) -> Result<(), &'static str> {
+        let is = Self::read(self.regs, PORT_IS);
+        Self::write(self.regs, PORT_IS, is);
+        // the port bit of the HBA status, also write 1 to clear
+        Self::write(self.abar, super::HBA_IS, 1 << self.index);
+
+        let tfd = Self::read(self.regs, PORT_TFD);
+        if is & PortInterrupt::TASK_FILE_ERROR.bits() != 0 || tfd & TFD_ERROR != 0 {
+            warn!("AHCI command failed, error {:#x}", (tfd >> 8) as u8);
+
+            // restart the port to clear the error
+            self.stop()?;
+            Self::write(self.regs, PORT_SERR, u32::MAX);
+            self.start()?;
+
+            return Err("AHCI command failed");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! ATAPI Drive
+//!
+//! CD-ROM drives attached to the IDE controller, which are driven by SCSI
+//! commands sent with the ATA PACKET command.
+//!
+//! reference: https://wiki.osdev.org/ATAPI
+//! reference: https://www.t10.org/ftp/t10/document.05/05-344r0.pdf (SCSI Block Commands)
+
+use super::consts::ATAPI_SECTOR_SIZE;
+use super::{identify_strings, lock_bus, IdeDrive};
+use alloc::boxed::Box;
+use storage::{Block2048, BlockDevice};
+
+/// Max sectors transferred by a single READ(10) command
+const MAX_SECTORS: usize = u16::MAX as usize;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScsiCommand {
+    /// Returns the last logical block address and the block size
+    ReadCapacity = 0x25,
+    /// Reads blocks, with a 32-bit LBA and a 16-bit block count
+    Read10 = 0x28,
+}
+
+#[derive(Clone)]
+pub struct AtapiDrive {
+    pub bus: u8,
+    pub drive: u8,
+    blocks: u64,
+    model: Box<str>,
+    serial: Box<str>,
+}
+
+impl AtapiDrive {
+    pub fn open(bus: u8, drive: u8) -> Option<Self> {
+        trace!("Opening packet drive {}@{}...", bus, drive);
+
+        match super::probe(bus, drive) {
+            Some(IdeDrive::Atapi(atapi_drive)) => Some(atapi_drive),
+            _ => {
+                warn!("Drive {}@{} is not a PATAPI drive", bus, drive);
+                None
+            }
+        }
+    }
+
+    /// Set up the drive from its IDENTIFY PACKET data
+    ///
+    /// Returns `None` if there is no readable medium in the drive.
+    pub(super) fn new(bus: u8, drive: u8, res: &[u16; 256]) -> Option<Self> {
+        let (model, serial) = identify_strings(res);
+
+        let mut atapi_drive = Self {
+            bus,
+            drive,
+            blocks: 0,
+            model,
+            serial,
+        };
+
+        // the first command after the medium is inserted fails with
+        // UNIT ATTENTION, so retry a few times
+        let capacity = (0..3).find_map(|_| atapi_drive.read_capacity().ok());
+
+        match capacity {
+            Some(blocks) => atapi_drive.blocks = blocks,
+            None => {
+                warn!("Drive {}@{} has no readable medium", bus, drive);
+                return None;
+            }
+        }
+
+        info!("Drive {} opened", atapi_drive);
+        Some(atapi_drive)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Read the number of blocks of the medium
+    fn read_capacity(&self) -> Result<u64, &'static str> {
+        let mut packet = [0u8; 12];
+        packet[0] = ScsiCommand::ReadCapacity as u8;
+
+        let mut buf = [0u8; 8];
+        let len = lock_bus(self.bus)?.send_packet(self.drive, &packet, &mut buf)?;
+
+        if len != buf.len() {
+            return Err("Invalid capacity data");
+        }
+
+        // last logical block address and block size, both big endian
+        let last_block = u32::from_be_bytes(buf[0..4].try_into().unwrap());
+        let block_size = u32::from_be_bytes(buf[4..8].try_into().unwrap());
+
+        if block_size as usize != ATAPI_SECTOR_SIZE {
+            warn!("Unsupported ATAPI block size: {}", block_size);
+            return Err("Unsupported block size");
+        }
+
+        Ok(last_block as u64 + 1)
+    }
+
+    /// Read consecutive blocks from the drive, `buf` holds whole blocks
+    pub fn read_block_raw(&self, block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        if buf.len() % ATAPI_SECTOR_SIZE != 0 {
+            return Err("Buffer is not sector aligned");
+        }
+
+        if block + (buf.len() / ATAPI_SECTOR_SIZE) as u64 > self.blocks {
+            return Err("Block out of range");
+        }
+
+        let mut bus = lock_bus(self.bus)?;
+
+        for (i, chunk) in buf.chunks_mut(MAX_SECTORS * ATAPI_SECTOR_SIZE).enumerate() {
+            let lba = (block + (i * MAX_SECTORS) as u64) as u32;
+            let count = (chunk.len() / ATAPI_SECTOR_SIZE) as u16;
+
+            let mut packet = [0u8; 12];
+            packet[0] = ScsiCommand::Read10 as u8;
+            packet[2..6].copy_from_slice(&lba.to_be_bytes());
+            packet[7..9].copy_from_slice(&count.to_be_bytes());
+
+            if bus.send_packet(self.drive, &packet, chunk)? != chunk.len() {
+                return Err("Read error");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn humanized_size(&self) -> (f32, &'static str) {
+        crate::humanized_size(self.blocks * ATAPI_SECTOR_SIZE as u64)
+    }
+}
+
+impl core::fmt::Display for AtapiDrive {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let (size, unit) = self.humanized_size();
+        write!(f, "{} {} ({} {})", self.model, self.serial, size, unit)
+    }
+}
+
+impl BlockDevice<Block2048> for AtapiDrive {
+    fn block_count(&self) -> storage::FsResult<usize> {
+        Ok(self.blocks as usize)
+    }
+
+    fn read_block(&self, offset: usize, block: &mut Block2048) -> storage::FsResult {
+        self.read_block_raw(offset as u64, block.as_mut())
+            .map_err(|_| storage::DeviceError::ReadError.into())
+    }
+
+    fn write_block(&self, _offset: usize, _block: &Block2048) -> storage::FsResult {
+        // CD-ROM drives are read-only
+        Err(storage::DeviceError::WriteError.into())
+    }
+
+    fn read_blocks(&self, offset: usize, blocks: &mut [Block2048]) -> storage::FsResult {
+        let mut buf = alloc::vec![0u8; blocks.len() * ATAPI_SECTOR_SIZE];
+        self.read_block_raw(offset as u64, &mut buf)
+            .map_err(|_| storage::FsError::from(storage::DeviceError::ReadError))?;
+
+        for (block, data) in blocks.iter_mut().zip(buf.chunks(ATAPI_SECTOR_SIZE)) {
+            block.as_mut().copy_from_slice(data);
+        }
+
+        Ok(())
+    }
+}
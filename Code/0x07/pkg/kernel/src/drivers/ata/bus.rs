@@ -5,10 +5,11 @@
 //! reference: https://github.com/theseus-os/Theseus/blob/HEAD/kernel/ata/src/lib.rs
 
 use super::consts::*;
+use super::dma::BusMaster;
 use alloc::boxed::Box;
 use x86_64::instructions::port::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 #[allow(dead_code)]
 pub struct AtaBus {
     id: u8,
@@ -31,13 +32,14 @@ pub struct AtaBus {
     alternate_status: PortReadOnly<u8>,
     control: PortWriteOnly<u8>,
     drive_blockess: PortReadOnly<u8>,
+    dma: Option<BusMaster>,
 }
 
 impl AtaBus {
     pub fn new(id: u8, irq: u8, io_base: u16, ctrl_base: u16) -> Self {
         Self {
             id,
-            irq, // actually not used as we poll the status
+            irq,
             io_base,
             ctrl_base,
             data: Port::<u16>::new(io_base),
@@ -54,6 +56,7 @@ impl AtaBus {
             alternate_status: PortReadOnly::new(ctrl_base),
             control: PortWriteOnly::new(ctrl_base),
             drive_blockess: PortReadOnly::new(ctrl_base + 1),
+            dma: None,
         }
     }
 
@@ -122,6 +125,50 @@ impl AtaBus {
         }
     }
 
+    /// Clears nIEN in the device control register,
+    /// so the drives raise the IDE interrupt on completion.
+    pub(super) fn enable_interrupt(&mut self) {
+        unsafe { self.control.write(0) }
+    }
+
+    /// Sets up bus-master DMA, if the IDE controller supports it.
+    pub(super) fn enable_dma(&mut self) {
+        self.dma = BusMaster::new(self.id);
+    }
+
+    pub(super) fn has_dma(&self) -> bool {
+        self.dma.is_some()
+    }
+
+    /// Waits for the IDE interrupt, the current process sleeps until it
+    /// arrives. Falls back to polling if the process cannot sleep here.
+    fn wait_irq(&mut self) {
+        if !super::wait_irq(self.id) {
+            self.poll(AtaStatus::BUSY, false);
+        }
+
+        // consumed, the next data block raises another one
+        super::clear_irq(self.id);
+    }
+
+    /// Waits for the drive to be ready for data transfer
+    fn wait_data(&mut self, cmd: AtaCommand) -> Result<(), &'static str> {
+        // Poll for the status to be not BUSY
+        self.poll(AtaStatus::BUSY, false);
+
+        if self.is_error() {
+            warn!("ATA error: {:?} command error", cmd);
+            self.debug();
+            return Err("Invalid operation");
+        }
+
+        // Poll for the status to be not BUSY and DATA_REQUEST_READY
+        self.poll(AtaStatus::BUSY, false);
+        self.poll(AtaStatus::DATA_REQUEST_READY, true);
+
+        Ok(())
+    }
+
     /// Log debug information about the bus
     fn debug(&mut self) {
         warn!("ATA error register  : {:?}", self.error());
@@ -130,29 +177,60 @@ impl AtaBus {
 
     /// Writes the given command
     ///
+    /// `count` is the number of sectors in 1..=256 for LBA28 commands and
+    /// 1..=65536 for LBA48 ones, the max value is encoded as 0.
+    ///
     /// reference: https://wiki.osdev.org/ATA_PIO_Mode#28_bit_PIO
-    fn write_command(&mut self, drive: u8, block: u32, cmd: AtaCommand) -> Result<(), &'static str> {
-        let bytes = block.to_le_bytes(); // a trick to convert u32 to [u8; 4]
+    /// reference: https://wiki.osdev.org/ATA_PIO_Mode#48_bit_PIO
+    fn write_command(
+        &mut self,
+        drive: u8,
+        block: u64,
+        count: u32,
+        cmd: AtaCommand,
+    ) -> Result<(), &'static str> {
+        let bytes = block.to_le_bytes(); // a trick to convert u64 to [u8; 8]
+
+        // the interrupt of the previous command is no longer interesting
+        super::clear_irq(self.id);
+
         unsafe {
-            // just 1 sector for current implementation
-            self.sector_count.write(1);
-
-            // Store the LBA28 address into four 8-bit registers
-            // LBA bits 0-7 go to lba_low register
-            self.lba_low.write(bytes[0]);
-            // LBA bits 8-15 go to lba_mid register
-            self.lba_mid.write(bytes[1]);
-            // LBA bits 16-23 go to lba_high register
-            self.lba_high.write(bytes[2]);
-
-            // Drive register: bits 24-27 of LBA + drive selection + LBA mode
-            // Bit 7: always 1
-            // Bit 6: LBA mode (1 for LBA, 0 for CHS)
-            // Bit 5: always 1
-            // Bit 4: drive number (0 for master, 1 for slave)
-            // Bits 0-3: LBA bits 24-27
-            let drive_reg = 0xE0 | ((drive & 1) << 4) | (bytes[3] & 0x0F);
-            self.drive.write(drive_reg);
+            if cmd.is_lba48() {
+                let count = (count as u16).to_le_bytes();
+
+                // Drive register: LBA mode + drive selection, no LBA bits
+                self.drive.write(0x40 | ((drive & 1) << 4));
+
+                // The registers are FIFOs of two bytes, the high bytes go first
+                self.sector_count.write(count[1]);
+                self.lba_low.write(bytes[3]);
+                self.lba_mid.write(bytes[4]);
+                self.lba_high.write(bytes[5]);
+
+                self.sector_count.write(count[0]);
+                self.lba_low.write(bytes[0]);
+                self.lba_mid.write(bytes[1]);
+                self.lba_high.write(bytes[2]);
+            } else {
+                self.sector_count.write(count as u8);
+
+                // Store the LBA28 address into four 8-bit registers
+                // LBA bits 0-7 go to lba_low register
+                self.lba_low.write(bytes[0]);
+                // LBA bits 8-15 go to lba_mid register
+                self.lba_mid.write(bytes[1]);
+                // LBA bits 16-23 go to lba_high register
+                self.lba_high.write(bytes[2]);
+
+                // Drive register: bits 24-27 of LBA + drive selection + LBA mode
+                // Bit 7: always 1
+                // Bit 6: LBA mode (1 for LBA, 0 for CHS)
+                // Bit 5: always 1
+                // Bit 4: drive number (0 for master, 1 for slave)
+                // Bits 0-3: LBA bits 24-27
+                let drive_reg = 0xE0 | ((drive & 1) << 4) | (bytes[3] & 0x0F);
+                self.drive.write(drive_reg);
+            }
 
             // Write the command register
             self.command.write(cmd as u8);
@@ -163,19 +241,6 @@ impl AtaBus {
             return Err("Unknown device");
         }
 
-        // Poll for the status to be not BUSY
-        self.poll(AtaStatus::BUSY, false);
-
-        if self.is_error() {
-            warn!("ATA error: {:?} command error", cmd);
-            self.debug();
-            return Err("Invalid operation");
-        }
-
-        // Poll for the status to be not BUSY and DATA_REQUEST_READY
-        self.poll(AtaStatus::BUSY, false);
-        self.poll(AtaStatus::DATA_REQUEST_READY, true);
-
         Ok(())
     }
 
@@ -187,7 +252,17 @@ impl AtaBus {
 
         // Use AtaCommand::IdentifyDevice to identify the drive
         // Call write_command with drive and 0 as the block number
-        if let Err(_e) = self.write_command(drive, 0, AtaCommand::IdentifyDevice) {
+        let ret = self.write_command(drive, 0, 1, AtaCommand::IdentifyDevice).and_then(|_| {
+            self.wait_irq();
+            self.wait_data(AtaCommand::IdentifyDevice)
+        });
+
+        if let Err(_e) = ret {
+            // Packet devices abort IDENTIFY DEVICE and leave their signature
+            if (self.cylinder_low(), self.cylinder_high()) == (0x14, 0xEB) {
+                return self.identify_packet_drive(drive);
+            }
+
             // If the status is empty, return AtaDeviceType::None
             if self.status().is_empty() {
                 return Ok(AtaDeviceType::None);
@@ -201,41 +276,180 @@ impl AtaBus {
         self.poll(AtaStatus::BUSY, false);
 
         Ok(match (self.cylinder_low(), self.cylinder_high()) {
-            // we only support PATA drives
             (0x00, 0x00) => AtaDeviceType::Pata(Box::new([0u16; 256].map(|_| self.read_data()))),
             // ignore the data as we don't support following types
-            (0x14, 0xEB) => AtaDeviceType::PataPi,
+            (0x14, 0xEB) => return self.identify_packet_drive(drive),
             (0x3C, 0xC3) => AtaDeviceType::Sata,
             (0x69, 0x96) => AtaDeviceType::SataPi,
             _ => AtaDeviceType::None,
         })
     }
 
-    /// Reads a block from the given drive and block number into the given buffer.
+    /// Identifies the ATAPI drive at the given `drive` number (0 or 1).
+    ///
+    /// reference: https://wiki.osdev.org/ATAPI#Detecting_an_ATAPI_device
+    fn identify_packet_drive(&mut self, drive: u8) -> Result<AtaDeviceType, &'static str> {
+        info!("Identifying packet drive {}", drive);
+
+        self.write_command(drive, 0, 1, AtaCommand::IdentifyPacket)?;
+        self.wait_irq();
+        self.wait_data(AtaCommand::IdentifyPacket)?;
+
+        Ok(AtaDeviceType::PataPi(Box::new(
+            [0u16; 256].map(|_| self.read_data()),
+        )))
+    }
+
+    /// Sends a SCSI command packet to the given ATAPI drive using PIO,
+    /// and reads the returned data into the given buffer.
+    ///
+    /// The drive may split the data into several blocks, each of them
+    /// raises an interrupt and tells its size in the byte count registers.
+    /// Data beyond the end of the buffer is discarded.
+    ///
+    /// Returns the number of bytes stored into the buffer.
+    ///
+    /// reference: https://wiki.osdev.org/ATAPI#The_PACKET_Command
+    pub(super) fn send_packet(
+        &mut self,
+        drive: u8,
+        packet: &[u8; 12],
+        buf: &mut [u8],
+    ) -> Result<usize, &'static str> {
+        // the max bytes per data block, must be even and not 0xFFFF
+        let limit = buf.len().clamp(2, 0xF800) as u16 & !1;
+
+        super::clear_irq(self.id);
+
+        unsafe {
+            self.drive.write(0xA0 | ((drive & 1) << 4));
+            self.status();
+
+            // PIO mode, no overlapped command
+            self.features.write(0);
+            self.lba_mid.write(limit as u8);
+            self.lba_high.write((limit >> 8) as u8);
+            self.command.write(AtaCommand::Packet as u8);
+        }
+
+        // the packet is sent without waiting for an interrupt
+        self.wait_data(AtaCommand::Packet)?;
+
+        for chunk in packet.chunks(2) {
+            self.write_data(u16::from_le_bytes([chunk[0], chunk[1]]));
+        }
+
+        let mut read = 0;
+
+        loop {
+            self.wait_irq();
+            self.poll(AtaStatus::BUSY, false);
+
+            let status = self.status();
+            if status.contains(AtaStatus::ERROR) {
+                debug!("ATAPI error: packet {:#04x} failed", packet[0]);
+                self.debug();
+                return Err("Packet command error");
+            }
+
+            // the command is completed once the drive stops requesting data
+            if !status.contains(AtaStatus::DATA_REQUEST_READY) {
+                break;
+            }
+
+            let count = u16::from_le_bytes([self.cylinder_low(), self.cylinder_high()]) as usize;
+
+            for _ in 0..count.div_ceil(2) {
+                let bytes = self.read_data().to_le_bytes();
+                for byte in bytes {
+                    if read < buf.len() {
+                        buf[read] = byte;
+                        read += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(read)
+    }
+
+    /// Sets the number of sectors per interrupt for READ/WRITE MULTIPLE.
+    pub(super) fn set_multiple_mode(&mut self, drive: u8, sectors: u16) -> Result<(), &'static str> {
+        self.write_command(drive, 0, sectors as u32, AtaCommand::SetMultipleMode)?;
+
+        self.wait_irq();
+        self.poll(AtaStatus::BUSY, false);
+
+        if self.is_error() {
+            warn!("ATA error: multiple mode of {} sectors rejected", sectors);
+            self.debug();
+            Err("Invalid operation")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes the volatile write cache of the drive to the medium.
+    ///
+    /// The drive may take a while, so the interrupt is waited for.
+    ///
+    /// reference: https://wiki.osdev.org/ATA_PIO_Mode#Cache_Flush
+    pub(super) fn flush_cache(&mut self, drive: u8, lba48: bool) -> Result<(), &'static str> {
+        let cmd = if lba48 {
+            AtaCommand::CacheFlushExt
+        } else {
+            AtaCommand::CacheFlush
+        };
+        self.write_command(drive, 0, 0, cmd)?;
+
+        self.wait_irq();
+        self.poll(AtaStatus::BUSY, false);
+
+        if self.is_error() {
+            warn!("ATA error: cache flush failed");
+            self.debug();
+            Err("Flush error")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reads sectors from the given drive and block number into the given buffer.
+    ///
+    /// The buffer holds `buf.len() / SECTOR_SIZE` sectors,
+    /// which must fit in a single command.
     ///
     /// reference: https://wiki.osdev.org/ATA_PIO_Mode#28_bit_PIO
     /// reference: https://wiki.osdev.org/IDE#Read.2FWrite_From_ATA_Drive
     pub(super) fn read_pio(
         &mut self,
         drive: u8,
-        block: u32,
+        mode: AtaTransfer,
+        block: u64,
         buf: &mut [u8],
     ) -> Result<(), &'static str> {
-        self.write_command(drive, block, AtaCommand::ReadPio)?;
-
-        // Read the data from the data port into the buffer
-        // Use buf.chunks_mut(2) to process 2 bytes at a time (16-bit data port)
-        // Pay attention to data endianness
-        for chunk in buf.chunks_mut(2) {
-            let data = self.read_data(); // Read 16-bit word from data port
-            let bytes = data.to_le_bytes(); // Convert to little-endian bytes
-
-            // Copy bytes to buffer
-            if chunk.len() >= 2 {
-                chunk[0] = bytes[0];
-                chunk[1] = bytes[1];
-            } else if chunk.len() == 1 {
-                chunk[0] = bytes[0];
+        let cmd = mode.read_command();
+        self.write_command(drive, block, (buf.len() / SECTOR_SIZE) as u32, cmd)?;
+
+        for data_block in buf.chunks_mut(mode.drq_block_size()) {
+            // the drive raises the interrupt once the data block is ready
+            self.wait_irq();
+            self.wait_data(cmd)?;
+
+            // Read the data from the data port into the buffer
+            // Use chunks_mut(2) to process 2 bytes at a time (16-bit data port)
+            // Pay attention to data endianness
+            for chunk in data_block.chunks_mut(2) {
+                let data = self.read_data(); // Read 16-bit word from data port
+                let bytes = data.to_le_bytes(); // Convert to little-endian bytes
+
+                // Copy bytes to buffer
+                if chunk.len() >= 2 {
+                    chunk[0] = bytes[0];
+                    chunk[1] = bytes[1];
+                } else if chunk.len() == 1 {
+                    chunk[0] = bytes[0];
+                }
             }
         }
 
@@ -248,28 +462,47 @@ impl AtaBus {
         }
     }
 
-    /// Writes a block to the given drive and block number from the given buffer.
+    /// Writes sectors to the given drive and block number from the given buffer.
+    ///
+    /// The buffer holds `buf.len() / SECTOR_SIZE` sectors,
+    /// which must fit in a single command.
     ///
     /// reference: https://wiki.osdev.org/ATA_PIO_Mode#28_bit_PIO
     /// reference: https://wiki.osdev.org/IDE#Read.2FWrite_From_ATA_Drive
-    pub(super) fn write_pio(&mut self, drive: u8, block: u32, buf: &[u8]) -> Result<(), &'static str> {
-        self.write_command(drive, block, AtaCommand::WritePio)?;
-
-        // Write the data from the buffer into the data port
-        // Use buf.chunks(2) to process 2 bytes at a time (16-bit data port)
-        // Pay attention to data endianness
-        for chunk in buf.chunks(2) {
-            let data = if chunk.len() >= 2 {
-                // Convert two bytes to 16-bit word in little-endian format
-                u16::from_le_bytes([chunk[0], chunk[1]])
-            } else {
-                // If only one byte, pad with zero
-                u16::from_le_bytes([chunk[0], 0])
-            };
+    pub(super) fn write_pio(
+        &mut self,
+        drive: u8,
+        mode: AtaTransfer,
+        block: u64,
+        buf: &[u8],
+    ) -> Result<(), &'static str> {
+        let cmd = mode.write_command();
+        self.write_command(drive, block, (buf.len() / SECTOR_SIZE) as u32, cmd)?;
+
+        for data_block in buf.chunks(mode.drq_block_size()) {
+            self.wait_data(cmd)?;
+
+            // Write the data from the buffer into the data port
+            // Use chunks(2) to process 2 bytes at a time (16-bit data port)
+            // Pay attention to data endianness
+            for chunk in data_block.chunks(2) {
+                let data = if chunk.len() >= 2 {
+                    // Convert two bytes to 16-bit word in little-endian format
+                    u16::from_le_bytes([chunk[0], chunk[1]])
+                } else {
+                    // If only one byte, pad with zero
+                    u16::from_le_bytes([chunk[0], 0])
+                };
+
+                self.write_data(data); // Write 16-bit word to data port
+            }
 
-            self.write_data(data); // Write 16-bit word to data port
+            // the drive raises the interrupt once the data block is written
+            self.wait_irq();
         }
 
+        self.poll(AtaStatus::BUSY, false);
+
         if self.is_error() {
             debug!("ATA error: data write error");
             self.debug();
@@ -278,4 +511,82 @@ impl AtaBus {
             Ok(())
         }
     }
+
+    /// Reads sectors from the given drive using bus-master DMA.
+    ///
+    /// The buffer holds `buf.len() / SECTOR_SIZE` sectors, at most
+    /// `dma::MAX_SECTORS` of them.
+    ///
+    /// reference: https://wiki.osdev.org/ATA/ATAPI_using_DMA
+    pub(super) fn read_dma(
+        &mut self,
+        drive: u8,
+        mode: AtaTransfer,
+        block: u64,
+        buf: &mut [u8],
+    ) -> Result<(), &'static str> {
+        let mut dma = self.dma.take().ok_or("DMA not available")?;
+
+        let ret = self.transfer_dma(&mut dma, drive, mode.dma_read_command(), block, buf.len());
+        if ret.is_ok() {
+            buf.copy_from_slice(dma.buffer(buf.len()));
+        }
+
+        self.dma = Some(dma);
+        ret
+    }
+
+    /// Writes sectors to the given drive using bus-master DMA.
+    ///
+    /// The buffer holds `buf.len() / SECTOR_SIZE` sectors, at most
+    /// `dma::MAX_SECTORS` of them.
+    ///
+    /// reference: https://wiki.osdev.org/ATA/ATAPI_using_DMA
+    pub(super) fn write_dma(
+        &mut self,
+        drive: u8,
+        mode: AtaTransfer,
+        block: u64,
+        buf: &[u8],
+    ) -> Result<(), &'static str> {
+        let mut dma = self.dma.take().ok_or("DMA not available")?;
+
+        dma.buffer_mut(buf.len()).copy_from_slice(buf);
+        let ret = self.transfer_dma(&mut dma, drive, mode.dma_write_command(), block, buf.len());
+
+        self.dma = Some(dma);
+        ret
+    }
+
+    fn transfer_dma(
+        &mut self,
+        dma: &mut BusMaster,
+        drive: u8,
+        cmd: AtaCommand,
+        block: u64,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let read = matches!(cmd, AtaCommand::ReadDma | AtaCommand::ReadDmaExt);
+
+        dma.prepare(read, len);
+        self.write_command(drive, block, (len / SECTOR_SIZE) as u32, cmd)?;
+        dma.start();
+
+        // the drive raises the interrupt once the whole transfer is done
+        self.wait_irq();
+        while dma.is_active() {
+            core::hint::spin_loop();
+        }
+
+        let ok = dma.finish();
+        self.poll(AtaStatus::BUSY, false);
+
+        if !ok || self.is_error() {
+            debug!("ATA error: {:?} DMA transfer error", cmd);
+            self.debug();
+            Err("DMA transfer error")
+        } else {
+            Ok(())
+        }
+    }
 }
@@ -4,6 +4,12 @@
 
 use alloc::boxed::Box;
 
+/// Size of a sector in bytes
+pub(super) const SECTOR_SIZE: usize = 512;
+
+/// Size of a sector of ATAPI (CD-ROM) drives in bytes
+pub(super) const ATAPI_SECTOR_SIZE: usize = 2048;
+
 bitflags! {
     /// The possible error values found in an ATA drive's error port.
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -50,10 +56,18 @@ pub(super) enum AtaCommand {
     ReadDma = 0xC8,
     /// Read sectors using DMA (48-bit LBA)
     ReadDmaExt = 0x25,
+    /// Read multiple sectors per interrupt using PIO (28-bit LBA)
+    ReadMultiple = 0xC4,
+    /// Read multiple sectors per interrupt using PIO (48-bit LBA)
+    ReadMultipleExt = 0x29,
     /// Write sectors using PIO (28-bit LBA)
     WritePio = 0x30,
     /// Write sectors using PIO (48-bit LBA)
     WritePioExt = 0x34,
+    /// Write multiple sectors per interrupt using PIO (28-bit LBA)
+    WriteMultiple = 0xC5,
+    /// Write multiple sectors per interrupt using PIO (48-bit LBA)
+    WriteMultipleExt = 0x39,
     /// Write sectors using DMA (28-bit LBA)
     WriteDma = 0xCA,
     /// Write sectors using DMA (48-bit LBA)
@@ -70,6 +84,79 @@ pub(super) enum AtaCommand {
     IdentifyPacket = 0xA1,
     /// Get identifying details of an ATA drive.
     IdentifyDevice = 0xEC,
+    /// Set the number of sectors per interrupt for READ/WRITE MULTIPLE.
+    SetMultipleMode = 0xC6,
+}
+
+impl AtaCommand {
+    /// Returns true if the command takes a 48-bit LBA and a 16-bit sector count.
+    pub(super) fn is_lba48(&self) -> bool {
+        matches!(
+            self,
+            Self::ReadPioExt
+                | Self::ReadMultipleExt
+                | Self::ReadDmaExt
+                | Self::WritePioExt
+                | Self::WriteMultipleExt
+                | Self::WriteDmaExt
+                | Self::CacheFlushExt
+        )
+    }
+}
+
+/// How the sectors of a drive are addressed and transferred using PIO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct AtaTransfer {
+    /// The drive supports 48-bit LBA (IDENTIFY word 83, bit 10).
+    pub lba48: bool,
+    /// Sectors transferred per interrupt by READ/WRITE MULTIPLE,
+    /// 1 to use READ/WRITE SECTORS instead.
+    pub multiple: u16,
+    /// Transfer with bus-master DMA instead of PIO
+    /// (IDENTIFY word 49, bit 8, and a bus master on the channel).
+    pub dma: bool,
+}
+
+impl AtaTransfer {
+    pub fn read_command(&self) -> AtaCommand {
+        match (self.lba48, self.multiple > 1) {
+            (false, false) => AtaCommand::ReadPio,
+            (false, true) => AtaCommand::ReadMultiple,
+            (true, false) => AtaCommand::ReadPioExt,
+            (true, true) => AtaCommand::ReadMultipleExt,
+        }
+    }
+
+    pub fn write_command(&self) -> AtaCommand {
+        match (self.lba48, self.multiple > 1) {
+            (false, false) => AtaCommand::WritePio,
+            (false, true) => AtaCommand::WriteMultiple,
+            (true, false) => AtaCommand::WritePioExt,
+            (true, true) => AtaCommand::WriteMultipleExt,
+        }
+    }
+
+    pub fn dma_read_command(&self) -> AtaCommand {
+        if self.lba48 { AtaCommand::ReadDmaExt } else { AtaCommand::ReadDma }
+    }
+
+    pub fn dma_write_command(&self) -> AtaCommand {
+        if self.lba48 { AtaCommand::WriteDmaExt } else { AtaCommand::WriteDma }
+    }
+
+    /// Max sectors transferred by a single command.
+    pub fn max_sectors(&self) -> usize {
+        match (self.dma, self.lba48) {
+            (true, _) => super::dma::MAX_SECTORS,
+            (false, true) => 65536,
+            (false, false) => 256,
+        }
+    }
+
+    /// Bytes transferred per interrupt, i.e. the size of a DRQ data block.
+    pub fn drq_block_size(&self) -> usize {
+        self.multiple.max(1) as usize * SECTOR_SIZE
+    }
 }
 
 /// The possible types of drive devices that can be attached to an IDE controller via ATA.
@@ -81,7 +168,7 @@ pub(super) enum AtaDeviceType {
     Pata(Box<[u16; 256]>),
     /// A parallel ATA (PATA) drive that uses the packet interface,
     /// like an optical CD-ROM drive.
-    PataPi,
+    PataPi(Box<[u16; 256]>),
     /// A serial ATA (SATA) drive that is operating in legacy IDE emulation mode,
     /// **not the standard AHCI interface for SATA**.
     /// Some systems refer to this as a `SEMB` (SATA Enclosure Management Bridge) device,
@@ -0,0 +1,191 @@
+//! PCI IDE bus-master DMA
+//!
+//! reference: https://wiki.osdev.org/ATA/ATAPI_using_DMA
+//! reference: Intel 82371AB PCI-TO-ISA / IDE XCELERATOR (PIIX4), section 2.7
+
+use super::consts::SECTOR_SIZE;
+use crate::drivers::pci;
+use crate::memory::{get_frame_alloc_for_sure, physical_to_virtual, FRAME_SIZE};
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::FrameAllocator;
+
+/// Frames of the bounce buffer of each bus
+///
+/// The buffer is physically contiguous, so it can be accessed as a single
+/// slice, and is described by one PRD entry per frame, as an entry must not
+/// cross a 64 KiB boundary.
+const BUFFER_FRAMES: usize = 16;
+
+/// Size of the bounce buffer in bytes
+pub(super) const BUFFER_SIZE: usize = BUFFER_FRAMES * FRAME_SIZE as usize;
+
+/// Max sectors transferred by a single DMA command
+pub(super) const MAX_SECTORS: usize = BUFFER_SIZE / SECTOR_SIZE;
+
+bitflags! {
+    /// Bus master IDE command register
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct BmCommand: u8 {
+        const START = 0x01;
+        /// Set for device to memory transfers, i.e. reads
+        const READ  = 0x08;
+    }
+}
+
+bitflags! {
+    /// Bus master IDE status register
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct BmStatus: u8 {
+        const ACTIVE    = 0x01;
+        /// Write 1 to clear
+        const ERROR     = 0x02;
+        /// Write 1 to clear
+        const INTERRUPT = 0x04;
+        const DRIVE0_DMA_CAPABLE = 0x20;
+        const DRIVE1_DMA_CAPABLE = 0x40;
+    }
+}
+
+/// Physical Region Descriptor
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+struct PrdEntry {
+    addr: u32,
+    /// 0 means 64 KiB
+    count: u16,
+    /// Bit 15: end of table
+    flags: u16,
+}
+
+/// Bus master of one IDE channel, with its PRD table and bounce buffer
+#[derive(Debug)]
+pub(super) struct BusMaster {
+    command: Port<u8>,
+    status: Port<u8>,
+    prdt_addr: Port<u32>,
+    /// Physical address of the PRD table
+    prdt: u64,
+    /// Physical address of the bounce buffer
+    buffer: u64,
+}
+
+impl BusMaster {
+    /// Sets up the bus master of the given channel of the IDE controller
+    ///
+    /// Returns `None` if there is no bus-master capable IDE controller or
+    /// no contiguous memory is left for the buffer.
+    pub fn new(channel: u8) -> Option<Self> {
+        // mass storage controller, IDE interface
+        let addr = pci::find_class(0x01, 0x01)?;
+        let (_, _, prog_if) = addr.class();
+
+        // prog_if bit 7: bus mastering is supported
+        if prog_if & 0x80 == 0 {
+            return None;
+        }
+
+        // BAR4 is the bus master I/O base, the secondary channel starts at +8
+        let bar4 = addr.bar(4);
+        if bar4 & 0x1 == 0 {
+            return None;
+        }
+
+        let base = (bar4 & 0xFFFC) as u16 + channel as u16 * 8;
+        addr.enable_bus_master();
+
+        // both channels share the function
+        pci::claim(addr, "ata");
+
+        let (prdt, buffer) = {
+            let mut alloc = get_frame_alloc_for_sure();
+            let prdt = alloc.allocate_frame()?.start_address().as_u64();
+            let buffer = alloc.allocate_contiguous(BUFFER_FRAMES)?.start_address().as_u64();
+            (prdt, buffer)
+        };
+
+        // the PRD table and the buffer are addressed with 32 bits
+        if prdt + FRAME_SIZE > u32::MAX as u64 || buffer + BUFFER_SIZE as u64 > u32::MAX as u64 {
+            warn!("ATA DMA memory is above 4 GiB");
+            return None;
+        }
+
+        info!(
+            "ATA channel {} bus master at {:#x}, buffer {:#x}",
+            channel, base, buffer
+        );
+
+        Some(Self {
+            command: Port::new(base),
+            status: Port::new(base + 2),
+            prdt_addr: Port::new(base + 4),
+            prdt,
+            buffer,
+        })
+    }
+
+    pub fn buffer(&self, len: usize) -> &[u8] {
+        let ptr = physical_to_virtual(self.buffer) as *const u8;
+        unsafe { core::slice::from_raw_parts(ptr, len.min(BUFFER_SIZE)) }
+    }
+
+    pub fn buffer_mut(&mut self, len: usize) -> &mut [u8] {
+        let ptr = physical_to_virtual(self.buffer) as *mut u8;
+        unsafe { core::slice::from_raw_parts_mut(ptr, len.min(BUFFER_SIZE)) }
+    }
+
+    /// Prepares a transfer of `len` bytes, before the command is sent to the drive
+    pub fn prepare(&mut self, read: bool, len: usize) {
+        let len = len.min(BUFFER_SIZE);
+        let frames = len.div_ceil(FRAME_SIZE as usize);
+        let table = physical_to_virtual(self.prdt) as *mut PrdEntry;
+
+        for i in 0..frames {
+            let offset = i * FRAME_SIZE as usize;
+            let entry = PrdEntry {
+                addr: (self.buffer + offset as u64) as u32,
+                count: (len - offset).min(FRAME_SIZE as usize) as u16,
+                flags: if i == frames - 1 { 1 << 15 } else { 0 },
+            };
+
+            unsafe { table.add(i).write_volatile(entry) };
+        }
+
+        unsafe {
+            self.command.write(0);
+            self.prdt_addr.write(self.prdt as u32);
+
+            let command = if read { BmCommand::READ } else { BmCommand::empty() };
+            self.command.write(command.bits());
+
+            // clear the error and interrupt bits
+            self.status.write((BmStatus::ERROR | BmStatus::INTERRUPT).bits());
+        }
+    }
+
+    /// Starts the prepared transfer, after the command is sent to the drive
+    pub fn start(&mut self) {
+        unsafe {
+            let command = self.command.read();
+            self.command.write(command | BmCommand::START.bits());
+        }
+    }
+
+    /// Returns true if the bus master is still transferring
+    pub fn is_active(&mut self) -> bool {
+        let status = BmStatus::from_bits_truncate(unsafe { self.status.read() });
+        status.contains(BmStatus::ACTIVE) && !status.contains(BmStatus::INTERRUPT)
+    }
+
+    /// Stops the transfer after the interrupt, returns false on error
+    pub fn finish(&mut self) -> bool {
+        unsafe {
+            let command = self.command.read();
+            self.command.write(command & !BmCommand::START.bits());
+
+            let status = BmStatus::from_bits_truncate(self.status.read());
+            self.status.write((BmStatus::ERROR | BmStatus::INTERRUPT).bits());
+
+            !status.contains(BmStatus::ERROR)
+        }
+    }
+}
@@ -4,20 +4,36 @@
 //! reference: https://wiki.osdev.org/ATA_PIO_Mode
 //! reference: https://github.com/theseus-os/Theseus/blob/HEAD/kernel/ata/src/lib.rs
 
+mod atapi;
 mod bus;
 mod consts;
+mod dma;
 
+pub use atapi::AtapiDrive;
+
+use crate::proc::{self, ProcessId};
 use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 use bus::AtaBus;
-use consts::AtaDeviceType;
-use spin::Mutex;
+use consts::{AtaDeviceType, AtaStatus, AtaTransfer, SECTOR_SIZE};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::port::PortReadOnly;
+
+/// (irq, io_base, ctrl_base) of the primary and secondary bus
+const BUS_PORTS: [(u8, u16, u16); 2] = [(14, 0x1F0, 0x3F6), (15, 0x170, 0x376)];
 
 lazy_static! {
     pub static ref BUSES: [Mutex<AtaBus>; 2] = {
-        let buses = [
-            Mutex::new(AtaBus::new(0, 14, 0x1F0, 0x3F6)),
-            Mutex::new(AtaBus::new(1, 15, 0x170, 0x376)),
-        ];
+        let buses = core::array::from_fn(|id| {
+            let (irq, io_base, ctrl_base) = BUS_PORTS[id];
+            let mut bus = AtaBus::new(id as u8, irq, io_base, ctrl_base);
+            bus.enable_interrupt();
+            bus.enable_dma();
+            Mutex::new(bus)
+        });
 
         info!("Initialized ATA Buses.");
 
@@ -25,11 +41,178 @@ lazy_static! {
     };
 }
 
+/// Processes waiting for the IDE interrupt or for the bus to be released
+static WAIT_QUEUES: [Mutex<VecDeque<ProcessId>>; 2] =
+    [const { Mutex::new(VecDeque::new()) }; 2];
+
+/// Set by the IDE interrupt handler, cleared before sending a command
+static IRQ_RECEIVED: [AtomicBool; 2] = [const { AtomicBool::new(false) }; 2];
+
+/// Handle the IDE interrupt of the given bus
+pub fn handle_irq(bus: u8) {
+    let (_, io_base, _) = BUS_PORTS[bus as usize];
+
+    // reading the status register acknowledges the interrupt,
+    // the bus itself may be locked by the sleeping process
+    unsafe { PortReadOnly::<u8>::new(io_base + 7).read() };
+
+    IRQ_RECEIVED[bus as usize].store(true, Ordering::Release);
+    wake_up_all(bus);
+}
+
+fn clear_irq(bus: u8) {
+    IRQ_RECEIVED[bus as usize].store(false, Ordering::Release);
+}
+
+fn wake_up_all(bus: u8) {
+    let pids = core::mem::take(&mut *WAIT_QUEUES[bus as usize].lock());
+    let manager = proc::get_process_manager();
+    for pid in pids {
+        manager.wake_up(pid, None);
+    }
+}
+
+/// Wait for the IDE interrupt of the given bus
+///
+/// Returns `false` if the current process cannot sleep, so the caller
+/// should poll the status register instead.
+fn wait_irq(bus: u8) -> bool {
+    let (_, _, ctrl_base) = BUS_PORTS[bus as usize];
+
+    proc::sleep_on(&WAIT_QUEUES[bus as usize], || {
+        if IRQ_RECEIVED[bus as usize].load(Ordering::Acquire) {
+            return true;
+        }
+
+        // in case the interrupt got lost, e.g. while the kernel is halting
+        let status = unsafe { PortReadOnly::<u8>::new(ctrl_base).read() };
+        !AtaStatus::from_bits_truncate(status).contains(AtaStatus::BUSY)
+    })
+}
+
+/// Guard of a locked bus, wakes up the processes waiting for it on drop
+struct BusGuard {
+    bus: u8,
+    inner: MutexGuard<'static, AtaBus>,
+}
+
+impl Deref for BusGuard {
+    type Target = AtaBus;
+
+    fn deref(&self) -> &AtaBus {
+        &self.inner
+    }
+}
+
+impl DerefMut for BusGuard {
+    fn deref_mut(&mut self) -> &mut AtaBus {
+        &mut self.inner
+    }
+}
+
+impl Drop for BusGuard {
+    fn drop(&mut self) {
+        wake_up_all(self.bus);
+    }
+}
+
+/// Lock the given bus, sleeping while another process is using it
+fn lock_bus(bus: u8) -> Result<BusGuard, &'static str> {
+    let mutex = &BUSES[bus as usize];
+
+    loop {
+        if let Some(inner) = mutex.try_lock() {
+            return Ok(BusGuard { bus, inner });
+        }
+
+        // the holder is sleeping, spinning here with interrupts
+        // disabled would never give it a chance to finish, unless it runs
+        // on another CPU when the caller cannot sleep
+        if !proc::sleep_on(&WAIT_QUEUES[bus as usize], || !mutex.is_locked()) {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Extract the (model, serial) strings from the IDENTIFY data
+pub(crate) fn identify_strings(res: &[u16; 256]) -> (Box<str>, Box<str>) {
+    // Convert u16 array to bytes, but keep the original byte order for strings
+    // ATA strings are stored with bytes swapped within each 16-bit word
+    let mut buf = [0u8; 512];
+    for (i, &word) in res.iter().enumerate() {
+        let bytes = word.to_le_bytes(); // Keep little-endian for proper string parsing
+        buf[i * 2] = bytes[1];     // Swap bytes within each word for strings
+        buf[i * 2 + 1] = bytes[0];
+    }
+
+    // Extract serial number (20 bytes starting at word 10, byte offset 20)
+    let serial = {
+        let serial_bytes = &buf[20..40];
+        // Convert bytes to string, removing null terminators and trimming
+        let serial_str = core::str::from_utf8(serial_bytes)
+            .unwrap_or("")
+            .trim_end_matches('\0')
+            .trim();
+        serial_str.into()
+    };
+
+    // Extract model name (40 bytes starting at word 27, byte offset 54)
+    let model = {
+        let model_bytes = &buf[54..94];
+        // Convert bytes to string, removing null terminators and trimming
+        let model_str = core::str::from_utf8(model_bytes)
+            .unwrap_or("")
+            .trim_end_matches('\0')
+            .trim();
+        model_str.into()
+    };
+
+    (model, serial)
+}
+
+/// A drive found on an IDE bus
+pub enum IdeDrive {
+    Ata(AtaDrive),
+    Atapi(AtapiDrive),
+}
+
+/// Identify the drive at the given bus and drive number
+///
+/// Returns `None` if there is no drive or its type is not supported.
+pub fn probe(bus: u8, drive: u8) -> Option<IdeDrive> {
+    let mut ata_bus = match lock_bus(bus) {
+        Ok(ata_bus) => ata_bus,
+        Err(e) => {
+            warn!("Drive {}@{} is not available: {}", bus, drive, e);
+            return None;
+        }
+    };
+
+    match ata_bus.identify_drive(drive) {
+        Ok(AtaDeviceType::Pata(res)) => Some(IdeDrive::Ata(AtaDrive::new(
+            &mut ata_bus,
+            bus,
+            drive,
+            &res,
+        ))),
+        Ok(AtaDeviceType::PataPi(res)) => {
+            // the packet commands lock the bus by themselves
+            drop(ata_bus);
+            AtapiDrive::new(bus, drive, &res).map(IdeDrive::Atapi)
+        }
+        _ => {
+            trace!("No supported drive at {}@{}", bus, drive);
+            None
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AtaDrive {
     pub bus: u8,
     pub drive: u8,
-    blocks: u32,
+    blocks: u64,
+    mode: AtaTransfer,
     model: Box<str>,
     serial: Box<str>,
 }
@@ -38,78 +221,131 @@ impl AtaDrive {
     pub fn open(bus: u8, drive: u8) -> Option<Self> {
         trace!("Opening drive {}@{}...", bus, drive);
 
-        // we only support PATA drives
-        if let Ok(AtaDeviceType::Pata(res)) = BUSES[bus as usize].lock().identify_drive(drive) {
-            // Convert u16 array to bytes, but keep the original byte order for strings
-            // ATA strings are stored with bytes swapped within each 16-bit word
-            let mut buf = [0u8; 512];
-            for (i, &word) in res.iter().enumerate() {
-                let bytes = word.to_le_bytes(); // Keep little-endian for proper string parsing
-                buf[i * 2] = bytes[1];     // Swap bytes within each word for strings
-                buf[i * 2 + 1] = bytes[0];
+        match probe(bus, drive) {
+            Some(IdeDrive::Ata(ata_drive)) => Some(ata_drive),
+            _ => {
+                warn!("Drive {}@{} is not a PATA drive", bus, drive);
+                None
             }
+        }
+    }
 
-            // Extract serial number (20 bytes starting at word 10, byte offset 20)
-            let serial = {
-                let serial_bytes = &buf[20..40];
-                // Convert bytes to string, removing null terminators and trimming
-                let serial_str = core::str::from_utf8(serial_bytes)
-                    .unwrap_or("")
-                    .trim_end_matches('\0')
-                    .trim();
-                serial_str.into()
-            };
-
-            // Extract model name (40 bytes starting at word 27, byte offset 54)
-            let model = {
-                let model_bytes = &buf[54..94];
-                // Convert bytes to string, removing null terminators and trimming
-                let model_str = core::str::from_utf8(model_bytes)
-                    .unwrap_or("")
-                    .trim_end_matches('\0')
-                    .trim();
-                model_str.into()
-            };
+    /// Set up the drive from its IDENTIFY data
+    fn new(ata_bus: &mut AtaBus, bus: u8, drive: u8, res: &[u16; 256]) -> Self {
+        let (model, serial) = identify_strings(res);
 
-            // Extract block count (4 bytes starting at word 60, byte offset 120)
-            // For this numeric value, use the original word order
-            let blocks = {
-                let word_60 = res[60];
-                let word_61 = res[61];
-                ((word_61 as u32) << 16) | (word_60 as u32)
-            };
-
-            let ata_drive = Self {
-                bus,
-                drive,
-                model,
-                serial,
-                blocks,
-            };
-            info!("Drive {} opened", ata_drive);
-            Some(ata_drive)
+        // Word 83 bit 10: 48-bit LBA is supported
+        let lba48 = res[83] & (1 << 10) != 0;
+
+        // For these numeric values, use the original word order
+        let blocks = if lba48 {
+            // Extract block count (8 bytes starting at word 100, byte offset 200)
+            res[100..104]
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &word| (acc << 16) | word as u64)
         } else {
-            warn!("Drive {}@{} is not a PATA drive", bus, drive);
-            None
-        }
+            // Extract block count (4 bytes starting at word 60, byte offset 120)
+            let word_60 = res[60];
+            let word_61 = res[61];
+            ((word_61 as u64) << 16) | (word_60 as u64)
+        };
+
+        // Word 47 bits 0-7: max sectors per interrupt of READ/WRITE MULTIPLE
+        let multiple = match res[47] & 0xFF {
+            0 | 1 => 1,
+            max => match ata_bus.set_multiple_mode(drive, max) {
+                Ok(()) => max,
+                Err(_) => 1,
+            },
+        };
+
+        // Word 49 bit 8: DMA is supported
+        let dma = ata_bus.has_dma() && res[49] & (1 << 8) != 0;
+
+        let ata_drive = Self {
+            bus,
+            drive,
+            model,
+            serial,
+            blocks,
+            mode: AtaTransfer {
+                lba48,
+                multiple,
+                dma,
+            },
+        };
+        info!("Drive {} opened, {:?}", ata_drive, ata_drive.mode);
+        ata_drive
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
     }
 
     fn block_size(&self) -> usize {
-        512 // Standard ATA block size
+        SECTOR_SIZE // Standard ATA block size
     }
 
     fn block_count(&self) -> Result<usize, &'static str> {
         Ok(self.blocks as usize)
     }
 
-    /// Read a block from the drive
-    pub fn read_block_raw(&self, block: u32, buf: &mut [u8]) -> Result<(), &'static str> {
-        BUSES[self.bus as usize].lock().read_pio(self.drive, block, buf)
+    /// Check that `len` bytes starting at `block` are whole sectors on the drive
+    fn check_range(&self, block: u64, len: usize) -> Result<(), &'static str> {
+        if len % SECTOR_SIZE != 0 {
+            return Err("Buffer is not sector aligned");
+        }
+
+        if block + (len / SECTOR_SIZE) as u64 > self.blocks {
+            return Err("Block out of range");
+        }
+
+        Ok(())
+    }
+
+    /// Read consecutive blocks from the drive, `buf` holds whole blocks
+    pub fn read_block_raw(&self, block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.check_range(block, buf.len())?;
+
+        let mut bus = lock_bus(self.bus)?;
+        let max_bytes = self.mode.max_sectors() * SECTOR_SIZE;
+
+        for (i, chunk) in buf.chunks_mut(max_bytes).enumerate() {
+            let block = block + (i * self.mode.max_sectors()) as u64;
+            if self.mode.dma {
+                bus.read_dma(self.drive, self.mode, block, chunk)?;
+            } else {
+                bus.read_pio(self.drive, self.mode, block, chunk)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write consecutive blocks to the drive, `buf` holds whole blocks
+    pub fn write_block_raw(&self, block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.check_range(block, buf.len())?;
+
+        let mut bus = lock_bus(self.bus)?;
+        let max_bytes = self.mode.max_sectors() * SECTOR_SIZE;
+
+        for (i, chunk) in buf.chunks(max_bytes).enumerate() {
+            let block = block + (i * self.mode.max_sectors()) as u64;
+            if self.mode.dma {
+                bus.write_dma(self.drive, self.mode, block, chunk)?;
+            } else {
+                bus.write_pio(self.drive, self.mode, block, chunk)?;
+            }
+        }
+
+        Ok(())
     }
 
-    /// Write a block to the drive
-    pub fn write_block_raw(&self, block: u32, buf: &[u8]) -> Result<(), &'static str> {
-        BUSES[self.bus as usize].lock().write_pio(self.drive, block, buf)
+    /// Write the cached data of the drive to the medium
+    pub fn flush(&self) -> Result<(), &'static str> {
+        let mut bus = lock_bus(self.bus)?;
+        bus.flush_cache(self.drive, self.mode.lba48)
     }
 
     fn humanized_size(&self) -> (f32, &'static str) {
@@ -141,9 +377,7 @@ impl BlockDevice<Block512> for AtaDrive {
         // Read the block
         // Use BUSES and self to get bus
         // Use read_pio to get data
-        BUSES[self.bus as usize]
-            .lock()
-            .read_pio(self.drive, offset as u32, block.as_mut())
+        self.read_block_raw(offset as u64, block.as_mut())
             .map_err(|_| storage::DeviceError::ReadError.into())
     }
 
@@ -151,9 +385,30 @@ impl BlockDevice<Block512> for AtaDrive {
         // Write the block
         // Use BUSES and self to get bus
         // Use write_pio to write data
-        BUSES[self.bus as usize]
-            .lock()
-            .write_pio(self.drive, offset as u32, block.as_ref())
+        self.write_block_raw(offset as u64, block.as_ref())
+            .map_err(|_| storage::DeviceError::WriteError.into())
+    }
+
+    fn read_blocks(&self, offset: usize, blocks: &mut [Block512]) -> storage::FsResult {
+        // Read all the blocks with as few commands as possible
+        let mut buf = alloc::vec![0u8; blocks.len() * SECTOR_SIZE];
+        self.read_block_raw(offset as u64, &mut buf)
+            .map_err(|_| storage::FsError::from(storage::DeviceError::ReadError))?;
+
+        for (block, data) in blocks.iter_mut().zip(buf.chunks(SECTOR_SIZE)) {
+            block.as_mut().copy_from_slice(data);
+        }
+
+        Ok(())
+    }
+
+    fn write_blocks(&self, offset: usize, blocks: &[Block512]) -> storage::FsResult {
+        let buf: Vec<u8> = blocks.iter().flat_map(|block| block.iter().copied()).collect();
+        self.write_block_raw(offset as u64, &buf)
             .map_err(|_| storage::DeviceError::WriteError.into())
     }
+
+    fn flush(&self) -> storage::FsResult {
+        AtaDrive::flush(self).map_err(|_| storage::DeviceError::WriteError.into())
+    }
 }
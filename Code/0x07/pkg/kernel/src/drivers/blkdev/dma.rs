@@ -0,0 +1,227 @@
+//! Disks transferring their data by DMA through a bounce buffer
+//!
+//! The AHCI, NVMe and virtio drivers carry one command at a time per
+//! device. A process locks the device, moves its data through the bounce
+//! buffer of the device and sleeps until the command completes, the other
+//! processes sleep until the device is released. Each driver only submits
+//! the command and waits for its completion, see `DmaDisk`.
+
+use crate::proc::{self, ProcessId};
+use alloc::collections::VecDeque;
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+use storage::{Block512, DeviceError, FsError, FsResult};
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// Processes waiting for the devices of a driver, woken by its interrupt
+#[derive(Default)]
+pub struct WaitQueue(Mutex<VecDeque<ProcessId>>);
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self(Mutex::new(VecDeque::new()))
+    }
+
+    pub fn wake_up_all(&self) {
+        let pids = core::mem::take(&mut *self.0.lock());
+        let manager = proc::get_process_manager();
+        for pid in pids {
+            manager.wake_up(pid, None);
+        }
+    }
+
+    /// Wait until `done`, sleeping if the interrupt will wake the queue
+    /// and polling otherwise
+    pub fn wait(&self, interrupt: bool, done: impl Fn() -> bool) {
+        if !(interrupt && proc::sleep_on(&self.0, &done)) {
+            while !done() {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// A device used by one process at a time
+pub struct DeviceLock<T> {
+    inner: Mutex<T>,
+    queue: &'static WaitQueue,
+}
+
+impl<T> DeviceLock<T> {
+    pub const fn new(inner: T, queue: &'static WaitQueue) -> Self {
+        Self {
+            inner: Mutex::new(inner),
+            queue,
+        }
+    }
+
+    /// Lock the device, sleeping while another process is using it
+    pub fn lock(&self) -> DeviceGuard<'_, T> {
+        loop {
+            if let Some(inner) = self.inner.try_lock() {
+                return DeviceGuard {
+                    inner,
+                    queue: self.queue,
+                };
+            }
+
+            // 无法睡眠时自旋等待其他 CPU 上的持有者
+            if !proc::sleep_on(&self.queue.0, || !self.inner.is_locked()) {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// Guard of a locked device, wakes up the processes waiting for it on drop
+pub struct DeviceGuard<'a, T> {
+    inner: MutexGuard<'a, T>,
+    queue: &'static WaitQueue,
+}
+
+impl<T> Deref for DeviceGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for DeviceGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for DeviceGuard<'_, T> {
+    fn drop(&mut self) {
+        self.queue.wake_up_all();
+    }
+}
+
+/// A disk of a DMA driver, accessed in 512-byte sectors
+///
+/// The blocks of the device may be larger, a write covering a block only
+/// partially reads the block first.
+pub(crate) trait DmaDisk {
+    /// The state of the device behind its lock
+    type Device;
+
+    fn device(&self) -> &DeviceLock<Self::Device>;
+
+    /// Bytes of a block of the device, a multiple of `SECTOR_SIZE`
+    fn block_size(&self) -> usize;
+
+    /// Size of the disk in blocks
+    fn blocks(&self) -> u64;
+
+    /// Max bytes of a single transfer, at least a block
+    fn max_transfer(&self) -> usize;
+
+    /// The first `len` bytes of the bounce buffer
+    fn bounce_buffer(device: &mut Self::Device, len: usize) -> &mut [u8];
+
+    /// Read or write `count` blocks from `block` with the bounce buffer,
+    /// and wait for the command to complete
+    fn transfer(
+        &self,
+        device: &mut Self::Device,
+        block: u64,
+        count: usize,
+        write: bool,
+    ) -> Result<(), &'static str>;
+}
+
+fn sectors<D: DmaDisk>(disk: &D) -> u64 {
+    disk.blocks() * (disk.block_size() / SECTOR_SIZE) as u64
+}
+
+/// Read or write consecutive sectors, `buf` holds whole sectors
+fn transfer<D: DmaDisk>(
+    disk: &D,
+    sector: u64,
+    buf: &mut [u8],
+    write: bool,
+) -> Result<(), &'static str> {
+    if sector + (buf.len() / SECTOR_SIZE) as u64 > sectors(disk) {
+        return Err("Block out of range");
+    }
+
+    let block_size = disk.block_size();
+    let chunk_size = disk.max_transfer() / block_size * block_size;
+
+    let start = sector as usize * SECTOR_SIZE;
+    let end = start + buf.len();
+
+    let mut pos = start;
+    while pos < end {
+        let first_block = pos / block_size;
+        let chunk_start = first_block * block_size;
+        let chunk_end = (chunk_start + chunk_size).min(end.next_multiple_of(block_size));
+        let count = (chunk_end - chunk_start) / block_size;
+
+        // the part of `buf` in this chunk, and its offset in the chunk
+        let offset = pos - chunk_start;
+        let len = chunk_end.min(end) - pos;
+        let data = &mut buf[pos - start..pos - start + len];
+
+        let mut device = disk.device().lock();
+        if write {
+            if offset != 0 || len != count * block_size {
+                disk.transfer(&mut device, first_block as u64, count, false)?;
+            }
+            D::bounce_buffer(&mut device, chunk_end - chunk_start)[offset..offset + len]
+                .copy_from_slice(data);
+            disk.transfer(&mut device, first_block as u64, count, true)?;
+        } else {
+            disk.transfer(&mut device, first_block as u64, count, false)?;
+            data.copy_from_slice(
+                &D::bounce_buffer(&mut device, chunk_end - chunk_start)[offset..offset + len],
+            );
+        }
+
+        pos += len;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn block_count<D: DmaDisk>(disk: &D) -> FsResult<usize> {
+    Ok(sectors(disk) as usize)
+}
+
+pub(crate) fn read_block<D: DmaDisk>(disk: &D, offset: usize, block: &mut Block512) -> FsResult {
+    transfer(disk, offset as u64, block.as_mut(), false).map_err(|_| DeviceError::ReadError.into())
+}
+
+pub(crate) fn write_block<D: DmaDisk>(disk: &D, offset: usize, block: &Block512) -> FsResult {
+    let mut buf = [0u8; SECTOR_SIZE];
+    buf.copy_from_slice(block.as_ref());
+    transfer(disk, offset as u64, &mut buf, true).map_err(|_| DeviceError::WriteError.into())
+}
+
+pub(crate) fn read_blocks<D: DmaDisk>(
+    disk: &D,
+    offset: usize,
+    blocks: &mut [Block512],
+) -> FsResult {
+    let mut buf = alloc::vec![0u8; blocks.len() * SECTOR_SIZE];
+    transfer(disk, offset as u64, &mut buf, false)
+        .map_err(|_| FsError::from(DeviceError::ReadError))?;
+
+    for (block, data) in blocks.iter_mut().zip(buf.chunks(SECTOR_SIZE)) {
+        block.as_mut().copy_from_slice(data);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn write_blocks<D: DmaDisk>(disk: &D, offset: usize, blocks: &[Block512]) -> FsResult {
+    let mut buf = alloc::vec![0u8; blocks.len() * SECTOR_SIZE];
+    for (data, block) in buf.chunks_mut(SECTOR_SIZE).zip(blocks) {
+        data.copy_from_slice(block.as_ref());
+    }
+
+    transfer(disk, offset as u64, &mut buf, true).map_err(|_| DeviceError::WriteError.into())
+}
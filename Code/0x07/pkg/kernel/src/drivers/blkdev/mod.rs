@@ -0,0 +1,212 @@
+//! Block device registry
+//!
+//! Probes the drives on the IDE buses and the MBR partitions on them,
+//! and names them like Linux does: `hda`..`hdd` by bus and drive number,
+//! with partitions numbered from 1, e.g. `hda1`. Virtio disks are named
+//! `vda`, `vdb`..., SATA disks `sda`, `sdb`... and NVMe namespaces
+//! `nvme0n1`... when their driver is bound. The partitions of a disk whose
+//! name ends with a digit are separated by `p`, e.g. `nvme0n1p1`.
+
+pub mod dma;
+
+use super::ata::{self, IdeDrive};
+use super::{ahci, nvme, virtio};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use storage::mbr::MbrTable;
+use storage::*;
+
+/// A device with 512-byte sectors, like a hard disk or a partition on it
+pub type Disk = Arc<dyn BlockDevice<Block512>>;
+
+/// A device with 2048-byte sectors, like a CD-ROM
+pub type Rom = Arc<dyn BlockDevice<Block2048>>;
+
+#[derive(Clone)]
+pub enum BlockDeviceHandle {
+    Disk(Disk),
+    Rom(Rom),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockDeviceKind {
+    Disk,
+    Partition,
+    Rom,
+}
+
+/// A named block device
+#[derive(Clone)]
+pub struct BlockDeviceEntry {
+    pub name: String,
+    pub kind: BlockDeviceKind,
+    pub model: String,
+    pub handle: BlockDeviceHandle,
+}
+
+impl BlockDeviceEntry {
+    /// Size of the device in bytes
+    pub fn size(&self) -> u64 {
+        let (count, size) = match &self.handle {
+            BlockDeviceHandle::Disk(disk) => (disk.block_count(), disk.block_size()),
+            BlockDeviceHandle::Rom(rom) => (rom.block_count(), rom.block_size()),
+        };
+
+        count.unwrap_or(0) as u64 * size as u64
+    }
+}
+
+static BLOCK_DEVICES: Mutex<Vec<BlockDeviceEntry>> = Mutex::new(Vec::new());
+
+pub fn init() {
+    info!("Probing block devices...");
+
+    for bus in 0..2 {
+        for drive in 0..2 {
+            let name = format!("hd{}", (b'a' + bus * 2 + drive) as char);
+
+            match ata::probe(bus, drive) {
+                Some(IdeDrive::Ata(ata_drive)) => {
+                    let model = ata_drive.model().to_string();
+                    register_disk(&name, &model, Arc::new(ata_drive));
+                }
+                Some(IdeDrive::Atapi(atapi_drive)) => register(BlockDeviceEntry {
+                    name,
+                    kind: BlockDeviceKind::Rom,
+                    model: atapi_drive.model().to_string(),
+                    handle: BlockDeviceHandle::Rom(Arc::new(atapi_drive)),
+                }),
+                None => {}
+            }
+        }
+    }
+
+    // the virtio, SATA and NVMe disks are registered when their driver is bound
+    virtio::blk::init();
+    ahci::init();
+    nvme::init();
+
+    info!("Initialized Block Devices.");
+}
+
+/// Register a disk and the partitions on it
+pub fn register_disk(name: &str, model: &str, disk: Disk) {
+    register(BlockDeviceEntry {
+        name: name.to_string(),
+        kind: BlockDeviceKind::Disk,
+        model: model.to_string(),
+        handle: BlockDeviceHandle::Disk(disk.clone()),
+    });
+
+    let table = match MbrTable::parse(disk) {
+        Ok(table) => table,
+        Err(err) => {
+            debug!("No partition table on {}: {:?}", name, err);
+            return;
+        }
+    };
+
+    let separator = if name.ends_with(|c: char| c.is_ascii_digit()) {
+        "p"
+    } else {
+        ""
+    };
+
+    // named by their slot, whether bootable or not
+    for (i, part) in table.slots().into_iter().enumerate() {
+        let Some(part) = part else {
+            continue;
+        };
+
+        register(BlockDeviceEntry {
+            name: format!("{}{}{}", name, separator, i + 1),
+            kind: BlockDeviceKind::Partition,
+            model: String::new(),
+            handle: BlockDeviceHandle::Disk(Arc::new(part)),
+        });
+    }
+}
+
+pub fn register(entry: BlockDeviceEntry) {
+    let (size, unit) = crate::humanized_size(entry.size());
+    info!(
+        "Block device {}: {:?} {:.2} {} {}",
+        entry.name, entry.kind, size, unit, entry.model
+    );
+
+    BLOCK_DEVICES.lock().push(entry);
+}
+
+pub fn get(name: &str) -> Option<BlockDeviceEntry> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .find(|entry| entry.name == name)
+        .cloned()
+}
+
+pub fn get_disk(name: &str) -> Option<Disk> {
+    match get(name)?.handle {
+        BlockDeviceHandle::Disk(disk) => Some(disk),
+        BlockDeviceHandle::Rom(_) => None,
+    }
+}
+
+/// Get the first CD-ROM drive with a medium in it
+pub fn first_rom() -> Option<Rom> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .find_map(|entry| match &entry.handle {
+            BlockDeviceHandle::Rom(rom) => Some(rom.clone()),
+            BlockDeviceHandle::Disk(_) => None,
+        })
+}
+
+/// Write the cached data of all disks to their medium
+///
+/// The partitions share the cache of their disk, so only the disks are
+/// flushed. Returns false if any of them failed.
+pub fn sync() -> bool {
+    let disks: Vec<(String, Disk)> = BLOCK_DEVICES
+        .lock()
+        .iter()
+        .filter(|entry| entry.kind == BlockDeviceKind::Disk)
+        .filter_map(|entry| match &entry.handle {
+            BlockDeviceHandle::Disk(disk) => Some((entry.name.clone(), disk.clone())),
+            BlockDeviceHandle::Rom(_) => None,
+        })
+        .collect();
+
+    let mut ok = true;
+    for (name, disk) in disks {
+        if let Err(err) = disk.flush() {
+            warn!("Failed to flush {}: {:?}", name, err);
+            ok = false;
+        }
+    }
+
+    ok
+}
+
+pub fn list() {
+    let devices = BLOCK_DEVICES.lock().clone();
+
+    println!("{:<10} {:<6} {:>10}  {}", "Name", "Type", "Size", "Model");
+
+    for entry in devices {
+        let kind = match entry.kind {
+            BlockDeviceKind::Disk => "disk",
+            BlockDeviceKind::Partition => "part",
+            BlockDeviceKind::Rom => "rom",
+        };
+
+        let (size, unit) = crate::humanized_size_short(entry.size());
+        let size = format!("{:.1}{}", size, unit);
+
+        println!("{:<10} {:<6} {:>10}  {}", entry.name, kind, size, entry.model);
+    }
+}
@@ -0,0 +1,198 @@
+//! ANSI escape sequence parser
+//!
+//! A subset of the DEC VT100 state machine: control characters, ESC
+//! sequences and CSI sequences are turned into actions, OSC strings and
+//! character set selections are consumed and dropped.
+//!
+//! reference: https://vt100.net/emu/dec_ansi_parser
+//! reference: https://invisible-island.net/xterm/ctlseqs/ctlseqs.html
+
+/// Parameters kept of a CSI sequence, the rest are dropped
+pub const MAX_PARAMS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// After ESC
+    Escape,
+    /// After ESC and a character set designator, which takes one more
+    Charset,
+    /// After ESC [, collecting the parameters
+    CsiParam,
+    /// A malformed CSI sequence, skipped up to its final byte
+    CsiIgnore,
+    /// After ESC ], up to BEL or ESC \
+    Osc,
+    /// ESC inside an OSC string, the start of ESC \
+    OscEscape,
+}
+
+/// The parameters and final byte of a CSI sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// Marked by a leading `?`, as in DEC private modes
+    pub private: bool,
+    pub action: char,
+}
+
+impl Csi {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// The `index`th parameter, `default` if it is missing or 0
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// A printable character
+    Print(char),
+    /// A C0 control character
+    Control(char),
+    /// ESC followed by its final character
+    Escape(char),
+    Csi(Csi),
+}
+
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+    csi: Csi,
+    /// A digit of the current parameter has been seen
+    in_param: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            csi: Csi {
+                params: [0; MAX_PARAMS],
+                len: 0,
+                private: false,
+                action: '\0',
+            },
+            in_param: false,
+        }
+    }
+
+    fn start_csi(&mut self) {
+        self.csi.params = [0; MAX_PARAMS];
+        self.csi.len = 0;
+        self.csi.private = false;
+        self.in_param = false;
+        self.state = State::CsiParam;
+    }
+
+    /// Feed a character, returns the action it completes if any
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        // cancel the sequence and start another one
+        if c == '\x1b' && self.state != State::Osc {
+            self.state = State::Escape;
+            return None;
+        }
+
+        // CAN and SUB abort a sequence
+        if (c == '\x18' || c == '\x1a') && self.state != State::Ground {
+            self.state = State::Ground;
+            return None;
+        }
+
+        match self.state {
+            State::Ground => Some(if c.is_control() {
+                Action::Control(c)
+            } else {
+                Action::Print(c)
+            }),
+            // control characters are executed in the middle of sequences
+            _ if c.is_control() && !matches!(self.state, State::Osc | State::OscEscape) => {
+                Some(Action::Control(c))
+            }
+            State::Escape => match c {
+                '[' => {
+                    self.start_csi();
+                    None
+                }
+                ']' => {
+                    self.state = State::Osc;
+                    None
+                }
+                '(' | ')' | '*' | '+' | '#' => {
+                    self.state = State::Charset;
+                    None
+                }
+                _ => {
+                    self.state = State::Ground;
+                    Some(Action::Escape(c))
+                }
+            },
+            State::Charset => {
+                self.state = State::Ground;
+                None
+            }
+            State::CsiParam => match c {
+                '0'..='9' => {
+                    if self.csi.len == 0 {
+                        self.csi.len = 1;
+                    }
+                    if let Some(param) = self.csi.params.get_mut(self.csi.len - 1) {
+                        let digit = c as u16 - '0' as u16;
+                        *param = param.saturating_mul(10).saturating_add(digit);
+                    }
+                    self.in_param = true;
+                    None
+                }
+                ';' => {
+                    // an empty parameter before the separator counts as 0
+                    if self.csi.len == 0 {
+                        self.csi.len = 1;
+                    }
+                    if self.csi.len < MAX_PARAMS {
+                        self.csi.len += 1;
+                    }
+                    self.in_param = false;
+                    None
+                }
+                '?' if self.csi.len == 0 && !self.in_param => {
+                    self.csi.private = true;
+                    None
+                }
+                '\x40'..='\x7e' => {
+                    self.state = State::Ground;
+                    self.csi.action = c;
+                    Some(Action::Csi(self.csi))
+                }
+                _ => {
+                    self.state = State::CsiIgnore;
+                    None
+                }
+            },
+            State::CsiIgnore => {
+                if ('\x40'..='\x7e').contains(&c) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            State::Osc => {
+                match c {
+                    '\x07' => self.state = State::Ground,
+                    '\x1b' => self.state = State::OscEscape,
+                    _ => {}
+                }
+                None
+            }
+            State::OscEscape => {
+                self.state = if c == '\\' { State::Ground } else { State::Osc };
+                None
+            }
+        }
+    }
+}
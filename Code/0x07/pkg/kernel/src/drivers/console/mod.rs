@@ -0,0 +1,549 @@
+//! Framebuffer Console
+//!
+//! A text console drawn on the framebuffer with the Noto Sans Mono bitmap
+//! font. It interprets the VT100 escape sequences the host terminal does
+//! on the serial side: cursor movement, erasing, SGR colors and scroll
+//! regions. Like a tty with `onlcr`, a line feed also returns the carriage.
+
+mod ansi;
+
+use super::framebuffer::{Color, FrameBuffer};
+use ansi::{Action, Csi, Parser};
+use boot::GraphicInfo;
+use core::fmt;
+use noto_sans_mono_bitmap::{get_raster, get_raster_width, FontWeight, RasterHeight};
+
+const FONT_WEIGHT: FontWeight = FontWeight::Regular;
+const FONT_HEIGHT: RasterHeight = RasterHeight::Size16;
+
+/// Size of a character cell in pixels
+const CELL_WIDTH: usize = get_raster_width(FONT_WEIGHT, FONT_HEIGHT);
+const CELL_HEIGHT: usize = FONT_HEIGHT.val();
+
+/// Scan lines of the cell covered by the cursor
+const CURSOR_HEIGHT: usize = 2;
+
+const TAB_WIDTH: usize = 8;
+
+/// Drawn for the characters missing in the font
+const BACKUP_CHAR: char = '\u{FFFD}';
+
+/// The 16 colors of the VGA text mode, the normal ones then the bright ones
+const PALETTE: [Color; 16] = [
+    Color::new(0x00, 0x00, 0x00),
+    Color::new(0xAA, 0x00, 0x00),
+    Color::new(0x00, 0xAA, 0x00),
+    Color::new(0xAA, 0x55, 0x00),
+    Color::new(0x00, 0x00, 0xAA),
+    Color::new(0xAA, 0x00, 0xAA),
+    Color::new(0x00, 0xAA, 0xAA),
+    Color::new(0xAA, 0xAA, 0xAA),
+    Color::new(0x55, 0x55, 0x55),
+    Color::new(0xFF, 0x55, 0x55),
+    Color::new(0x55, 0xFF, 0x55),
+    Color::new(0xFF, 0xFF, 0x55),
+    Color::new(0x55, 0x55, 0xFF),
+    Color::new(0xFF, 0x55, 0xFF),
+    Color::new(0x55, 0xFF, 0xFF),
+    Color::new(0xFF, 0xFF, 0xFF),
+];
+
+const DEFAULT_FG: u8 = 7;
+const DEFAULT_BG: u8 = 0;
+
+/// A color set by SGR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TermColor {
+    /// One of the 256 xterm colors
+    Indexed(u8),
+    Rgb(Color),
+}
+
+impl TermColor {
+    fn to_color(self) -> Color {
+        match self {
+            TermColor::Indexed(index) if index < 16 => PALETTE[index as usize],
+            // 6x6x6 color cube
+            TermColor::Indexed(index) if index < 232 => {
+                let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+                let index = index - 16;
+                Color::new(level(index / 36), level(index / 6 % 6), level(index % 6))
+            }
+            // grayscale ramp
+            TermColor::Indexed(index) => {
+                let level = 8 + (index - 232) * 10;
+                Color::new(level, level, level)
+            }
+            TermColor::Rgb(color) => color,
+        }
+    }
+}
+
+/// Graphic rendition set by SGR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Attributes {
+    fg: TermColor,
+    bg: TermColor,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Attributes {
+    const DEFAULT: Attributes = Attributes {
+        fg: TermColor::Indexed(DEFAULT_FG),
+        bg: TermColor::Indexed(DEFAULT_BG),
+        bold: false,
+        reverse: false,
+    };
+
+    /// Colors of the text, bold brightens the 8 normal colors
+    fn colors(&self) -> (Color, Color) {
+        let fg = match self.fg {
+            TermColor::Indexed(index) if self.bold && index < 8 => PALETTE[index as usize + 8],
+            fg => fg.to_color(),
+        };
+        let bg = self.bg.to_color();
+
+        if self.reverse {
+            (bg, fg)
+        } else {
+            (fg, bg)
+        }
+    }
+}
+
+/// Cursor state kept by DECSC and restored by DECRC
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    col: usize,
+    row: usize,
+    attrs: Attributes,
+}
+
+pub struct Console {
+    fb: FrameBuffer,
+    cols: usize,
+    rows: usize,
+    /// Equals `cols` after the last column is filled, until the next
+    /// character wraps the line
+    col: usize,
+    row: usize,
+    /// First and last rows of the scroll region
+    top: usize,
+    bottom: usize,
+    attrs: Attributes,
+    saved: SavedCursor,
+    parser: Parser,
+    cursor_visible: bool,
+    cursor_shown: bool,
+}
+
+once_mutex!(pub CONSOLE: Console);
+
+guard_access_fn!(pub get_console(CONSOLE: Console));
+
+/// Set up the console if the bootloader left a usable framebuffer
+pub fn init(graphic_info: Option<&GraphicInfo>) {
+    let Some(info) = graphic_info else {
+        info!("No framebuffer, console on serial only.");
+        return;
+    };
+
+    let Some(fb) = FrameBuffer::new(info) else {
+        warn!("Framebuffer format {:?} not supported.", info.mode.pixel_format());
+        return;
+    };
+
+    let console = Console::new(fb);
+    let (cols, rows) = (console.cols, console.rows);
+    init_CONSOLE(console);
+
+    info!("Framebuffer Console Initialized, {}x{} characters.", cols, rows);
+}
+
+impl Console {
+    pub fn new(fb: FrameBuffer) -> Self {
+        let cols = (fb.width() / CELL_WIDTH).max(1);
+        let rows = (fb.height() / CELL_HEIGHT).max(1);
+
+        let mut console = Self {
+            fb,
+            cols,
+            rows,
+            col: 0,
+            row: 0,
+            top: 0,
+            bottom: rows - 1,
+            attrs: Attributes::DEFAULT,
+            saved: SavedCursor {
+                col: 0,
+                row: 0,
+                attrs: Attributes::DEFAULT,
+            },
+            parser: Parser::new(),
+            cursor_visible: true,
+            cursor_shown: false,
+        };
+
+        console.clear();
+        console.toggle_cursor();
+        console
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    pub fn clear(&mut self) {
+        self.erase_rows(0, self.rows);
+        self.col = 0;
+        self.row = 0;
+        self.cursor_shown = false;
+    }
+
+    /// Back to the state after power on
+    fn reset(&mut self) {
+        self.attrs = Attributes::DEFAULT;
+        self.saved = SavedCursor {
+            col: 0,
+            row: 0,
+            attrs: Attributes::DEFAULT,
+        };
+        self.top = 0;
+        self.bottom = self.rows - 1;
+        self.cursor_visible = true;
+        self.clear();
+    }
+
+    /// Invert the bottom lines of the cell under the cursor, twice restores it
+    fn toggle_cursor(&mut self) {
+        let x = self.col.min(self.cols - 1) * CELL_WIDTH;
+        let y = self.row * CELL_HEIGHT + CELL_HEIGHT - CURSOR_HEIGHT;
+
+        for dy in 0..CURSOR_HEIGHT {
+            for dx in 0..CELL_WIDTH {
+                let c = self.fb.pixel(x + dx, y + dy);
+                self.fb.set_pixel(x + dx, y + dy, Color::new(!c.r, !c.g, !c.b));
+            }
+        }
+
+        self.cursor_shown = !self.cursor_shown;
+    }
+
+    /// The erased cells take the current background, not reversed
+    fn erase_color(&self) -> Color {
+        self.attrs.bg.to_color()
+    }
+
+    /// Erase the cells `start..end` of `row`
+    fn erase_cells(&mut self, row: usize, start: usize, end: usize) {
+        let end = end.min(self.cols);
+        if start < end {
+            let color = self.erase_color();
+            self.fb.fill_rect(
+                start * CELL_WIDTH,
+                row * CELL_HEIGHT,
+                (end - start) * CELL_WIDTH,
+                CELL_HEIGHT,
+                color,
+            );
+        }
+    }
+
+    /// Erase the rows `start..end`
+    fn erase_rows(&mut self, start: usize, end: usize) {
+        let end = end.min(self.rows);
+        if start < end {
+            let color = self.erase_color();
+            let width = self.fb.width();
+            self.fb.fill_rect(
+                0,
+                start * CELL_HEIGHT,
+                width,
+                (end - start) * CELL_HEIGHT,
+                color,
+            );
+        }
+    }
+
+    /// Scroll the rows `top..=bottom` up by `lines`
+    fn scroll_up(&mut self, top: usize, bottom: usize, lines: usize) {
+        let color = self.erase_color();
+        self.fb
+            .scroll_up(top * CELL_HEIGHT, (bottom + 1) * CELL_HEIGHT, lines * CELL_HEIGHT, color);
+    }
+
+    /// Scroll the rows `top..=bottom` down by `lines`
+    fn scroll_down(&mut self, top: usize, bottom: usize, lines: usize) {
+        let color = self.erase_color();
+        self.fb
+            .scroll_down(top * CELL_HEIGHT, (bottom + 1) * CELL_HEIGHT, lines * CELL_HEIGHT, color);
+    }
+
+    fn draw_char(&mut self, c: char) {
+        let x = self.col * CELL_WIDTH;
+        let y = self.row * CELL_HEIGHT;
+        let (fg, bg) = self.attrs.colors();
+
+        self.fb.fill_rect(x, y, CELL_WIDTH, CELL_HEIGHT, bg);
+
+        let raster = get_raster(c, FONT_WEIGHT, FONT_HEIGHT)
+            .or_else(|| get_raster(BACKUP_CHAR, FONT_WEIGHT, FONT_HEIGHT));
+
+        if let Some(raster) = raster {
+            for (dy, line) in raster.raster().iter().enumerate() {
+                for (dx, &alpha) in line.iter().enumerate() {
+                    if alpha != 0 {
+                        self.fb.set_pixel(x + dx, y + dy, fg.blend(bg, alpha));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move down a row, scrolling at the bottom of the scroll region
+    fn line_feed(&mut self) {
+        if self.row == self.bottom {
+            self.scroll_up(self.top, self.bottom, 1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    /// Move up a row, scrolling at the top of the scroll region
+    fn reverse_line_feed(&mut self) {
+        if self.row == self.top {
+            self.scroll_down(self.top, self.bottom, 1);
+        } else if self.row > 0 {
+            self.row -= 1;
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        if self.col >= self.cols {
+            self.col = 0;
+            self.line_feed();
+        }
+        self.draw_char(c);
+        self.col += 1;
+    }
+
+    fn control(&mut self, c: char) {
+        match c {
+            '\n' | '\x0b' | '\x0c' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            '\r' => self.col = 0,
+            '\x08' => self.col = self.col.min(self.cols - 1).saturating_sub(1),
+            '\t' => {
+                self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1);
+            }
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, c: char) {
+        match c {
+            // DECSC, DECRC
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            // IND, NEL, RI
+            'D' => self.line_feed(),
+            'E' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            'M' => self.reverse_line_feed(),
+            // RIS
+            'c' => self.reset(),
+            _ => {}
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = SavedCursor {
+            col: self.col,
+            row: self.row,
+            attrs: self.attrs,
+        };
+    }
+
+    fn restore_cursor(&mut self) {
+        self.col = self.saved.col.min(self.cols - 1);
+        self.row = self.saved.row.min(self.rows - 1);
+        self.attrs = self.saved.attrs;
+    }
+
+    /// Move the cursor to the 0-based `row` and `col`, clamped to the screen
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.col = col.min(self.cols - 1);
+    }
+
+    fn csi(&mut self, csi: &Csi) {
+        let n = csi.param(0, 1) as usize;
+        let col = self.col.min(self.cols - 1);
+
+        if csi.private {
+            // DECTCEM, show or hide the cursor
+            if csi.params().contains(&25) {
+                match csi.action {
+                    'h' => self.cursor_visible = true,
+                    'l' => self.cursor_visible = false,
+                    _ => {}
+                }
+            }
+            return;
+        }
+
+        match csi.action {
+            // CUU, CUD, CUF, CUB
+            'A' => {
+                // stop at the top margin when starting inside the region
+                let limit = if self.row >= self.top { self.top } else { 0 };
+                self.move_to(self.row.saturating_sub(n).max(limit), col);
+            }
+            'B' => {
+                let limit = if self.row <= self.bottom { self.bottom } else { self.rows - 1 };
+                self.move_to((self.row + n).min(limit), col);
+            }
+            'C' => self.move_to(self.row, col + n),
+            'D' => self.move_to(self.row, col.saturating_sub(n)),
+            // CNL, CPL
+            'E' => self.move_to(self.row + n, 0),
+            'F' => self.move_to(self.row.saturating_sub(n), 0),
+            // CHA, VPA
+            'G' | '`' => self.move_to(self.row, n - 1),
+            'd' => self.move_to(n - 1, col),
+            // CUP
+            'H' | 'f' => {
+                let row = csi.param(0, 1) as usize;
+                let col = csi.param(1, 1) as usize;
+                self.move_to(row - 1, col - 1);
+            }
+            // ED
+            'J' => match csi.param(0, 0) {
+                0 => {
+                    self.erase_cells(self.row, col, self.cols);
+                    self.erase_rows(self.row + 1, self.rows);
+                }
+                1 => {
+                    self.erase_rows(0, self.row);
+                    self.erase_cells(self.row, 0, col + 1);
+                }
+                _ => self.erase_rows(0, self.rows),
+            },
+            // EL
+            'K' => match csi.param(0, 0) {
+                0 => self.erase_cells(self.row, col, self.cols),
+                1 => self.erase_cells(self.row, 0, col + 1),
+                _ => self.erase_cells(self.row, 0, self.cols),
+            },
+            // ECH
+            'X' => self.erase_cells(self.row, col, col + n),
+            // IL, DL, only inside the scroll region
+            'L' if (self.top..=self.bottom).contains(&self.row) => {
+                self.scroll_down(self.row, self.bottom, n);
+                self.col = 0;
+            }
+            'M' if (self.top..=self.bottom).contains(&self.row) => {
+                self.scroll_up(self.row, self.bottom, n);
+                self.col = 0;
+            }
+            // SU, SD
+            'S' => self.scroll_up(self.top, self.bottom, n),
+            'T' => self.scroll_down(self.top, self.bottom, n),
+            // SGR
+            'm' => self.sgr(csi.params()),
+            // DECSTBM
+            'r' => {
+                let top = csi.param(0, 1) as usize - 1;
+                let bottom = (csi.param(1, self.rows as u16) as usize).min(self.rows) - 1;
+                if top < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            // SCOSC, SCORC
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    /// Select graphic rendition
+    fn sgr(&mut self, params: &[u16]) {
+        // no parameter is a reset
+        if params.is_empty() {
+            self.attrs = Attributes::DEFAULT;
+            return;
+        }
+
+        let mut iter = params.iter().copied();
+        while let Some(param) = iter.next() {
+            match param {
+                0 => self.attrs = Attributes::DEFAULT,
+                1 => self.attrs.bold = true,
+                22 => self.attrs.bold = false,
+                7 => self.attrs.reverse = true,
+                27 => self.attrs.reverse = false,
+                30..=37 => self.attrs.fg = TermColor::Indexed((param - 30) as u8),
+                39 => self.attrs.fg = Attributes::DEFAULT.fg,
+                40..=47 => self.attrs.bg = TermColor::Indexed((param - 40) as u8),
+                49 => self.attrs.bg = Attributes::DEFAULT.bg,
+                90..=97 => self.attrs.fg = TermColor::Indexed((param - 90 + 8) as u8),
+                100..=107 => self.attrs.bg = TermColor::Indexed((param - 100 + 8) as u8),
+                // 38;5;n and 38;2;r;g;b, 48 for the background
+                38 | 48 => {
+                    let color = match iter.next() {
+                        Some(5) => iter.next().map(|n| TermColor::Indexed(n as u8)),
+                        Some(2) => {
+                            let mut next = || iter.next().unwrap_or(0) as u8;
+                            Some(TermColor::Rgb(Color::new(next(), next(), next())))
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(color) = color {
+                        if param == 38 {
+                            self.attrs.fg = color;
+                        } else {
+                            self.attrs.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn put_char(&mut self, c: char) {
+        match self.parser.advance(c) {
+            Some(Action::Print(c)) => self.print(c),
+            Some(Action::Control(c)) => self.control(c),
+            Some(Action::Escape(c)) => self.escape(c),
+            Some(Action::Csi(csi)) => self.csi(&csi),
+            None => {}
+        }
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.cursor_shown {
+            self.toggle_cursor();
+        }
+
+        for c in s.chars() {
+            self.put_char(c);
+        }
+
+        if self.cursor_visible {
+            self.toggle_cursor();
+        }
+
+        Ok(())
+    }
+}
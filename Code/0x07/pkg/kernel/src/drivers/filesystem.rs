@@ -1,40 +1,68 @@
-use super::ata::*;
+use super::blkdev;
 use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::ToString;
 use storage::fat16::Fat16;
-use storage::mbr::*;
+use storage::iso9660::Iso9660;
 use storage::*;
 
 pub static ROOTFS: spin::Once<Mount> = spin::Once::new();
 
+pub static CDROM: spin::Once<Mount> = spin::Once::new();
+
+/// Mount point of the first CD-ROM with a medium in it
+pub const CDROM_MOUNT_POINT: &str = "/cdrom";
+
 pub fn get_rootfs() -> &'static Mount {
     ROOTFS.get().unwrap()
 }
 
-pub fn init() {
-    info!("Opening disk device...");
+/// Get the filesystem that `path` belongs to
+pub fn get_fs(path: &str) -> &'static Mount {
+    if let Some(cdrom) = CDROM.get() {
+        let rest = path.strip_prefix(CDROM_MOUNT_POINT);
+        if rest.is_some_and(|rest| rest.is_empty() || rest.starts_with('/')) {
+            return cdrom;
+        }
+    }
 
-    let drive = AtaDrive::open(0, 0).expect("Failed to open disk device");
+    get_rootfs()
+}
+
+pub fn init(root_device: &str) {
+    info!("Opening root device {}...", root_device);
 
-    // only get the first partition
-    let part = MbrTable::parse(drive)
-        .expect("Failed to parse MBR")
-        .partitions()
-        .expect("Failed to get partitions")
-        .remove(0);
+    let disk = blkdev::get_disk(root_device)
+        .unwrap_or_else(|| panic!("Root device {} not found", root_device));
 
     info!("Mounting filesystem...");
 
-    ROOTFS.call_once(|| Mount::new(Box::new(Fat16::new(part)), "/".into()));
+    ROOTFS.call_once(|| Mount::new(Box::new(Fat16::new(disk)), "/".into()));
 
     trace!("Root filesystem: {:#?}", ROOTFS.get().unwrap());
 
+    mount_cdrom();
+
     info!("Initialized Filesystem.");
 }
 
+/// Mount the ISO 9660 image in the CD-ROM drive, which is optional
+fn mount_cdrom() {
+    let Some(rom) = blkdev::first_rom() else {
+        return;
+    };
+
+    match Iso9660::new(rom) {
+        Ok(fs) => {
+            CDROM.call_once(|| Mount::new(Box::new(fs), CDROM_MOUNT_POINT.into()));
+            info!("Mounted CD-ROM at {}", CDROM_MOUNT_POINT);
+        }
+        Err(err) => warn!("Failed to mount CD-ROM: {:?}", err),
+    }
+}
+
 pub fn ls(root_path: &str) {
-    let iter = match get_rootfs().read_dir(root_path) {
+    let iter = match get_fs(root_path).read_dir(root_path) {
         Ok(iter) => iter,
         Err(err) => {
             warn!("{:?}", err);
@@ -77,7 +105,7 @@ pub fn ls(root_path: &str) {
 }
 
 pub fn cat(file_path: &str) {
-    let mut file_handle = match get_rootfs().open_file(file_path) {
+    let mut file_handle = match get_fs(file_path).open_file(file_path) {
         Ok(handle) => handle,
         Err(err) => {
             warn!("Failed to open file '{}': {:?}", file_path, err);
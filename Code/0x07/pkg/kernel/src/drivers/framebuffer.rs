@@ -0,0 +1,170 @@
+//! UEFI GOP Framebuffer
+//!
+//! The framebuffer set up by the bootloader, written directly through the
+//! physical memory mapping. Only 32-bit RGB and BGR pixels are supported.
+
+use crate::memory::physical_to_virtual;
+use boot::{GraphicInfo, PixelFormat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0, 0, 0);
+    pub const WHITE: Color = Color::new(0xFF, 0xFF, 0xFF);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Mix `self` into `bg` by `alpha`, 0 is `bg` and 255 is `self`
+    pub fn blend(self, bg: Color, alpha: u8) -> Color {
+        let mix = |fg: u8, bg: u8| {
+            ((fg as u16 * alpha as u16 + bg as u16 * (255 - alpha as u16)) / 255) as u8
+        };
+        Color::new(mix(self.r, bg.r), mix(self.g, bg.g), mix(self.b, bg.b))
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameBuffer {
+    /// Virtual address of the first pixel
+    base: u64,
+    width: usize,
+    height: usize,
+    /// Pixels per scan line, may be larger than the width
+    stride: usize,
+    /// The blue byte comes first
+    bgr: bool,
+}
+
+impl FrameBuffer {
+    pub fn new(info: &GraphicInfo) -> Option<Self> {
+        let bgr = match info.mode.pixel_format() {
+            PixelFormat::Rgb => false,
+            PixelFormat::Bgr => true,
+            _ => return None,
+        };
+
+        let (width, height) = info.mode.resolution();
+        let stride = info.mode.stride();
+
+        if (stride * height * 4) as u64 > info.fb_size {
+            return None;
+        }
+
+        Some(Self {
+            base: physical_to_virtual(info.fb_addr),
+            width,
+            height,
+            stride,
+            bgr,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn encode(&self, color: Color) -> u32 {
+        let (r, g, b) = (color.r as u32, color.g as u32, color.b as u32);
+        if self.bgr {
+            r << 16 | g << 8 | b
+        } else {
+            b << 16 | g << 8 | r
+        }
+    }
+
+    fn decode(&self, pixel: u32) -> Color {
+        let (high, g, low) = ((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8);
+        if self.bgr {
+            Color::new(high, g, low)
+        } else {
+            Color::new(low, g, high)
+        }
+    }
+
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        (self.base as *mut u32).wrapping_add(y * self.stride + x)
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            let pixel = self.encode(color);
+            unsafe { self.pixel_ptr(x, y).write_volatile(pixel) };
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        if x < self.width && y < self.height {
+            self.decode(unsafe { self.pixel_ptr(x, y).read_volatile() })
+        } else {
+            Color::BLACK
+        }
+    }
+
+    /// Fill the rectangle at (`x`, `y`), clipped to the screen
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        let pixel = self.encode(color);
+        let right = (x + width).min(self.width);
+        let bottom = (y + height).min(self.height);
+
+        for row in y..bottom {
+            for col in x..right {
+                unsafe { self.pixel_ptr(col, row).write_volatile(pixel) };
+            }
+        }
+    }
+
+    /// Move the scan lines in `top..bottom` up by `lines`, the lines left
+    /// at the bottom are filled with `color`
+    pub fn scroll_up(&mut self, top: usize, bottom: usize, lines: usize, color: Color) {
+        let bottom = bottom.min(self.height);
+        if top >= bottom {
+            return;
+        }
+
+        let lines = lines.min(bottom - top);
+        let moved = bottom - top - lines;
+
+        unsafe {
+            core::ptr::copy(
+                self.pixel_ptr(0, top + lines),
+                self.pixel_ptr(0, top),
+                moved * self.stride,
+            );
+        }
+
+        self.fill_rect(0, top + moved, self.width, lines, color);
+    }
+
+    /// Move the scan lines in `top..bottom` down by `lines`, the lines left
+    /// at the top are filled with `color`
+    pub fn scroll_down(&mut self, top: usize, bottom: usize, lines: usize, color: Color) {
+        let bottom = bottom.min(self.height);
+        if top >= bottom {
+            return;
+        }
+
+        let lines = lines.min(bottom - top);
+        let moved = bottom - top - lines;
+
+        unsafe {
+            core::ptr::copy(
+                self.pixel_ptr(0, top),
+                self.pixel_ptr(0, top + lines),
+                moved * self.stride,
+            );
+        }
+
+        self.fill_rect(0, top, self.width, lines, color);
+    }
+}
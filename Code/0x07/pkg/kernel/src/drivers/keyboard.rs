@@ -0,0 +1,239 @@
+//! PS/2 Keyboard
+//!
+//! Only the first port of the i8042 controller is used. The scancodes
+//! received on IRQ1 are decoded by `pc-keyboard`, which tracks the
+//! modifiers and maps the keys with the configured layout, and go to the
+//! console like the characters received by the serial port.
+//!
+//! reference: https://wiki.osdev.org/I8042_PS/2_Controller
+//! reference: https://wiki.osdev.org/PS/2_Keyboard
+
+use crate::drivers::tty::Tty;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1, ScancodeSet2};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+const DATA_PORT: u16 = 0x60;
+/// Status register when read, command register when written
+const COMMAND_PORT: u16 = 0x64;
+
+/// Status register bits
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+/// Polls of the status register before giving up
+const TIMEOUT: usize = 100_000;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    ReadConfig = 0x20,
+    WriteConfig = 0x60,
+    DisablePort2 = 0xA7,
+    SelfTest = 0xAA,
+    TestPort1 = 0xAB,
+    DisablePort1 = 0xAD,
+    EnablePort1 = 0xAE,
+}
+
+bitflags! {
+    /// Controller configuration byte
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct ControllerConfig: u8 {
+        const PORT1_INTERRUPT   = 1 << 0;
+        const PORT2_INTERRUPT   = 1 << 1;
+        const PORT1_CLOCK_OFF   = 1 << 4;
+        const PORT2_CLOCK_OFF   = 1 << 5;
+        /// Scancodes of port 1 are translated to set 1
+        const PORT1_TRANSLATION = 1 << 6;
+    }
+}
+
+/// The scancode set depends on the translation done by the controller
+enum Decoder {
+    Set1(Keyboard<layouts::AnyLayout, ScancodeSet1>),
+    Set2(Keyboard<layouts::AnyLayout, ScancodeSet2>),
+}
+
+impl Decoder {
+    fn add_byte(&mut self, byte: u8) -> Option<DecodedKey> {
+        match self {
+            Decoder::Set1(keyboard) => {
+                let event = keyboard.add_byte(byte).ok()??;
+                keyboard.process_keyevent(event)
+            }
+            Decoder::Set2(keyboard) => {
+                let event = keyboard.add_byte(byte).ok()??;
+                keyboard.process_keyevent(event)
+            }
+        }
+    }
+}
+
+/// None until a keyboard is found, the scancodes are dropped meanwhile
+static DECODER: Mutex<Option<Decoder>> = Mutex::new(None);
+
+/// The keyboard layout named `name` in the boot config
+fn layout(name: &str) -> Option<layouts::AnyLayout> {
+    let layout = match name {
+        "us" => layouts::AnyLayout::Us104Key(layouts::Us104Key),
+        "uk" => layouts::AnyLayout::Uk105Key(layouts::Uk105Key),
+        "de" => layouts::AnyLayout::De105Key(layouts::De105Key),
+        "fr" | "azerty" => layouts::AnyLayout::Azerty(layouts::Azerty),
+        "jp" | "jis" => layouts::AnyLayout::Jis109Key(layouts::Jis109Key),
+        "dvorak" => layouts::AnyLayout::Dvorak104Key(layouts::Dvorak104Key),
+        "colemak" => layouts::AnyLayout::Colemak(layouts::Colemak),
+        _ => return None,
+    };
+    Some(layout)
+}
+
+fn status() -> u8 {
+    unsafe { PortReadOnly::<u8>::new(COMMAND_PORT).read() }
+}
+
+/// Wait until the controller accepts a byte
+fn wait_write() -> Result<(), &'static str> {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err("i8042 input buffer stays full")
+}
+
+/// Wait until the controller has a byte for us
+fn wait_read() -> Result<(), &'static str> {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_OUTPUT_FULL != 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err("i8042 does not respond")
+}
+
+fn command(command: Command) -> Result<(), &'static str> {
+    wait_write()?;
+    unsafe { PortWriteOnly::<u8>::new(COMMAND_PORT).write(command as u8) };
+    Ok(())
+}
+
+fn read_data() -> Result<u8, &'static str> {
+    wait_read()?;
+    Ok(unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+fn write_data(data: u8) -> Result<(), &'static str> {
+    wait_write()?;
+    unsafe { Port::<u8>::new(DATA_PORT).write(data) };
+    Ok(())
+}
+
+fn read_config() -> Result<ControllerConfig, &'static str> {
+    command(Command::ReadConfig)?;
+    Ok(ControllerConfig::from_bits_retain(read_data()?))
+}
+
+fn write_config(config: ControllerConfig) -> Result<(), &'static str> {
+    command(Command::WriteConfig)?;
+    write_data(config.bits())
+}
+
+/// Set up the controller with interrupts of port 1 enabled
+///
+/// Returns true if the scancodes are translated to set 1.
+fn init_controller() -> Result<bool, &'static str> {
+    // nothing decodes to all bits set, the controller is missing
+    if status() == 0xFF {
+        return Err("no i8042 controller");
+    }
+
+    command(Command::DisablePort1)?;
+    command(Command::DisablePort2)?;
+
+    // drop the bytes received before
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+
+    let mut config = read_config()?;
+    config -= ControllerConfig::PORT1_INTERRUPT | ControllerConfig::PORT2_INTERRUPT;
+    write_config(config)?;
+
+    command(Command::SelfTest)?;
+    if read_data()? != 0x55 {
+        return Err("i8042 self test failed");
+    }
+
+    // the self test may reset the controller
+    write_config(config)?;
+
+    command(Command::TestPort1)?;
+    if read_data()? != 0x00 {
+        return Err("PS/2 port 1 test failed");
+    }
+
+    command(Command::EnablePort1)?;
+
+    config -= ControllerConfig::PORT1_CLOCK_OFF;
+    config |= ControllerConfig::PORT1_INTERRUPT;
+    write_config(config)?;
+
+    Ok(config.contains(ControllerConfig::PORT1_TRANSLATION))
+}
+
+/// Find the keyboard and decode its keys with the layout named `layout_name`
+pub fn init(layout_name: &str) {
+    let layout = layout(layout_name).unwrap_or_else(|| {
+        warn!("Unknown keyboard layout {}, using us", layout_name);
+        layouts::AnyLayout::Us104Key(layouts::Us104Key)
+    });
+
+    // the decoder must be ready before the controller raises interrupts
+    let mut decoder = DECODER.lock();
+
+    // IRQ1 goes to this CPU, its handler must not take the responses of
+    // the controller meanwhile
+    match interrupts::without_interrupts(init_controller) {
+        Ok(translated) => {
+            let handle = HandleControl::MapLettersToUnicode;
+            *decoder = Some(if translated {
+                Decoder::Set1(Keyboard::new(ScancodeSet1::new(), layout, handle))
+            } else {
+                Decoder::Set2(Keyboard::new(ScancodeSet2::new(), layout, handle))
+            });
+
+            info!(
+                "PS/2 keyboard initialized, scancode set {}, layout {}",
+                if translated { 1 } else { 2 },
+                layout_name
+            );
+        }
+        Err(err) => warn!("PS/2 keyboard: {}", err),
+    }
+}
+
+/// Handle IRQ1, a byte of a scancode is available
+pub fn handle_irq() {
+    // read it anyway, or the controller will not send the next one
+    let byte = unsafe { Port::<u8>::new(DATA_PORT).read() };
+
+    let Some(mut decoder) = DECODER.try_lock() else {
+        return;
+    };
+
+    let Some(decoder) = decoder.as_mut() else {
+        return;
+    };
+
+    // keys without a character, like the arrows, are ignored
+    if let Some(DecodedKey::Unicode(c)) = decoder.add_byte(byte) {
+        // the delete key, which would erase like backspace does
+        if c != '\x7f' {
+            Tty::Console.receive(c);
+        }
+    }
+}
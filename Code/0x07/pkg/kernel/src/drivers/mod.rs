@@ -1,5 +1,17 @@
 pub mod uart16550;
 pub mod serial;
-pub mod input;
+pub mod framebuffer;
+pub mod console;
+pub mod tty;
+pub mod keyboard;
+pub mod rtc;
+pub mod random;
+pub mod pit;
 pub mod ata;
+pub mod ahci;
+pub mod nvme;
+pub mod pci;
+pub mod acpi;
+pub mod virtio;
+pub mod blkdev;
 pub mod filesystem; 
\ No newline at end of file
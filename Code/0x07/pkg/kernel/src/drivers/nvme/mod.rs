@@ -0,0 +1,600 @@
+//! NVMe Controller
+//!
+//! The controller is set up with the admin queue and a single I/O queue
+//! pair, which carries one command at a time. The data goes through a
+//! bounce buffer described by PRPs, see `blkdev::dma`.
+//!
+//! reference: https://wiki.osdev.org/NVMe
+//! reference: NVM Express Base Specification 1.4
+
+mod queue;
+
+use crate::drivers::blkdev::dma::{self, DeviceLock, DmaDisk, WaitQueue, SECTOR_SIZE};
+use crate::drivers::{blkdev, pci};
+use crate::interrupt::{self, Irq};
+use crate::memory::{get_frame_alloc_for_sure, physical_to_virtual, FRAME_SIZE};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, Ordering};
+use queue::{Command, QueuePair};
+use storage::{Block512, BlockDevice};
+use x86_64::structures::paging::FrameAllocator;
+
+/// Valid LBA data sizes of a namespace format, as a power of two
+const LBADS_MIN: u32 = 9;
+const LBADS_MAX: u32 = 16;
+
+/// Frames of the bounce buffer of each controller
+const BUFFER_FRAMES: usize = 16;
+
+/// Size of the bounce buffer in bytes
+const BUFFER_SIZE: usize = BUFFER_FRAMES * FRAME_SIZE as usize;
+
+// controller registers
+const REG_CAP: usize = 0x00;
+const REG_VS: usize = 0x08;
+const REG_CC: usize = 0x14;
+const REG_CSTS: usize = 0x1C;
+const REG_AQA: usize = 0x24;
+const REG_ASQ: usize = 0x28;
+const REG_ACQ: usize = 0x30;
+
+/// CC: enable, 64-byte submission and 16-byte completion queue entries,
+/// NVM command set and 4 KiB memory pages
+const CC_EN: u32 = 1 << 0;
+const CC_IOSQES: u32 = 6 << 16;
+const CC_IOCQES: u32 = 4 << 20;
+
+/// CSTS: ready, controller fatal status
+const CSTS_RDY: u32 = 1 << 0;
+const CSTS_CFS: u32 = 1 << 1;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AdminCommand {
+    CreateIoSq = 0x01,
+    CreateIoCq = 0x05,
+    Identify = 0x06,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IoCommand {
+    Flush = 0x00,
+    Write = 0x01,
+    Read = 0x02,
+}
+
+/// Controller or namespace structure returned by IDENTIFY
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdentifyCns {
+    Namespace = 0x00,
+    Controller = 0x01,
+    ActiveNamespaces = 0x02,
+}
+
+/// Processes waiting for a command to complete or for a controller to be released
+static WAIT_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Controllers are numbered nvme0, nvme1... in the order they are found
+static NEXT_INDEX: AtomicU8 = AtomicU8::new(0);
+
+static DRIVER: pci::PciDriver = pci::PciDriver {
+    name: "nvme",
+    // mass storage controller, NVM, NVMe
+    matches: &[pci::PciMatch::ClassProgIf(0x01, 0x08, 0x02)],
+    probe,
+};
+
+pub fn init() {
+    pci::register_driver(&DRIVER);
+}
+
+/// Handle the completion interrupt, shared by all controllers
+pub fn handle_irq() {
+    WAIT_QUEUE.wake_up_all();
+}
+
+fn probe(dev: &pci::PciDevice) -> bool {
+    let Some((addr, size)) = dev.bars[0].and_then(|bar| bar.memory()) else {
+        warn!("NVMe {}: no register BAR", dev.addr);
+        return false;
+    };
+
+    if addr + size > u32::MAX as u64 + 1 {
+        warn!("NVMe {}: BAR0 at {:#x} is not mapped", dev.addr, addr);
+        return false;
+    }
+
+    dev.addr.enable_bus_master();
+
+    let interrupt = enable_interrupt(dev);
+
+    let controller = match Controller::new(physical_to_virtual(addr), interrupt) {
+        Ok(controller) => controller,
+        Err(err) => {
+            warn!("NVMe {}: {}", dev.addr, err);
+            return false;
+        }
+    };
+
+    let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+    let model = controller.model.clone();
+    let controller = Arc::new(DeviceLock::new(controller, &WAIT_QUEUE));
+
+    let namespaces = match NvmeNamespace::probe_all(&controller) {
+        Ok(namespaces) => namespaces,
+        Err(err) => {
+            warn!("NVMe {}: {}", dev.addr, err);
+            return false;
+        }
+    };
+
+    for ns in namespaces {
+        let name = format!("nvme{}n{}", index, ns.nsid);
+        info!(
+            "NVMe namespace {}: {} blocks of {} bytes",
+            name, ns.lbas, ns.lba_size
+        );
+        blkdev::register_disk(&name, &model, Arc::new(ns));
+    }
+
+    true
+}
+
+/// Deliver the interrupts with MSI-X entry 0 or MSI, returns false if
+/// neither can be used, so the completion queue is polled.
+fn enable_interrupt(dev: &pci::PciDevice) -> bool {
+    let Some((apic_id, vector)) = interrupt::msi_target(Irq::Nvme) else {
+        return false;
+    };
+
+    if let Some(msix) = dev.msix() {
+        // the BIR may name no BAR or one not implemented
+        let (bir, _) = msix.table();
+        let bar = dev.bars.get(bir as usize).copied().flatten().filter(|bar| {
+            bar.memory()
+                .is_some_and(|(addr, size)| addr + size <= u32::MAX as u64 + 1)
+        });
+
+        if bar.is_some_and(|bar| msix.set_entry(&bar, 0, apic_id, vector).is_some()) {
+            msix.enable();
+            return true;
+        }
+    }
+
+    if let Some(msi) = dev.msi() {
+        msi.enable(apic_id, vector);
+        return true;
+    }
+
+    false
+}
+
+pub(crate) struct Controller {
+    /// Virtual address of the registers
+    regs: u64,
+    admin: QueuePair,
+    io: QueuePair,
+    /// Physical address of the bounce buffer
+    buffer: u64,
+    /// Physical address of the PRP list describing the bounce buffer
+    prp_list: u64,
+    /// Max bytes of a single transfer
+    max_transfer: usize,
+    /// Completion interrupts are delivered, otherwise the queues are polled
+    interrupt: bool,
+    model: Box<str>,
+    serial: Box<str>,
+}
+
+impl Controller {
+    fn read32(&self, reg: usize) -> u32 {
+        unsafe { ((self.regs as usize + reg) as *const u32).read_volatile() }
+    }
+
+    fn write32(&self, reg: usize, value: u32) {
+        unsafe { ((self.regs as usize + reg) as *mut u32).write_volatile(value) }
+    }
+
+    fn read64(&self, reg: usize) -> u64 {
+        unsafe { ((self.regs as usize + reg) as *const u64).read_volatile() }
+    }
+
+    fn write64(&self, reg: usize, value: u64) {
+        unsafe { ((self.regs as usize + reg) as *mut u64).write_volatile(value) }
+    }
+
+    /// Wait for CSTS.RDY to become `ready`
+    fn wait_ready(&self, ready: bool) -> Result<(), &'static str> {
+        for _ in 0..10_000_000 {
+            let csts = self.read32(REG_CSTS);
+            if csts & CSTS_CFS != 0 {
+                return Err("Controller fatal status");
+            }
+            if (csts & CSTS_RDY != 0) == ready {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+
+        Err("Controller timed out")
+    }
+
+    /// Reset the controller and set up the admin and I/O queues
+    fn new(regs: u64, interrupt: bool) -> Result<Self, &'static str> {
+        let cap = unsafe { (regs as *const u64).read_volatile() };
+
+        // CAP.MPSMIN: the minimum page size must allow 4 KiB pages
+        if (cap >> 48) & 0xF != 0 {
+            return Err("4 KiB pages are not supported");
+        }
+
+        // CAP.MQES is 0-based, CAP.DSTRD is the doorbell stride
+        let max_entries = ((cap & 0xFFFF) as u16).saturating_add(1);
+        let stride = 4 << ((cap >> 32) & 0xF);
+
+        let admin = QueuePair::new(0, max_entries, regs, stride).ok_or("Out of memory")?;
+        let io = QueuePair::new(1, max_entries, regs, stride).ok_or("Out of memory")?;
+
+        let (buffer, prp_list) = {
+            let mut alloc = get_frame_alloc_for_sure();
+            let buffer = alloc
+                .allocate_contiguous(BUFFER_FRAMES)
+                .ok_or("Out of memory")?
+                .start_address()
+                .as_u64();
+            let prp_list = alloc
+                .allocate_frame()
+                .ok_or("Out of memory")?
+                .start_address()
+                .as_u64();
+            (buffer, prp_list)
+        };
+
+        // the PRP list holds the pages of the buffer after the first one
+        let list = physical_to_virtual(prp_list) as *mut u64;
+        for i in 1..BUFFER_FRAMES {
+            unsafe { list.add(i - 1).write_volatile(buffer + i as u64 * FRAME_SIZE) };
+        }
+
+        let mut controller = Self {
+            regs,
+            admin,
+            io,
+            buffer,
+            prp_list,
+            max_transfer: BUFFER_SIZE,
+            interrupt,
+            model: Box::from(""),
+            serial: Box::from(""),
+        };
+
+        let version = controller.read32(REG_VS);
+        debug!(
+            "NVMe version {}.{}, CAP {:#x}",
+            version >> 16,
+            (version >> 8) & 0xFF,
+            controller.read64(REG_CAP)
+        );
+
+        // disable the controller before the admin queue is set up
+        let cc = controller.read32(REG_CC);
+        if cc & CC_EN != 0 {
+            controller.write32(REG_CC, cc & !CC_EN);
+        }
+        controller.wait_ready(false)?;
+
+        // AQA: admin completion and submission queue sizes, 0-based
+        let size = controller.admin.size() as u32 - 1;
+        controller.write32(REG_AQA, size << 16 | size);
+        controller.write64(REG_ASQ, controller.admin.sq_addr());
+        controller.write64(REG_ACQ, controller.admin.cq_addr());
+
+        controller.write32(REG_CC, CC_EN | CC_IOSQES | CC_IOCQES);
+        controller.wait_ready(true)?;
+
+        controller.identify_controller()?;
+        controller.create_io_queues()?;
+
+        Ok(controller)
+    }
+
+    /// Submit a command to the admin or the I/O queue and wait for it
+    ///
+    /// Returns the command specific result.
+    fn execute(&mut self, admin: bool, command: Command) -> Result<u32, &'static str> {
+        let interrupt = self.interrupt;
+        let queue = if admin { &mut self.admin } else { &mut self.io };
+
+        queue.submit(command);
+
+        WAIT_QUEUE.wait(interrupt, || queue.has_completion());
+
+        let completion = queue.pop_completion().ok_or("No completion")?;
+
+        match completion.status_code() {
+            0 => Ok(completion.result),
+            status => {
+                warn!(
+                    "NVMe command {:#x} failed with status {:#x}",
+                    command.opcode, status
+                );
+                Err("NVMe command failed")
+            }
+        }
+    }
+
+    /// IDENTIFY into the first page of the buffer
+    fn identify(&mut self, cns: IdentifyCns, nsid: u32) -> Result<&[u8], &'static str> {
+        self.execute(
+            true,
+            Command {
+                opcode: AdminCommand::Identify as u8,
+                nsid,
+                prp1: self.buffer,
+                cdw10: cns as u32,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(self.buffer(FRAME_SIZE as usize))
+    }
+
+    fn identify_controller(&mut self) -> Result<(), &'static str> {
+        let data = self.identify(IdentifyCns::Controller, 0)?;
+
+        let string = |bytes: &[u8]| -> Box<str> {
+            core::str::from_utf8(bytes)
+                .unwrap_or("")
+                .trim_end_matches('\0')
+                .trim()
+                .into()
+        };
+
+        let serial = string(&data[4..24]);
+        let model = string(&data[24..64]);
+
+        // MDTS: max data transfer size in units of the minimum page size,
+        // as a power of two, 0 means no limit
+        let mdts = data[77];
+
+        self.serial = serial;
+        self.model = model;
+        if mdts != 0 && mdts < 16 {
+            self.max_transfer = self.max_transfer.min((FRAME_SIZE as usize) << mdts);
+        }
+
+        info!("NVMe controller {} ({})", self.model, self.serial);
+
+        Ok(())
+    }
+
+    fn create_io_queues(&mut self) -> Result<(), &'static str> {
+        let qid = self.io.id() as u32;
+        let size = self.io.size() as u32 - 1;
+
+        // CDW11: interrupt vector 0, interrupts enabled, physically contiguous
+        let ien = if self.interrupt { 1 << 1 } else { 0 };
+        self.execute(
+            true,
+            Command {
+                opcode: AdminCommand::CreateIoCq as u8,
+                prp1: self.io.cq_addr(),
+                cdw10: size << 16 | qid,
+                cdw11: ien | 1,
+                ..Default::default()
+            },
+        )?;
+
+        // CDW11: the completion queue, physically contiguous
+        self.execute(
+            true,
+            Command {
+                opcode: AdminCommand::CreateIoSq as u8,
+                prp1: self.io.sq_addr(),
+                cdw10: size << 16 | qid,
+                cdw11: qid << 16 | 1,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn buffer(&self, len: usize) -> &[u8] {
+        let ptr = physical_to_virtual(self.buffer) as *const u8;
+        unsafe { core::slice::from_raw_parts(ptr, len.min(BUFFER_SIZE)) }
+    }
+
+    fn buffer_mut(&mut self, len: usize) -> &mut [u8] {
+        let ptr = physical_to_virtual(self.buffer) as *mut u8;
+        unsafe { core::slice::from_raw_parts_mut(ptr, len.min(BUFFER_SIZE)) }
+    }
+
+    /// Read or write `count` blocks of `lba_size` bytes with the buffer
+    fn transfer(
+        &mut self,
+        command: IoCommand,
+        nsid: u32,
+        lba: u64,
+        count: usize,
+        lba_size: usize,
+    ) -> Result<(), &'static str> {
+        let len = count * lba_size;
+        if count == 0 || len > self.max_transfer {
+            return Err("Invalid transfer size");
+        }
+
+        // the second PRP is the next page, or the list if there are more
+        let page = FRAME_SIZE as usize;
+        let prp2 = match len {
+            len if len <= page => 0,
+            len if len <= 2 * page => self.buffer + FRAME_SIZE,
+            _ => self.prp_list,
+        };
+
+        self.execute(
+            false,
+            Command {
+                opcode: command as u8,
+                nsid,
+                prp1: self.buffer,
+                prp2,
+                cdw10: lba as u32,
+                cdw11: (lba >> 32) as u32,
+                // number of blocks, 0-based
+                cdw12: count as u32 - 1,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+/// A namespace of a controller
+///
+/// It is accessed in 512-byte sectors whatever its block size is, so the
+/// partitions on it can be mounted like on any other disk.
+#[derive(Clone)]
+pub struct NvmeNamespace {
+    controller: Arc<DeviceLock<Controller>>,
+    nsid: u32,
+    lba_size: usize,
+    /// Size of the namespace in blocks of `lba_size`
+    lbas: u64,
+    /// Max bytes of a single transfer of the controller
+    max_transfer: usize,
+}
+
+impl NvmeNamespace {
+    /// Find the active namespaces of the controller
+    fn probe_all(controller: &Arc<DeviceLock<Controller>>) -> Result<Vec<Self>, &'static str> {
+        let mut namespaces = Vec::new();
+        let mut ctrl = controller.lock();
+
+        let ids: Vec<u32> = ctrl
+            .identify(IdentifyCns::ActiveNamespaces, 0)?
+            .chunks(4)
+            .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+            .take_while(|&id| id != 0)
+            .collect();
+
+        for nsid in ids {
+            let data = ctrl.identify(IdentifyCns::Namespace, nsid)?;
+
+            // NSZE: size in blocks
+            let lbas = u64::from_le_bytes(data[0..8].try_into().unwrap());
+
+            // FLBAS bits 0-3: the LBA format in use, LBADS is its block size
+            let format = (data[26] & 0xF) as usize;
+            let lbads = data[128 + format * 4 + 2] as u32;
+
+            // 512 B to 64 KiB in practice, larger ones would not fit in a shift
+            if !(LBADS_MIN..=LBADS_MAX).contains(&lbads) {
+                warn!("NVMe namespace {}: invalid block size 2^{}", nsid, lbads);
+                continue;
+            }
+
+            let lba_size = 1usize << lbads;
+            if !(SECTOR_SIZE..=ctrl.max_transfer).contains(&lba_size) {
+                warn!("NVMe namespace {}: block size {} not supported", nsid, lba_size);
+                continue;
+            }
+
+            namespaces.push(Self {
+                controller: controller.clone(),
+                nsid,
+                lba_size,
+                lbas,
+                max_transfer: ctrl.max_transfer,
+            });
+        }
+
+        Ok(namespaces)
+    }
+
+    /// Write the volatile write cache of the namespace to the medium,
+    /// which completes at once if the controller has none
+    pub fn flush(&self) -> Result<(), &'static str> {
+        self.controller.lock().execute(
+            false,
+            Command {
+                opcode: IoCommand::Flush as u8,
+                nsid: self.nsid,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+impl DmaDisk for NvmeNamespace {
+    type Device = Controller;
+
+    fn device(&self) -> &DeviceLock<Controller> {
+        &self.controller
+    }
+
+    fn block_size(&self) -> usize {
+        self.lba_size
+    }
+
+    fn blocks(&self) -> u64 {
+        self.lbas
+    }
+
+    fn max_transfer(&self) -> usize {
+        self.max_transfer
+    }
+
+    fn bounce_buffer(ctrl: &mut Controller, len: usize) -> &mut [u8] {
+        ctrl.buffer_mut(len)
+    }
+
+    fn transfer(
+        &self,
+        ctrl: &mut Controller,
+        lba: u64,
+        count: usize,
+        write: bool,
+    ) -> Result<(), &'static str> {
+        let command = if write {
+            IoCommand::Write
+        } else {
+            IoCommand::Read
+        };
+        ctrl.transfer(command, self.nsid, lba, count, self.lba_size)
+    }
+}
+
+impl BlockDevice<Block512> for NvmeNamespace {
+    fn block_count(&self) -> storage::FsResult<usize> {
+        dma::block_count(self)
+    }
+
+    fn read_block(&self, offset: usize, block: &mut Block512) -> storage::FsResult {
+        dma::read_block(self, offset, block)
+    }
+
+    fn write_block(&self, offset: usize, block: &Block512) -> storage::FsResult {
+        dma::write_block(self, offset, block)
+    }
+
+    fn read_blocks(&self, offset: usize, blocks: &mut [Block512]) -> storage::FsResult {
+        dma::read_blocks(self, offset, blocks)
+    }
+
+    fn write_blocks(&self, offset: usize, blocks: &[Block512]) -> storage::FsResult {
+        dma::write_blocks(self, offset, blocks)
+    }
+
+    fn flush(&self) -> storage::FsResult {
+        NvmeNamespace::flush(self).map_err(|_| storage::DeviceError::WriteError.into())
+    }
+}
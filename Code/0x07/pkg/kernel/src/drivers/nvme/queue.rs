@@ -0,0 +1,166 @@
+//! NVMe submission and completion queues
+//!
+//! Each queue of a pair lives in a frame of its own, which limits the
+//! queues to 64 entries, more than enough for one command at a time.
+
+use crate::memory::{get_frame_alloc_for_sure, physical_to_virtual, FRAME_SIZE};
+use x86_64::structures::paging::FrameAllocator;
+
+/// Entries of each queue, bounded by the frame size and the controller
+pub(super) const QUEUE_SIZE: u16 = (FRAME_SIZE / 64) as u16;
+
+/// Submission queue entry
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct Command {
+    pub opcode: u8,
+    pub flags: u8,
+    /// Set by the queue when submitted
+    pub cid: u16,
+    pub nsid: u32,
+    pub reserved: u64,
+    pub mptr: u64,
+    pub prp1: u64,
+    pub prp2: u64,
+    pub cdw10: u32,
+    pub cdw11: u32,
+    pub cdw12: u32,
+    pub cdw13: u32,
+    pub cdw14: u32,
+    pub cdw15: u32,
+}
+
+/// Completion queue entry
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Completion {
+    /// Command specific result
+    pub result: u32,
+    pub reserved: u32,
+    pub sq_head: u16,
+    pub sq_id: u16,
+    pub cid: u16,
+    /// Bit 0 is the phase tag, bits 1-15 are the status field
+    pub status: u16,
+}
+
+impl Completion {
+    /// Status code type and status code, 0 on success
+    pub fn status_code(&self) -> u16 {
+        (self.status >> 1) & 0x7FF
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct QueuePair {
+    id: u16,
+    size: u16,
+    /// Physical address of the submission queue
+    sq: u64,
+    /// Physical address of the completion queue
+    cq: u64,
+    sq_tail: u16,
+    cq_head: u16,
+    /// The phase tag of new completions, flipped each time the queue wraps
+    phase: bool,
+    /// Virtual addresses of the doorbell registers
+    sq_doorbell: u64,
+    cq_doorbell: u64,
+    next_cid: u16,
+}
+
+impl QueuePair {
+    /// Allocate queue pair `id` of at most `max_size` entries, the doorbells
+    /// are found from the registers at `regs` and the doorbell stride.
+    pub fn new(id: u16, max_size: u16, regs: u64, stride: u64) -> Option<Self> {
+        let (sq, cq) = {
+            let mut alloc = get_frame_alloc_for_sure();
+            let sq = alloc.allocate_frame()?.start_address().as_u64();
+            let cq = alloc.allocate_frame()?.start_address().as_u64();
+            (sq, cq)
+        };
+
+        // the phase tags of the completion queue must start cleared
+        unsafe {
+            core::ptr::write_bytes(physical_to_virtual(cq) as *mut u8, 0, FRAME_SIZE as usize);
+        }
+
+        let doorbell = regs + 0x1000 + 2 * id as u64 * stride;
+
+        Some(Self {
+            id,
+            size: max_size.min(QUEUE_SIZE),
+            sq,
+            cq,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: true,
+            sq_doorbell: doorbell,
+            cq_doorbell: doorbell + stride,
+            next_cid: 0,
+        })
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn sq_addr(&self) -> u64 {
+        self.sq
+    }
+
+    pub fn cq_addr(&self) -> u64 {
+        self.cq
+    }
+
+    /// Place the command at the tail and ring the doorbell
+    ///
+    /// Returns the command id.
+    pub fn submit(&mut self, mut command: Command) -> u16 {
+        command.cid = self.next_cid;
+        self.next_cid = self.next_cid.wrapping_add(1);
+
+        let entry = physical_to_virtual(self.sq) as *mut Command;
+        unsafe {
+            entry.add(self.sq_tail as usize).write_volatile(command);
+        }
+
+        self.sq_tail = (self.sq_tail + 1) % self.size;
+        unsafe { (self.sq_doorbell as *mut u32).write_volatile(self.sq_tail as u32) };
+
+        command.cid
+    }
+
+    fn head(&self) -> Completion {
+        let entry = physical_to_virtual(self.cq) as *const Completion;
+        unsafe { entry.add(self.cq_head as usize).read_volatile() }
+    }
+
+    /// Returns true if a new completion is at the head
+    pub fn has_completion(&self) -> bool {
+        (self.head().status & 1 != 0) == self.phase
+    }
+
+    /// Take the completion at the head and tell the controller
+    pub fn pop_completion(&mut self) -> Option<Completion> {
+        if !self.has_completion() {
+            return None;
+        }
+
+        let completion = self.head();
+
+        self.cq_head += 1;
+        if self.cq_head == self.size {
+            self.cq_head = 0;
+            self.phase = !self.phase;
+        }
+
+        unsafe { (self.cq_doorbell as *mut u32).write_volatile(self.cq_head as u32) };
+
+        Some(completion)
+    }
+}
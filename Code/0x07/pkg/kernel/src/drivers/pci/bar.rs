@@ -0,0 +1,141 @@
+//! Base Address Registers
+//!
+//! reference: https://wiki.osdev.org/PCI#Base_Address_Registers
+
+use super::{PciAddress, PciCommand};
+
+/// A decoded base address register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    /// I/O space, addressed by ports
+    Io { port: u16, size: u32 },
+    /// Memory space, mapped into the physical address space
+    Memory {
+        addr: u64,
+        size: u64,
+        prefetchable: bool,
+        is_64bit: bool,
+    },
+}
+
+impl Bar {
+    /// Port base of an I/O BAR
+    pub fn io_port(&self) -> Option<u16> {
+        match *self {
+            Bar::Io { port, .. } => Some(port),
+            Bar::Memory { .. } => None,
+        }
+    }
+
+    /// Physical address and size of a memory BAR
+    pub fn memory(&self) -> Option<(u64, u64)> {
+        match *self {
+            Bar::Memory { addr, size, .. } => Some((addr, size)),
+            Bar::Io { .. } => None,
+        }
+    }
+}
+
+impl core::fmt::Display for Bar {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            Bar::Io { port, size } => write!(f, "I/O ports at {:#x} [size={}]", port, size),
+            Bar::Memory {
+                addr,
+                size,
+                prefetchable,
+                is_64bit,
+            } => {
+                let (size, unit) = crate::humanized_size_short(size);
+                write!(
+                    f,
+                    "Memory at {:#x} ({}-bit, {}) [size={}{}]",
+                    addr,
+                    if is_64bit { 64 } else { 32 },
+                    if prefetchable {
+                        "prefetchable"
+                    } else {
+                        "non-prefetchable"
+                    },
+                    size,
+                    unit
+                )
+            }
+        }
+    }
+}
+
+/// Decode the first `count` BARs of a function
+///
+/// The size of a BAR is found by writing all ones to it and reading back
+/// the bits that stick, with decoding disabled in the command register
+/// so the device does not respond at a bogus address meanwhile.
+/// Host bridges are left alone, as they may stop decoding the main memory.
+pub(super) fn decode_bars(addr: PciAddress, count: u8) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+
+    let (class, subclass, _) = addr.class();
+    let command = addr.command();
+    if (class, subclass) != (0x06, 0x00) {
+        addr.set_command(command - (PciCommand::IO_SPACE | PciCommand::MEMORY_SPACE));
+    }
+
+    let mut index = 0;
+    while index < count {
+        let offset = 0x10 + index * 4;
+        let value = addr.read(offset);
+
+        addr.write(offset, u32::MAX);
+        let mask = addr.read(offset);
+        addr.write(offset, value);
+
+        if value & 0x1 != 0 {
+            // I/O space, bit 1 is reserved
+            let size = !(mask & !0x3) & 0xFFFF;
+            if mask != 0 {
+                bars[index as usize] = Some(Bar::Io {
+                    port: (value & 0xFFFC) as u16,
+                    size: size + 1,
+                });
+            }
+            index += 1;
+            continue;
+        }
+
+        // memory space, bits 1-2 are the type and bit 3 is prefetchable
+        let is_64bit = (value >> 1) & 0x3 == 0x2;
+        let prefetchable = value & 0x8 != 0;
+
+        let (base, mask) = if is_64bit && index + 1 < count {
+            let high_offset = offset + 4;
+            let high = addr.read(high_offset);
+
+            addr.write(high_offset, u32::MAX);
+            let high_mask = addr.read(high_offset);
+            addr.write(high_offset, high);
+
+            (
+                (high as u64) << 32 | (value & !0xF) as u64,
+                (high_mask as u64) << 32 | (mask & !0xF) as u64,
+            )
+        } else {
+            ((value & !0xF) as u64, (mask & !0xF) as u64 | 0xFFFF_FFFF_0000_0000)
+        };
+
+        if mask & 0xFFFF_FFFF != 0 {
+            bars[index as usize] = Some(Bar::Memory {
+                addr: base,
+                size: !mask + 1,
+                prefetchable,
+                is_64bit,
+            });
+        }
+
+        // a 64-bit BAR takes the next register as its upper half
+        index += if is_64bit { 2 } else { 1 };
+    }
+
+    addr.set_command(command);
+
+    bars
+}
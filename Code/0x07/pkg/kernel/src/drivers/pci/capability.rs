@@ -0,0 +1,196 @@
+//! Capabilities, MSI and MSI-X
+//!
+//! reference: https://wiki.osdev.org/PCI#IRQ_Handling
+//! reference: https://wiki.osdev.org/PCI#Enabling_MSI
+
+use super::{Bar, PciAddress};
+use crate::memory::physical_to_virtual;
+use alloc::vec::Vec;
+
+pub const CAP_POWER_MANAGEMENT: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR_SPECIFIC: u8 = 0x09;
+pub const CAP_PCI_EXPRESS: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+/// Base of the message address, which targets the local APICs
+const MSI_ADDRESS_BASE: u64 = 0xFEE0_0000;
+
+/// An entry in the capability list of a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset in the configuration space
+    pub offset: u8,
+}
+
+/// Walk the capability list, bit 4 of the status register tells if it exists
+pub(super) fn read_capabilities(addr: PciAddress) -> Vec<Capability> {
+    let mut caps = Vec::new();
+
+    if addr.read_u16(0x06) & (1 << 4) == 0 {
+        return caps;
+    }
+
+    let mut offset = addr.read(0x34) as u8 & 0xFC;
+
+    // the list lives in the 192 bytes after the header, so a broken
+    // list which loops is cut off here
+    while offset != 0 && caps.len() < 48 {
+        let value = addr.read(offset);
+        caps.push(Capability {
+            id: value as u8,
+            offset,
+        });
+        offset = (value >> 8) as u8 & 0xFC;
+    }
+
+    caps
+}
+
+fn message_address(apic_id: u8) -> u64 {
+    MSI_ADDRESS_BASE | (apic_id as u64) << 12
+}
+
+/// Message Signaled Interrupts
+#[derive(Debug, Clone, Copy)]
+pub struct Msi {
+    addr: PciAddress,
+    offset: u8,
+}
+
+impl Msi {
+    pub(super) fn new(addr: PciAddress, offset: u8) -> Self {
+        Self { addr, offset }
+    }
+
+    fn control(&self) -> u16 {
+        self.addr.read_u16(self.offset + 2)
+    }
+
+    fn set_control(&self, control: u16) {
+        self.addr.write_u16(self.offset + 2, control);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.control() & 0x1 != 0
+    }
+
+    /// The function takes a 64-bit message address
+    pub fn is_64bit(&self) -> bool {
+        self.control() & (1 << 7) != 0
+    }
+
+    pub fn per_vector_masking(&self) -> bool {
+        self.control() & (1 << 8) != 0
+    }
+
+    /// Number of vectors the function requests
+    pub fn max_vectors(&self) -> usize {
+        1 << ((self.control() >> 1) & 0x7)
+    }
+
+    /// Deliver the interrupt of the function as `vector` to the given local APIC,
+    /// with a single message enabled.
+    pub fn enable(&self, apic_id: u8, vector: u8) {
+        let address = message_address(apic_id);
+
+        self.addr.write(self.offset + 4, address as u32);
+        let data_offset = if self.is_64bit() {
+            self.addr.write(self.offset + 8, (address >> 32) as u32);
+            self.offset + 12
+        } else {
+            self.offset + 8
+        };
+        self.addr.write_u16(data_offset, vector as u16);
+
+        // bits 4-6: multiple message enable, 0 for a single vector
+        self.set_control((self.control() & !0x70) | 0x1);
+    }
+
+    pub fn disable(&self) {
+        self.set_control(self.control() & !0x1);
+    }
+}
+
+/// Extended Message Signaled Interrupts
+///
+/// The message table and the pending bit array live in the memory of a BAR.
+#[derive(Debug, Clone, Copy)]
+pub struct MsiX {
+    addr: PciAddress,
+    offset: u8,
+}
+
+impl MsiX {
+    /// Size of an entry in the message table
+    const ENTRY_SIZE: u64 = 16;
+
+    pub(super) fn new(addr: PciAddress, offset: u8) -> Self {
+        Self { addr, offset }
+    }
+
+    fn control(&self) -> u16 {
+        self.addr.read_u16(self.offset + 2)
+    }
+
+    fn set_control(&self, control: u16) {
+        self.addr.write_u16(self.offset + 2, control);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.control() & (1 << 15) != 0
+    }
+
+    /// Number of entries in the message table
+    pub fn table_size(&self) -> usize {
+        (self.control() & 0x7FF) as usize + 1
+    }
+
+    /// Returns (BAR index, offset in the BAR) of the message table
+    pub fn table(&self) -> (u8, u32) {
+        let value = self.addr.read(self.offset + 4);
+        ((value & 0x7) as u8, value & !0x7)
+    }
+
+    /// Returns (BAR index, offset in the BAR) of the pending bit array
+    pub fn pending_bit_array(&self) -> (u8, u32) {
+        let value = self.addr.read(self.offset + 8);
+        ((value & 0x7) as u8, value & !0x7)
+    }
+
+    /// Program entry `index` of the message table to deliver `vector`
+    /// to the given local APIC, `bar` is the BAR holding the table.
+    ///
+    /// The table must be below the end of the mapped physical memory.
+    pub fn set_entry(&self, bar: &Bar, index: usize, apic_id: u8, vector: u8) -> Option<()> {
+        if index >= self.table_size() {
+            return None;
+        }
+
+        let (base, _) = bar.memory()?;
+        let (_, offset) = self.table();
+        let entry = base + offset as u64 + index as u64 * Self::ENTRY_SIZE;
+        let entry = physical_to_virtual(entry) as *mut u32;
+        let address = message_address(apic_id);
+
+        unsafe {
+            entry.write_volatile(address as u32);
+            entry.add(1).write_volatile((address >> 32) as u32);
+            entry.add(2).write_volatile(vector as u32);
+            // vector control, bit 0 masks the entry
+            entry.add(3).write_volatile(0);
+        }
+
+        Some(())
+    }
+
+    pub fn enable(&self) {
+        // bit 14 is the function mask
+        self.set_control((self.control() & !(1 << 14)) | (1 << 15));
+    }
+
+    pub fn disable(&self) {
+        self.set_control(self.control() & !(1 << 15));
+    }
+}
@@ -0,0 +1,78 @@
+//! PCI drivers
+//!
+//! A driver lists the functions it supports by vendor/device or class,
+//! and is bound to every matching function which is not bound yet.
+
+use super::{devices, PciAddress, PciDevice};
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+
+/// Drivers bound to functions
+static BINDINGS: Mutex<BTreeMap<PciAddress, &'static str>> = Mutex::new(BTreeMap::new());
+
+/// How a driver matches a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciMatch {
+    /// (vendor id, device id)
+    Id(u16, u16),
+    /// (class, subclass)
+    Class(u8, u8),
+    /// (class, subclass, prog_if)
+    ClassProgIf(u8, u8, u8),
+}
+
+impl PciMatch {
+    pub fn matches(&self, dev: &PciDevice) -> bool {
+        match *self {
+            PciMatch::Id(vendor, device) => (dev.vendor_id, dev.device_id) == (vendor, device),
+            PciMatch::Class(class, subclass) => (dev.class, dev.subclass) == (class, subclass),
+            PciMatch::ClassProgIf(class, subclass, prog_if) => {
+                (dev.class, dev.subclass, dev.prog_if) == (class, subclass, prog_if)
+            }
+        }
+    }
+}
+
+pub struct PciDriver {
+    pub name: &'static str,
+    pub matches: &'static [PciMatch],
+    /// Set up the function, returns false if the driver cannot handle it
+    pub probe: fn(&PciDevice) -> bool,
+}
+
+/// Bind the driver to all matching functions which are not bound yet
+///
+/// Returns the number of functions bound.
+pub fn register_driver(driver: &'static PciDriver) -> usize {
+    let mut count = 0;
+
+    for dev in devices() {
+        if !driver.matches.iter().any(|m| m.matches(dev)) {
+            continue;
+        }
+
+        if BINDINGS.lock().contains_key(&dev.addr) {
+            continue;
+        }
+
+        // the probe may take a while, so the bindings are not locked here
+        if (driver.probe)(dev) {
+            info!("PCI {} bound to driver {}", dev.addr, driver.name);
+            BINDINGS.lock().insert(dev.addr, driver.name);
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// Mark a function as used by a driver which does not go through
+/// `register_driver`, so it is not handed to another one.
+pub fn claim(addr: PciAddress, name: &'static str) -> bool {
+    BINDINGS.lock().try_insert(addr, name).is_ok()
+}
+
+/// Name of the driver bound to the function
+pub fn bound_driver(addr: PciAddress) -> Option<&'static str> {
+    BINDINGS.lock().get(&addr).copied()
+}
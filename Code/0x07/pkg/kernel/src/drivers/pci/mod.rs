@@ -0,0 +1,340 @@
+//! PCI bus
+//!
+//! Enumerates the functions on the PCI buses through the configuration space
+//! access mechanism #1, decodes their BARs and capabilities, and binds them
+//! to the registered drivers.
+//!
+//! reference: https://wiki.osdev.org/PCI
+//! reference: https://wiki.osdev.org/PCI#Configuration_Space_Access_Mechanism_.231
+
+mod bar;
+mod capability;
+mod driver;
+
+pub use bar::*;
+pub use capability::*;
+pub use driver::*;
+
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// The address and data ports must be used as a pair
+static CONFIG_PORTS: Mutex<(Port<u32>, Port<u32>)> =
+    Mutex::new((Port::new(CONFIG_ADDRESS), Port::new(CONFIG_DATA)));
+
+/// The functions found on the PCI buses, enumerated on first use
+static DEVICES: spin::Once<Vec<PciDevice>> = spin::Once::new();
+
+bitflags! {
+    /// PCI command register
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct PciCommand: u16 {
+        const IO_SPACE          = 1 << 0;
+        const MEMORY_SPACE      = 1 << 1;
+        const BUS_MASTER        = 1 << 2;
+        const INTERRUPT_DISABLE = 1 << 10;
+    }
+}
+
+/// Location of a PCI function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(&self, offset: u8) -> u32 {
+        (1 << 31)
+            | (self.bus as u32) << 16
+            | (self.device as u32 & 0x1F) << 11
+            | (self.function as u32 & 0x07) << 8
+            | (offset as u32 & 0xFC)
+    }
+
+    /// Reads a dword from the configuration space, `offset` is dword aligned
+    pub fn read(&self, offset: u8) -> u32 {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut ports = CONFIG_PORTS.lock();
+            unsafe {
+                ports.0.write(self.config_address(offset));
+                ports.1.read()
+            }
+        })
+    }
+
+    /// Writes a dword to the configuration space, `offset` is dword aligned
+    pub fn write(&self, offset: u8, value: u32) {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut ports = CONFIG_PORTS.lock();
+            unsafe {
+                ports.0.write(self.config_address(offset));
+                ports.1.write(value);
+            }
+        })
+    }
+
+    /// Reads a word from the configuration space, `offset` is word aligned
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read(offset) >> ((offset & 0x2) * 8)) as u16
+    }
+
+    /// Writes a word to the configuration space, `offset` is word aligned
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 0x2) * 8;
+        let dword = self.read(offset) & !(0xFFFF << shift);
+        self.write(offset, dword | (value as u32) << shift);
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read(0x00) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read(0x00) >> 16) as u16
+    }
+
+    /// Returns (class, subclass, prog_if)
+    pub fn class(&self) -> (u8, u8, u8) {
+        let value = self.read(0x08);
+        ((value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8)
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.read(0x08) as u8
+    }
+
+    pub fn header_type(&self) -> u8 {
+        (self.read(0x0C) >> 16) as u8
+    }
+
+    /// Reads the raw value of the base address register `index` (0..6)
+    pub fn bar(&self, index: u8) -> u32 {
+        self.read(0x10 + index * 4)
+    }
+
+    pub fn command(&self) -> PciCommand {
+        PciCommand::from_bits_retain(self.read_u16(0x04))
+    }
+
+    pub fn set_command(&self, command: PciCommand) {
+        self.write_u16(0x04, command.bits());
+    }
+
+    /// Enables I/O space, memory space and bus mastering in the command register
+    pub fn enable_bus_master(&self) {
+        self.set_command(
+            self.command()
+                | PciCommand::IO_SPACE
+                | PciCommand::MEMORY_SPACE
+                | PciCommand::BUS_MASTER,
+        );
+    }
+
+    /// Returns (interrupt line, interrupt pin), the pin is 0 if not used
+    pub fn interrupt(&self) -> (u8, u8) {
+        let value = self.read(0x3C);
+        (value as u8, (value >> 8) as u8)
+    }
+
+    /// Returns true if a function is present at this address
+    pub fn exists(&self) -> bool {
+        self.vendor_id() != 0xFFFF
+    }
+}
+
+impl core::fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A function found on the PCI buses
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub addr: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    pub interrupt_line: u8,
+    pub interrupt_pin: u8,
+    /// Only general devices (header type 0) have all six BARs
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+}
+
+impl PciDevice {
+    fn probe(addr: PciAddress) -> Self {
+        let (class, subclass, prog_if) = addr.class();
+        let (interrupt_line, interrupt_pin) = addr.interrupt();
+        let header_type = addr.header_type() & 0x7F;
+
+        let bar_count = match header_type {
+            0x00 => 6,
+            0x01 => 2,
+            _ => 0,
+        };
+
+        Self {
+            addr,
+            vendor_id: addr.vendor_id(),
+            device_id: addr.device_id(),
+            class,
+            subclass,
+            prog_if,
+            revision: addr.revision(),
+            header_type,
+            interrupt_line,
+            interrupt_pin,
+            bars: bar::decode_bars(addr, bar_count),
+            capabilities: capability::read_capabilities(addr),
+        }
+    }
+
+    /// Find the capability with the given id
+    pub fn capability(&self, id: u8) -> Option<&Capability> {
+        self.capabilities.iter().find(|cap| cap.id == id)
+    }
+
+    /// MSI of the function, if it is capable
+    pub fn msi(&self) -> Option<Msi> {
+        self.capability(CAP_MSI).map(|cap| Msi::new(self.addr, cap.offset))
+    }
+
+    /// MSI-X of the function, if it is capable
+    pub fn msix(&self) -> Option<MsiX> {
+        self.capability(CAP_MSIX).map(|cap| MsiX::new(self.addr, cap.offset))
+    }
+}
+
+/// Enumerate all functions by brute force, which is simple and fast enough
+fn scan() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+
+    for bus in 0..=255u8 {
+        for device in 0..32 {
+            let addr = PciAddress::new(bus, device, 0);
+            if !addr.exists() {
+                continue;
+            }
+
+            let functions = if addr.header_type() & 0x80 != 0 { 8 } else { 1 };
+
+            for function in 0..functions {
+                let addr = PciAddress::new(bus, device, function);
+                if addr.exists() {
+                    devices.push(PciDevice::probe(addr));
+                }
+            }
+        }
+    }
+
+    devices
+}
+
+pub fn init() {
+    for dev in devices() {
+        debug!(
+            "PCI {} [{:04x}:{:04x}] {}",
+            dev.addr,
+            dev.vendor_id,
+            dev.device_id,
+            class_name(dev.class, dev.subclass)
+        );
+    }
+
+    info!("Initialized PCI Bus, {} functions found.", devices().len());
+}
+
+/// All functions found on the PCI buses
+pub fn devices() -> &'static [PciDevice] {
+    DEVICES.call_once(scan)
+}
+
+/// Finds the first function of the given class and subclass
+pub fn find_class(class: u8, subclass: u8) -> Option<PciAddress> {
+    devices()
+        .iter()
+        .find(|dev| (dev.class, dev.subclass) == (class, subclass))
+        .map(|dev| dev.addr)
+}
+
+/// Human readable name of a class code
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVM controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        _ => "Unclassified device",
+    }
+}
+
+/// Print the functions found, like `lspci`
+pub fn list() {
+    println!(
+        "{:<8} {:<10} {:<26} {:<10} {}",
+        "Address", "ID", "Class", "Driver", "IRQ/MSI"
+    );
+
+    for dev in devices() {
+        let id = format!("{:04x}:{:04x}", dev.vendor_id, dev.device_id);
+        let driver = driver::bound_driver(dev.addr).unwrap_or("-");
+
+        let irq = match (dev.interrupt_pin, dev.msi(), dev.msix()) {
+            (_, _, Some(msix)) => format!("MSI-X x{}", msix.table_size()),
+            (_, Some(msi), None) => format!("MSI x{}", msi.max_vectors()),
+            (0, None, None) => "-".to_string(),
+            (_, None, None) => format!("IRQ {}", dev.interrupt_line),
+        };
+
+        println!(
+            "{:<8} {:<10} {:<26} {:<10} {}",
+            dev.addr,
+            id,
+            class_name(dev.class, dev.subclass),
+            driver,
+            irq
+        );
+
+        for (i, bar) in dev.bars.iter().enumerate() {
+            if let Some(bar) = bar {
+                println!("         BAR{}: {}", i, bar);
+            }
+        }
+    }
+}
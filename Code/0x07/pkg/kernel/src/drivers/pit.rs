@@ -0,0 +1,53 @@
+//! Programmable Interval Timer
+//!
+//! Only used as a reference of time to calibrate the LAPIC timer and the
+//! TSC at boot. Channel 2 counts down once, its gate and output are on
+//! port 0x61, so it is polled without any interrupt.
+//!
+//! reference: https://wiki.osdev.org/Programmable_Interval_Timer
+
+use x86_64::instructions::port::Port;
+
+/// Frequency of the oscillator of the PIT
+pub const PIT_HZ: u64 = 1_193_182;
+
+const CHANNEL2_DATA: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Gate and output of channel 2, shared with the PC speaker
+const CONTROL: u16 = 0x61;
+
+/// Channel 2, low byte then high byte, mode 0, binary
+const CHANNEL2_ONESHOT: u8 = 0b1011_0000;
+
+const CONTROL_GATE2: u8 = 1 << 0;
+const CONTROL_SPEAKER: u8 = 1 << 1;
+const CONTROL_OUT2: u8 = 1 << 5;
+
+/// The longest a countdown can last, in microseconds
+pub const MAX_WAIT_US: u64 = 0xFFFF * 1_000_000 / PIT_HZ;
+
+/// Busy wait for `us` microseconds, up to `MAX_WAIT_US`
+pub fn wait_us(us: u64) {
+    let count = (us.min(MAX_WAIT_US) * PIT_HZ / 1_000_000).max(1) as u16;
+
+    let mut control = Port::<u8>::new(CONTROL);
+    let mut data = Port::<u8>::new(CHANNEL2_DATA);
+
+    unsafe {
+        // gate low while programming, and keep the speaker quiet
+        let value = control.read() & !(CONTROL_GATE2 | CONTROL_SPEAKER);
+        control.write(value);
+
+        Port::<u8>::new(COMMAND).write(CHANNEL2_ONESHOT);
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+
+        // the countdown starts with the gate high, the output goes high at 0
+        control.write(value | CONTROL_GATE2);
+        while control.read() & CONTROL_OUT2 == 0 {
+            core::hint::spin_loop();
+        }
+
+        control.write(value);
+    }
+}
@@ -0,0 +1,236 @@
+//! Entropy pool
+//!
+//! The random bytes come from a ChaCha20 keystream. Its key is the state of
+//! the pool: the entropy gathered is folded into it before every request,
+//! and it is replaced by more keystream after every request, so the bytes
+//! given out cannot be found again from the state.
+//!
+//! The entropy comes from RDSEED and RDRAND when the CPU has them, and from
+//! the TSC at every interrupt, whose low bits jitter.
+//!
+//! reference: https://datatracker.ietf.org/doc/html/rfc8439#section-2.3
+//! reference: https://blog.cr.yp.to/20170723-random.html
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86::cpuid::CpuId;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::random::RdRand;
+
+/// "expand 32-byte k"
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+const BLOCK_SIZE: usize = 64;
+
+/// Bytes given out between two rekeys
+const MAX_REQUEST: usize = 256;
+
+/// Words of entropy gathered before they are folded into the key
+const INPUT_WORDS: usize = 16;
+
+static POOL: Mutex<EntropyPool> = Mutex::new(EntropyPool::new());
+
+/// TSC of the interrupts, mixed without taking the lock of the pool
+static JITTER: AtomicU64 = AtomicU64::new(0);
+
+struct EntropyPool {
+    key: [u32; 8],
+    input: [u32; INPUT_WORDS],
+    /// Next word of `input` to mix into
+    position: usize,
+    /// The input was mixed into since the last reseed
+    dirty: bool,
+}
+
+impl EntropyPool {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            input: [0; INPUT_WORDS],
+            position: 0,
+            dirty: false,
+        }
+    }
+
+    fn mix(&mut self, sample: u64) {
+        for word in [sample as u32, (sample >> 32) as u32] {
+            let slot = &mut self.input[self.position];
+            *slot = slot.rotate_left(7) ^ word;
+            self.position = (self.position + 1) % INPUT_WORDS;
+        }
+        self.dirty = true;
+    }
+
+    /// Fold the input into the key
+    fn reseed(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        let mut key = self.key;
+        for (key, input) in key.iter_mut().zip(&self.input[..8]) {
+            *key ^= input;
+        }
+        let mut nonce = [0; 4];
+        for (i, word) in self.input[8..].iter().enumerate() {
+            nonce[i % 4] ^= word;
+        }
+
+        let block = chacha20_block(&key, &nonce);
+        self.key.copy_from_slice(&block[..8]);
+        self.input = [0; INPUT_WORDS];
+        self.dirty = false;
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        self.mix(JITTER.load(Ordering::Relaxed));
+        self.mix(rdtsc());
+        self.reseed();
+
+        let mut nonce = [0u32; 4];
+        for chunk in buf.chunks_mut(BLOCK_SIZE) {
+            // the first block is the next key
+            nonce[0] += 1;
+            let block = chacha20_block(&self.key, &nonce);
+            for (byte, value) in chunk.iter_mut().zip(block.iter().flat_map(|w| w.to_le_bytes())) {
+                *byte = value;
+            }
+        }
+
+        let block = chacha20_block(&self.key, &[0; 4]);
+        self.key.copy_from_slice(&block[..8]);
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// A block of the keystream, the counter and the nonce make up `nonce`
+fn chacha20_block(key: &[u32; 8], nonce: &[u32; 4]) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12..].copy_from_slice(nonce);
+
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    for (word, input) in state.iter_mut().zip(input) {
+        *word = word.wrapping_add(input);
+    }
+    state
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+fn has_rdseed() -> bool {
+    CpuId::new()
+        .get_extended_feature_info()
+        .is_some_and(|info| info.has_rdseed())
+}
+
+/// Only call it if `has_rdseed`
+unsafe fn rdseed() -> Option<u64> {
+    let mut value = 0;
+    // fails when the entropy of the CPU runs out, retry a few times
+    for _ in 0..10 {
+        if unsafe { x86::random::rdseed64(&mut value) } {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Mix a sample from the hardware generators, if any
+fn mix_hardware(pool: &mut EntropyPool) -> bool {
+    let mut mixed = false;
+
+    let seed = if has_rdseed() { unsafe { rdseed() } } else { None };
+    if let Some(seed) = seed {
+        pool.mix(seed);
+        mixed = true;
+    }
+    if let Some(value) = RdRand::new().and_then(|rdrand| rdrand.get_u64()) {
+        pool.mix(value);
+        mixed = true;
+    }
+
+    mixed
+}
+
+/// Seed the pool with what is there at boot
+pub fn init() {
+    let sources = interrupts::without_interrupts(|| {
+        let mut pool = POOL.lock();
+
+        let hardware = (0..4).fold(false, |mixed, _| mix_hardware(&mut pool) | mixed);
+        pool.mix(crate::utils::time::realtime().as_secs());
+        pool.mix(rdtsc());
+        pool.reseed();
+
+        hardware
+    });
+
+    if sources {
+        info!("Entropy pool seeded from the CPU generators and the TSC");
+    } else {
+        warn!("No RDSEED or RDRAND, the entropy pool relies on interrupt timing");
+    }
+}
+
+/// Mix the time of an interrupt, called by every hardware interrupt
+#[inline]
+pub fn add_interrupt_timing() {
+    let jitter = JITTER.load(Ordering::Relaxed);
+    JITTER.store(jitter.rotate_left(5) ^ rdtsc(), Ordering::Relaxed);
+}
+
+/// Mix bytes from elsewhere, they can only add to the entropy
+pub fn add_bytes(bytes: &[u8]) {
+    interrupts::without_interrupts(|| {
+        let mut pool = POOL.lock();
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            pool.mix(u64::from_le_bytes(word));
+        }
+    });
+}
+
+/// Fill `buf` with random bytes
+pub fn fill(buf: &mut [u8]) {
+    // a chunk at a time, not to keep the interrupts off for long
+    for chunk in buf.chunks_mut(MAX_REQUEST) {
+        interrupts::without_interrupts(|| {
+            let mut pool = POOL.lock();
+            mix_hardware(&mut pool);
+            pool.fill(chunk);
+        });
+    }
+}
+
+/// Whether `path` is the random device
+pub fn is_device(path: &str) -> bool {
+    matches!(
+        path.strip_prefix(crate::drivers::tty::DEV_DIR),
+        Some("random" | "urandom")
+    )
+}
@@ -0,0 +1,196 @@
+//! CMOS Real-Time Clock
+//!
+//! The date and time are read from the CMOS registers, in BCD or binary
+//! and with a 12 or 24 hour clock as status register B says. The clock is
+//! taken as UTC. The update-ended interrupt fires once a second, right
+//! after the RTC has updated its registers, and keeps the wall clock in
+//! step.
+//!
+//! reference: https://wiki.osdev.org/CMOS
+//! reference: https://wiki.osdev.org/RTC
+
+use chrono::{DateTime, NaiveDate, Utc};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Set in the register index, keeps the NMI disabled meanwhile
+const NMI_DISABLE: u8 = 0x80;
+/// Selected once an access is done, with the NMI enabled again
+const REG_STATUS_D: u8 = 0x0D;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    Seconds = 0x00,
+    Minutes = 0x02,
+    Hours = 0x04,
+    Day = 0x07,
+    Month = 0x08,
+    Year = 0x09,
+    StatusA = 0x0A,
+    StatusB = 0x0B,
+    StatusC = 0x0C,
+    /// Not standard, but where every PC since the ACPI era keeps it
+    Century = 0x32,
+}
+
+/// Status register A, the registers are being updated
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+/// Bit 7 of the hours in 12 hour mode
+const HOUR_PM: u8 = 1 << 7;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct StatusB: u8 {
+        const HOUR_24 = 1 << 1;
+        const BINARY = 1 << 2;
+        const UPDATE_ENDED_INTERRUPT = 1 << 4;
+        const ALARM_INTERRUPT = 1 << 5;
+        const PERIODIC_INTERRUPT = 1 << 6;
+    }
+}
+
+bitflags! {
+    /// The interrupts pending, cleared when read
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct StatusC: u8 {
+        const UPDATE_ENDED = 1 << 4;
+        const ALARM = 1 << 5;
+        const PERIODIC = 1 << 6;
+    }
+}
+
+/// The registers as read, before decoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+fn read_register(reg: Register) -> u8 {
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(NMI_DISABLE | reg as u8);
+        let value = Port::<u8>::new(CMOS_DATA).read();
+        Port::<u8>::new(CMOS_ADDRESS).write(REG_STATUS_D);
+        value
+    }
+}
+
+fn write_register(reg: Register, value: u8) {
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(NMI_DISABLE | reg as u8);
+        Port::<u8>::new(CMOS_DATA).write(value);
+        Port::<u8>::new(CMOS_ADDRESS).write(REG_STATUS_D);
+    }
+}
+
+fn status_b() -> StatusB {
+    StatusB::from_bits_retain(read_register(Register::StatusB))
+}
+
+/// Read the registers once no update is in progress
+fn read_raw() -> RawTime {
+    while read_register(Register::StatusA) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    RawTime {
+        second: read_register(Register::Seconds),
+        minute: read_register(Register::Minutes),
+        hour: read_register(Register::Hours),
+        day: read_register(Register::Day),
+        month: read_register(Register::Month),
+        year: read_register(Register::Year),
+        century: read_register(Register::Century),
+    }
+}
+
+/// Read the registers until two reads agree, an update may start right
+/// after the check of `read_raw`
+fn read_stable() -> RawTime {
+    let mut last = read_raw();
+    loop {
+        let time = read_raw();
+        if time == last {
+            return time;
+        }
+        last = time;
+    }
+}
+
+fn decode(raw: RawTime, status: StatusB) -> Option<DateTime<Utc>> {
+    let binary = status.contains(StatusB::BINARY);
+    let value = |v: u8| -> u32 {
+        if binary {
+            v as u32
+        } else {
+            (v & 0x0F) as u32 + (v >> 4) as u32 * 10
+        }
+    };
+
+    // the PM bit is not part of the BCD value
+    let mut hour = value(raw.hour & !HOUR_PM);
+    if !status.contains(StatusB::HOUR_24) {
+        // 12 AM is midnight, 12 PM is noon
+        hour %= 12;
+        if raw.hour & HOUR_PM != 0 {
+            hour += 12;
+        }
+    }
+
+    // the century register may be missing, assume the 21st century then
+    let century = match value(raw.century) {
+        century @ 19..=21 => century,
+        _ => 20,
+    };
+    let year = century * 100 + value(raw.year);
+
+    NaiveDate::from_ymd_opt(year as i32, value(raw.month), value(raw.day))?
+        .and_hms_opt(hour, value(raw.minute), value(raw.second))
+        .map(|time| time.and_utc())
+}
+
+/// Read the current date and time of the RTC
+pub fn read_time() -> Option<DateTime<Utc>> {
+    interrupts::without_interrupts(|| decode(read_stable(), status_b()))
+}
+
+/// Seed the wall clock and enable the update-ended interrupt
+pub fn init() {
+    match read_time() {
+        Some(time) => {
+            crate::utils::time::set_realtime(time.timestamp().max(0) as u64);
+            info!("RTC initialized, time: {}", time);
+        }
+        None => warn!("RTC: invalid date and time, the wall clock starts at the epoch"),
+    }
+
+    interrupts::without_interrupts(|| {
+        write_register(Register::StatusB, (status_b() | StatusB::UPDATE_ENDED_INTERRUPT).bits());
+        // the interrupts pending are cleared by reading status C
+        read_register(Register::StatusC);
+    });
+}
+
+/// Handle IRQ8, the RTC has updated its registers
+pub fn handle_irq() {
+    let status = StatusC::from_bits_retain(read_register(Register::StatusC));
+
+    if status.contains(StatusC::UPDATE_ENDED) {
+        crate::utils::time::tick_second();
+
+        // nothing changes in the registers for almost a second now
+        if let Some(time) = decode(read_raw(), status_b()) {
+            crate::utils::time::set_realtime(time.timestamp().max(0) as u64);
+        }
+    }
+}
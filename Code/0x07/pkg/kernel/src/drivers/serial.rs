@@ -1,22 +1,72 @@
-use super::uart16550::SerialPort;
+use super::uart16550::{SerialConfig, SerialPort};
+use spin::{Mutex, MutexGuard};
 
 const SERIAL_IO_PORT: u16 = 0x3F8; // COM1
+const SERIAL2_IO_PORT: u16 = 0x2F8; // COM2
 
 // 使用常量泛型定义串口
 once_mutex!(pub SERIAL: SerialPort<SERIAL_IO_PORT>);
+// COM2 不一定存在, 只在自检通过后初始化
+once_mutex!(pub SERIAL2: SerialPort<SERIAL2_IO_PORT>);
+
+/// Initialize the serial ports with the `serial` option of the boot config
+pub fn init(settings: &str) {
+    let parsed = SerialConfig::parse(settings);
+    let config = parsed.unwrap_or(SerialConfig::DEFAULT);
 
-pub fn init() {
-    // 不再需要传入端口地址
     init_SERIAL(SerialPort::<SERIAL_IO_PORT>::new());
-    get_serial_for_sure().init();
-    
+    if !get_serial_for_sure().init(config) {
+        panic!("Serial port initialization failed");
+    }
+
     // 添加清屏转义序列
     // \x1b[2J 是清屏命令
     // \x1b[H 是将光标移到屏幕左上角
     print!("\x1b[2J\x1b[H");
-    
+
     println!("{}", crate::get_ascii_header());
-    println!("[+] Serial Initialized.");
+    if parsed.is_none() {
+        println!("[!] Invalid serial settings '{}', using {}", settings, config);
+    }
+    println!("[+] Serial Initialized: {}", config);
+
+    let mut serial2 = SerialPort::<SERIAL2_IO_PORT>::new();
+    if serial2.init(config) {
+        init_SERIAL2(serial2);
+        println!("[+] COM2 Initialized.");
+    }
+}
+
+/// Queue the output of the serial ports, once their interrupts are routed
+pub fn enable_buffering() {
+    if let Some(mut serial) = get_serial() {
+        serial.enable_buffering();
+    }
+    if let Some(mut serial) = get_serial2() {
+        serial.enable_buffering();
+    }
+}
+
+/// Send what is queued, before the interrupts stop for good
+pub fn flush() {
+    if let Some(mut serial) = get_serial() {
+        serial.flush();
+    }
+    if let Some(mut serial) = get_serial2() {
+        serial.flush();
+    }
 }
 
 guard_access_fn!(pub get_serial(SERIAL: SerialPort<SERIAL_IO_PORT>));
+guard_access_fn!(pub get_serial2(SERIAL2: SerialPort<SERIAL2_IO_PORT>));
+
+/// `get_serial` waiting while another CPU prints, only with interrupts
+/// disabled outside of the printing paths, so this CPU cannot hold it
+pub fn wait_serial() -> Option<MutexGuard<'static, SerialPort<SERIAL_IO_PORT>>> {
+    SERIAL.get().map(Mutex::lock)
+}
+
+/// `get_serial2` waiting while another CPU prints, see `wait_serial`
+pub fn wait_serial2() -> Option<MutexGuard<'static, SerialPort<SERIAL2_IO_PORT>>> {
+    SERIAL2.get().map(Mutex::lock)
+}
@@ -0,0 +1,189 @@
+//! Line discipline
+//!
+//! Sits between the characters received by a terminal and the processes
+//! reading it. In canonical mode the input is edited line by line and only
+//! complete lines can be read, otherwise every character is readable as
+//! soon as it arrives. Ctrl-C and Ctrl-Z become signals for the foreground
+//! job instead of input if `ISIG` is set.
+//!
+//! reference: https://man7.org/linux/man-pages/man3/termios.3.html
+
+use super::Tty;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+
+/// Bytes of a line in canonical mode, the characters beyond are dropped
+const MAX_CANON: usize = 1024;
+/// Bytes waiting to be read, the input beyond is dropped
+const MAX_INPUT: usize = 4096;
+/// The sender is asked to stop above this many bytes waiting, and to go on
+/// below the low water mark
+const INPUT_HIGH_WATER: usize = MAX_INPUT * 3 / 4;
+const INPUT_LOW_WATER: usize = MAX_INPUT / 4;
+
+const CTRL_C: char = '\x03';
+const CTRL_Z: char = '\x1a';
+
+bitflags! {
+    /// The bits of `TTY_SET_MODE` in the syscall crate, but `TTY_NONBLOCK`
+    /// which belongs to the file descriptor
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct TtyMode: usize {
+        const CANON = ysos_syscall::TTY_CANON;
+        const ECHO = ysos_syscall::TTY_ECHO;
+        const ISIG = ysos_syscall::TTY_ISIG;
+    }
+}
+
+impl TtyMode {
+    /// A terminal edits lines and waits for them by default
+    pub const DEFAULT: Self = Self::CANON.union(Self::ECHO).union(Self::ISIG);
+}
+
+/// Sent to the foreground job of a terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtySignal {
+    /// Ctrl-C, the job is killed
+    Interrupt,
+    /// Ctrl-Z, the job is stopped
+    Suspend,
+}
+
+pub struct LineDiscipline {
+    mode: TtyMode,
+    /// The line being edited in canonical mode
+    line: String,
+    /// Input ready to be read
+    ready: VecDeque<u8>,
+    /// Raised by the input, waiting to be delivered
+    signal: Option<TtySignal>,
+}
+
+impl LineDiscipline {
+    pub const fn new() -> Self {
+        Self {
+            mode: TtyMode::DEFAULT,
+            line: String::new(),
+            ready: VecDeque::new(),
+            signal: None,
+        }
+    }
+
+    pub fn mode(&self) -> TtyMode {
+        self.mode
+    }
+
+    /// Switch to `mode`, the line being edited becomes readable when
+    /// leaving canonical mode
+    pub fn set_mode(&mut self, mode: TtyMode) {
+        if !mode.contains(TtyMode::CANON) {
+            let line = core::mem::take(&mut self.line);
+            self.push_ready(line.as_bytes());
+        }
+        self.mode = mode;
+    }
+
+    /// Take the signal raised since the last call
+    pub fn take_signal(&mut self) -> Option<TtySignal> {
+        self.signal.take()
+    }
+
+    /// Whether a read would return some input
+    pub fn has_input(&self) -> bool {
+        !self.ready.is_empty()
+    }
+
+    /// Whether the sender should stop, or may go on if `false`; `None`
+    /// between the water marks
+    pub fn throttle(&self) -> Option<bool> {
+        match self.ready.len() {
+            len if len >= INPUT_HIGH_WATER => Some(true),
+            len if len <= INPUT_LOW_WATER => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Read the input ready, a line at most in canonical mode
+    ///
+    /// Returns 0 if nothing is ready.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+
+        while count < buf.len() {
+            let Some(byte) = self.ready.pop_front() else {
+                break;
+            };
+
+            buf[count] = byte;
+            count += 1;
+
+            if byte == b'\n' && self.mode.contains(TtyMode::CANON) {
+                break;
+            }
+        }
+
+        count
+    }
+
+    /// Handle a character received by `tty`
+    pub fn receive(&mut self, tty: Tty, c: char) {
+        if self.mode.contains(TtyMode::ISIG) && (c == CTRL_C || c == CTRL_Z) {
+            let (signal, echo) = match c {
+                CTRL_C => (TtySignal::Interrupt, "^C\r\n"),
+                _ => (TtySignal::Suspend, "^Z\r\n"),
+            };
+
+            // the pending input was meant for the job
+            self.line.clear();
+            self.ready.clear();
+            self.signal = Some(signal);
+
+            self.echo(tty, echo);
+            return;
+        }
+
+        if !self.mode.contains(TtyMode::CANON) {
+            let mut bytes = [0; 4];
+            self.push_ready(c.encode_utf8(&mut bytes).as_bytes());
+            self.echo(tty, c.encode_utf8(&mut bytes));
+            return;
+        }
+
+        match c {
+            '\r' | '\n' => {
+                let mut line = core::mem::take(&mut self.line);
+                line.push('\n');
+                self.push_ready(line.as_bytes());
+                self.echo(tty, "\r\n");
+            }
+            '\x08' | '\x7f' => {
+                if self.line.pop().is_some() {
+                    self.echo(tty, "\x08 \x08");
+                }
+            }
+            // other control characters have no meaning in a line
+            c if c.is_control() && c != '\t' => {}
+            c => {
+                if self.line.len() + c.len_utf8() < MAX_CANON {
+                    self.line.push(c);
+                    let mut bytes = [0; 4];
+                    self.echo(tty, c.encode_utf8(&mut bytes));
+                }
+            }
+        }
+    }
+
+    fn echo(&self, tty: Tty, s: &str) {
+        if self.mode.contains(TtyMode::ECHO) {
+            tty.write_str(s);
+        }
+    }
+
+    fn push_ready(&mut self, bytes: &[u8]) {
+        if self.ready.len() + bytes.len() > MAX_INPUT {
+            warn!("Input of the tty is full, dropping {} bytes", bytes.len());
+            return;
+        }
+        self.ready.extend(bytes);
+    }
+}
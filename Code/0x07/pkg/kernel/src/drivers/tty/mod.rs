@@ -0,0 +1,323 @@
+//! Terminals
+//!
+//! The console is COM1 together with the keyboard and the screen, it is
+//! where the kernel logs go and the stdio of processes by default. COM2 is
+//! a terminal of its own, opened as `/dev/ttyS1`, which can also run a
+//! second shell or take the kernel logs instead, see `com2` in boot.conf.
+//!
+//! The input of each terminal goes through its line discipline. The
+//! programs started by a process of a terminal are its foreground jobs,
+//! which get the Ctrl-C and Ctrl-Z of the terminal.
+
+mod ldisc;
+
+pub use ldisc::{TtyMode, TtySignal};
+
+use super::serial::{get_serial2, wait_serial, wait_serial2, SERIAL2};
+use super::uart16550::{Parity, SerialConfig};
+use crate::proc::{still_alive, ProcessId, SleepError};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt::{Arguments, Write};
+use ldisc::LineDiscipline;
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts;
+use ysos_syscall::{SerialAttr, EAGAIN, EINTR, PARITY_EVEN, PARITY_NONE, PARITY_ODD};
+
+/// Directory of the device files
+pub const DEV_DIR: &str = "/dev/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tty {
+    /// COM1, the keyboard and the screen
+    Console,
+    /// COM2
+    Serial1,
+}
+
+/// What COM2 is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Com2Usage {
+    /// A terminal for the processes that open it
+    Tty,
+    /// A second shell session runs on it
+    Shell,
+    /// The kernel logs go to it instead of the console
+    Log,
+}
+
+static COM2_USAGE: Once<Com2Usage> = Once::new();
+
+/// The jobs started on a terminal
+struct Jobs {
+    /// The last one alive is in the foreground
+    running: Vec<ProcessId>,
+    /// Stopped by Ctrl-Z, the last one is continued first
+    stopped: Vec<ProcessId>,
+}
+
+impl Jobs {
+    const fn new() -> Self {
+        Self {
+            running: Vec::new(),
+            stopped: Vec::new(),
+        }
+    }
+}
+
+// 按 Tty 的顺序排列, 在中断处理中也会使用
+static LDISC: [Mutex<LineDiscipline>; 2] =
+    [Mutex::new(LineDiscipline::new()), Mutex::new(LineDiscipline::new())];
+// 等待终端输入的进程
+static READERS: [Mutex<VecDeque<ProcessId>>; 2] = [const { Mutex::new(VecDeque::new()) }; 2];
+static JOBS: [Mutex<Jobs>; 2] = [Mutex::new(Jobs::new()), Mutex::new(Jobs::new())];
+
+/// Set what COM2 is used for, from the `com2` option of the boot config
+pub fn init(com2: &str) {
+    let usage = match com2 {
+        "tty" => Com2Usage::Tty,
+        "shell" => Com2Usage::Shell,
+        "log" => Com2Usage::Log,
+        _ => {
+            warn!("Unknown com2 usage {}, using tty", com2);
+            Com2Usage::Tty
+        }
+    };
+
+    if usage != Com2Usage::Tty && !Tty::Serial1.is_present() {
+        warn!("COM2 is not present, cannot be used for {:?}", usage);
+        return;
+    }
+
+    COM2_USAGE.call_once(|| usage);
+}
+
+pub fn com2_usage() -> Com2Usage {
+    COM2_USAGE.get().copied().unwrap_or(Com2Usage::Tty)
+}
+
+/// The terminal the kernel logs are written to
+pub fn log_tty() -> Tty {
+    match com2_usage() {
+        Com2Usage::Log => Tty::Serial1,
+        _ => Tty::Console,
+    }
+}
+
+impl Tty {
+    pub const ALL: [Tty; 2] = [Tty::Console, Tty::Serial1];
+
+    /// The terminal of the device file at `path`
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path.strip_prefix(DEV_DIR)? {
+            "console" | "ttyS0" => Some(Tty::Console),
+            "ttyS1" => Some(Tty::Serial1),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tty::Console => "console",
+            Tty::Serial1 => "ttyS1",
+        }
+    }
+
+    pub fn is_present(self) -> bool {
+        match self {
+            Tty::Console => true,
+            Tty::Serial1 => SERIAL2.get().is_some(),
+        }
+    }
+
+    /// Line settings of the serial port of the terminal
+    pub fn serial_attr(self) -> Option<SerialAttr> {
+        let config = interrupts::without_interrupts(|| match self {
+            Tty::Console => wait_serial().map(|serial| serial.config()),
+            Tty::Serial1 => wait_serial2().map(|serial| serial.config()),
+        })?;
+
+        Some(SerialAttr {
+            baud_rate: config.baud_rate,
+            data_bits: config.data_bits,
+            parity: match config.parity {
+                Parity::None => PARITY_NONE,
+                Parity::Odd => PARITY_ODD,
+                Parity::Even => PARITY_EVEN,
+            },
+            stop_bits: config.stop_bits,
+            fifo_trigger: config.fifo_trigger,
+            flow_control: config.flow_control,
+        })
+    }
+
+    /// Change the line settings of the serial port of the terminal
+    ///
+    /// Returns false if they are not valid.
+    pub fn set_serial_attr(self, attr: &SerialAttr) -> bool {
+        let config = SerialConfig {
+            baud_rate: attr.baud_rate,
+            data_bits: attr.data_bits,
+            parity: match attr.parity {
+                PARITY_NONE => Parity::None,
+                PARITY_ODD => Parity::Odd,
+                PARITY_EVEN => Parity::Even,
+                _ => return false,
+            },
+            stop_bits: attr.stop_bits,
+            fifo_trigger: attr.fifo_trigger,
+            flow_control: attr.flow_control,
+        };
+
+        interrupts::without_interrupts(|| match self {
+            Tty::Console => wait_serial().is_some_and(|mut serial| serial.set_config(config)),
+            Tty::Serial1 => wait_serial2().is_some_and(|mut serial| serial.set_config(config)),
+        })
+    }
+
+    fn ldisc(self) -> &'static Mutex<LineDiscipline> {
+        &LDISC[self as usize]
+    }
+
+    fn jobs(self) -> &'static Mutex<Jobs> {
+        &JOBS[self as usize]
+    }
+
+    /// Handle a character received, called by the interrupt handlers
+    pub fn receive(self, c: char) {
+        let (has_input, throttle) = {
+            let mut ldisc = self.ldisc().lock();
+            ldisc.receive(self, c);
+            (ldisc.has_input(), ldisc.throttle())
+        };
+
+        // Ctrl-C empties the input, the sender may go on again then
+        if let Some(throttled) = throttle {
+            self.throttle(throttled);
+        }
+
+        if has_input {
+            let pids = core::mem::take(&mut *READERS[self as usize].lock());
+            let manager = crate::proc::get_process_manager();
+            for pid in pids {
+                manager.wake_up(pid, None);
+            }
+        }
+    }
+
+    fn has_input(self) -> bool {
+        interrupts::without_interrupts(|| self.ldisc().lock().has_input())
+    }
+
+    /// Read the input of the terminal
+    ///
+    /// Waits until some input is ready, unless `nonblocking` is set.
+    /// Returns `-EAGAIN` if nothing is ready without waiting, and `-EINTR`
+    /// if the wait was interrupted by Ctrl-C.
+    pub fn read(self, buf: &mut [u8], nonblocking: bool) -> isize {
+        if !buf.is_empty() {
+            if nonblocking {
+                if !self.has_input() {
+                    return -EAGAIN;
+                }
+            } else {
+                match crate::proc::sleep_on_interruptible(&READERS[self as usize], || self.has_input()) {
+                    Ok(()) => {}
+                    // 无法睡眠时轮询
+                    Err(SleepError::Unable) => {
+                        while !self.has_input() {
+                            core::hint::spin_loop();
+                        }
+                    }
+                    Err(SleepError::Interrupted) => return -EINTR,
+                }
+            }
+        }
+
+        interrupts::without_interrupts(|| {
+            let (count, throttle) = {
+                let mut ldisc = self.ldisc().lock();
+                let count = ldisc.read(buf);
+                (count, ldisc.throttle())
+            };
+
+            if throttle == Some(false) {
+                self.throttle(false);
+            }
+            count as isize
+        })
+    }
+
+    /// Drop or raise RTS of the serial port, for its flow control
+    fn throttle(self, throttled: bool) {
+        match self {
+            Tty::Console => wait_serial().map(|mut serial| serial.throttle(throttled)),
+            Tty::Serial1 => wait_serial2().map(|mut serial| serial.throttle(throttled)),
+        };
+    }
+
+    pub fn mode(self) -> TtyMode {
+        interrupts::without_interrupts(|| self.ldisc().lock().mode())
+    }
+
+    pub fn set_mode(self, mode: TtyMode) {
+        interrupts::without_interrupts(|| self.ldisc().lock().set_mode(mode));
+    }
+
+    /// Take the signal raised by the input since the last call
+    pub fn take_signal(self) -> Option<TtySignal> {
+        interrupts::without_interrupts(|| self.ldisc().lock().take_signal())
+    }
+
+    /// Run the process `pid` in the foreground
+    pub fn push_job(self, pid: ProcessId) {
+        self.jobs().lock().running.push(pid);
+    }
+
+    /// The foreground job, the jobs exited are dropped on the way
+    pub fn foreground(self) -> Option<ProcessId> {
+        let mut jobs = self.jobs().lock();
+        while let Some(&pid) = jobs.running.last() {
+            if still_alive(pid) {
+                return Some(pid);
+            }
+            jobs.running.pop();
+        }
+        None
+    }
+
+    /// Move the job `pid` stopped by Ctrl-Z out of the foreground
+    pub fn stop_job(self, pid: ProcessId) {
+        let mut jobs = self.jobs().lock();
+        jobs.running.retain(|&p| p != pid);
+        jobs.stopped.push(pid);
+    }
+
+    /// Bring the last job stopped back to the foreground, the caller
+    /// continues it
+    pub fn continue_job(self) -> Option<ProcessId> {
+        let mut jobs = self.jobs().lock();
+        while let Some(pid) = jobs.stopped.pop() {
+            if still_alive(pid) {
+                jobs.running.push(pid);
+                return Some(pid);
+            }
+        }
+        None
+    }
+
+    pub fn write_fmt(self, args: Arguments) {
+        match self {
+            Tty::Console => crate::utils::print_internal(args),
+            Tty::Serial1 => interrupts::without_interrupts(|| {
+                if let Some(mut serial) = get_serial2() {
+                    serial.write_fmt(args).unwrap();
+                }
+            }),
+        }
+    }
+
+    pub fn write_str(self, s: &str) {
+        self.write_fmt(format_args!("{}", s));
+    }
+}
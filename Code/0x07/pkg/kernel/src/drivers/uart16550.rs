@@ -1,118 +1,437 @@
+//! UART 16550 serial port
+//!
+//! The line settings come from the `serial` option of the boot config and
+//! can be changed later through the terminal. Once the interrupts are on,
+//! the bytes sent are queued and the THRE interrupt moves them to the
+//! transmit FIFO, so a long print does not wait for every byte to go out.
+//!
+//! RTS/CTS flow control is done in software, which works on every 16550:
+//! nothing is sent while CTS is low, and the terminal drops RTS while its
+//! input queue is nearly full.
+//!
+//! reference: https://wiki.osdev.org/Serial_Ports
+//! reference: https://www.lammertbies.nl/comm/info/serial-uart
+
 use core::fmt;
-use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+use x86_64::instructions::port::Port;
+
+/// The baud rate is this clock divided by the divisor latch
+const UART_CLOCK: u32 = 115200;
+/// Bytes waiting to be moved to the transmit FIFO
+const TX_BUFFER_SIZE: usize = 4096;
+/// Bytes the transmit FIFO holds
+const TX_FIFO_SIZE: usize = 16;
+/// Polls of the transmitter before a byte is dropped, about a second on
+/// the ports of a PC, should the other end keep CTS low
+const TX_TIMEOUT_SPINS: usize = 1_000_000;
+
+// 寄存器偏移, DLAB 置位时 0 和 1 为波特率除数
+const REG_DATA: u16 = 0;
+const REG_INTERRUPT_ENABLE: u16 = 1;
+const REG_INTERRUPT_ID: u16 = 2;
+const REG_FIFO_CONTROL: u16 = 2;
+const REG_LINE_CONTROL: u16 = 3;
+const REG_MODEM_CONTROL: u16 = 4;
+const REG_LINE_STATUS: u16 = 5;
+const REG_MODEM_STATUS: u16 = 6;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct InterruptEnable: u8 {
+        const RECEIVED = 1 << 0;
+        const TRANSMIT_EMPTY = 1 << 1;
+        const LINE_STATUS = 1 << 2;
+        const MODEM_STATUS = 1 << 3;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct ModemControl: u8 {
+        const DTR = 1 << 0;
+        const RTS = 1 << 1;
+        const OUT1 = 1 << 2;
+        /// Connects the interrupt line of the UART
+        const OUT2 = 1 << 3;
+        const LOOPBACK = 1 << 4;
+    }
+}
+
+const LINE_DLAB: u8 = 1 << 7;
+const LINE_TWO_STOP_BITS: u8 = 1 << 2;
+const LINE_PARITY_ENABLE: u8 = 1 << 3;
+const LINE_EVEN_PARITY: u8 = 1 << 4;
+
+const FIFO_ENABLE: u8 = 0x01;
+/// Clear both FIFOs, with `FIFO_ENABLE`
+const FIFO_CLEAR: u8 = 0x06;
+
+const STATUS_DATA_READY: u8 = 1 << 0;
+const STATUS_THR_EMPTY: u8 = 1 << 5;
+
+const MODEM_CTS: u8 = 1 << 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// Line settings of a serial port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    /// 5 to 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2
+    pub stop_bits: u8,
+    /// Bytes in the receive FIFO raising an interrupt: 1, 4, 8 or 14
+    pub fifo_trigger: u8,
+    /// RTS/CTS hardware flow control
+    pub flow_control: bool,
+}
+
+impl SerialConfig {
+    /// 38400 baud, 8 data bits, no parity, 1 stop bit
+    pub const DEFAULT: Self = Self {
+        baud_rate: 38400,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: 1,
+        fifo_trigger: 14,
+        flow_control: false,
+    };
+
+    /// Parse `<baud>[,<data><parity><stop>][,fifo<n>][,rtscts]`, e.g.
+    /// `115200,8n1,fifo8,rtscts`, the settings left out are the default
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut config = Self::DEFAULT;
+        let mut fields = s.split(',').map(str::trim);
+
+        config.baud_rate = fields.next()?.parse().ok()?;
+
+        for field in fields {
+            if field == "rtscts" {
+                config.flow_control = true;
+            } else if let Some(trigger) = field.strip_prefix("fifo") {
+                config.fifo_trigger = trigger.parse().ok()?;
+            } else if let [data, parity, stop] = field.as_bytes() {
+                config.data_bits = data.wrapping_sub(b'0');
+                config.stop_bits = stop.wrapping_sub(b'0');
+                config.parity = match parity.to_ascii_lowercase() {
+                    b'n' => Parity::None,
+                    b'o' => Parity::Odd,
+                    b'e' => Parity::Even,
+                    _ => return None,
+                };
+            } else {
+                return None;
+            }
+        }
+
+        config.is_valid().then_some(config)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.divisor().is_some()
+            && (5..=8).contains(&self.data_bits)
+            && (1..=2).contains(&self.stop_bits)
+            && matches!(self.fifo_trigger, 1 | 4 | 8 | 14)
+    }
+
+    /// The divisor latch, None if the baud rate cannot be made exactly
+    fn divisor(&self) -> Option<u16> {
+        if self.baud_rate == 0 || !UART_CLOCK.is_multiple_of(self.baud_rate) {
+            return None;
+        }
+        u16::try_from(UART_CLOCK / self.baud_rate).ok()
+    }
+
+    fn line_control(&self) -> u8 {
+        let mut value = self.data_bits - 5;
+        if self.stop_bits == 2 {
+            value |= LINE_TWO_STOP_BITS;
+        }
+        match self.parity {
+            Parity::None => {}
+            Parity::Odd => value |= LINE_PARITY_ENABLE,
+            Parity::Even => value |= LINE_PARITY_ENABLE | LINE_EVEN_PARITY,
+        }
+        value
+    }
+
+    fn fifo_control(&self) -> u8 {
+        let trigger = match self.fifo_trigger {
+            1 => 0b00,
+            4 => 0b01,
+            8 => 0b10,
+            _ => 0b11,
+        };
+        FIFO_ENABLE | trigger << 6
+    }
+}
+
+impl fmt::Display for SerialConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'n',
+            Parity::Odd => 'o',
+            Parity::Even => 'e',
+        };
+        write!(
+            f,
+            "{},{}{}{},fifo{}",
+            self.baud_rate, self.data_bits, parity, self.stop_bits, self.fifo_trigger
+        )?;
+        if self.flow_control {
+            write!(f, ",rtscts")?;
+        }
+        Ok(())
+    }
+}
 
 /// A port-mapped UART 16550 serial interface.
-pub struct SerialPort<const BASE_ADDR: u16>;
+pub struct SerialPort<const BASE_ADDR: u16> {
+    config: SerialConfig,
+    interrupts: InterruptEnable,
+    /// Ring of the bytes waiting to be sent
+    tx: [u8; TX_BUFFER_SIZE],
+    tx_head: usize,
+    tx_len: usize,
+    /// Sending is driven by the THRE interrupt, the bytes are sent one by
+    /// one as soon as possible otherwise
+    buffered: bool,
+    /// RTS is dropped while the receiver cannot take more, with flow control
+    throttled: bool,
+}
+
+impl<const BASE_ADDR: u16> Default for SerialPort<BASE_ADDR> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl<const BASE_ADDR: u16> SerialPort<BASE_ADDR> {
     pub const fn new() -> Self {
-        Self
+        Self {
+            config: SerialConfig::DEFAULT,
+            interrupts: InterruptEnable::empty(),
+            tx: [0; TX_BUFFER_SIZE],
+            tx_head: 0,
+            tx_len: 0,
+            buffered: false,
+            throttled: false,
+        }
+    }
+
+    fn read(reg: u16) -> u8 {
+        unsafe { Port::new(BASE_ADDR + reg).read() }
     }
 
-    /// Initializes the serial port.
-    pub fn init(&self) {
+    fn write(reg: u16, value: u8) {
+        unsafe { Port::new(BASE_ADDR + reg).write(value) }
+    }
+
+    /// Initializes the serial port with `config`.
+    ///
+    /// Returns false if the loopback test fails, there is no UART at the port.
+    pub fn init(&mut self, config: SerialConfig) -> bool {
         // 禁用所有中断
-        let mut interrupt_enable = PortWriteOnly::new(BASE_ADDR + 1);
-        unsafe {
-            interrupt_enable.write(0x00_u8);
-        }
-        
-        // 启用 DLAB (设置波特率除数)
-        let mut line_control = PortWriteOnly::new(BASE_ADDR + 3);
-        unsafe {
-            line_control.write(0x80_u8);
-        }
-        
-        // 设置除数为 3 (低字节) 38400 波特率
-        let mut divisor_lo = PortWriteOnly::new(BASE_ADDR + 0);
-        unsafe {
-            divisor_lo.write(0x03_u8);
-        }
-        
-        // 高字节设置为 0
-        let mut divisor_hi = PortWriteOnly::new(BASE_ADDR + 1);
-        unsafe {
-            divisor_hi.write(0x00_u8);
-        }
-        
-        // 8 位数据, 无奇偶校验, 一个停止位
-        unsafe {
-            line_control.write(0x03_u8);
-        }
-        
-        // 启用 FIFO, 清空, 使用 14 字节阈值
-        let mut fifo_control = PortWriteOnly::new(BASE_ADDR + 2);
-        unsafe {
-            fifo_control.write(0xC7_u8);
-        }
-        
-        // 启用 IRQ, 设置 RTS/DSR
-        let mut modem_control = PortWriteOnly::new(BASE_ADDR + 4);
-        unsafe {
-            modem_control.write(0x0B_u8);
-        }
-        
-        // 设置回环模式, 测试串行芯片
-        unsafe {
-            modem_control.write(0x1E_u8);
-        }
-        
-        // 测试串行芯片 (发送 0xAE 字节并检查返回值)
-        let mut data = Port::new(BASE_ADDR);
-        unsafe {
-            data.write(0xAE_u8);
-        }
-        
-        // 检查串口是否有故障 (返回字节是否与发送字节相同)
-        unsafe {
-            if data.read() != 0xAE_u8 {
-                panic!("Serial port initialization failed");
-            }
+        self.set_interrupts(InterruptEnable::empty());
+
+        // 设置波特率, 数据位, 校验位, 停止位和 FIFO 阈值
+        self.program_line(config);
+        Self::write(REG_FIFO_CONTROL, config.fifo_control() | FIFO_CLEAR);
+
+        // 设置回环模式, 测试串行芯片 (发送 0xAE 字节并检查返回值)
+        Self::write(
+            REG_MODEM_CONTROL,
+            (ModemControl::RTS | ModemControl::OUT1 | ModemControl::OUT2 | ModemControl::LOOPBACK).bits(),
+        );
+        Self::write(REG_DATA, 0xAE);
+        if Self::read(REG_DATA) != 0xAE {
+            return false;
         }
-        
+
         // 设置为正常操作模式 (非回环模式, IRQ 启用, OUT#1 和 OUT#2 位启用)
-        unsafe {
-            modem_control.write(0x0F_u8);
+        self.program_modem();
+
+        // 启用接收数据中断, 流控时还要知道 CTS 的变化
+        self.update_interrupts();
+
+        true
+    }
+
+    pub fn config(&self) -> SerialConfig {
+        self.config
+    }
+
+    /// Change the line settings, after the bytes queued are sent
+    ///
+    /// The FIFOs are only cleared if their trigger level changes, the bytes
+    /// in flight are kept otherwise. Returns false if `config` is not valid.
+    pub fn set_config(&mut self, config: SerialConfig) -> bool {
+        if !config.is_valid() {
+            return false;
+        }
+
+        self.flush();
+        let fifo_changed = config.fifo_trigger != self.config.fifo_trigger;
+        self.program_line(config);
+        if fifo_changed {
+            Self::write(REG_FIFO_CONTROL, config.fifo_control() | FIFO_CLEAR);
+        }
+        self.program_modem();
+        self.update_interrupts();
+        true
+    }
+
+    /// Queue the bytes sent from now on, the THRE interrupt must be routed
+    pub fn enable_buffering(&mut self) {
+        self.buffered = true;
+    }
+
+    fn program_line(&mut self, config: SerialConfig) {
+        self.config = config;
+        let divisor = config.divisor().unwrap_or(3);
+
+        // 启用 DLAB, 设置波特率除数
+        Self::write(REG_LINE_CONTROL, LINE_DLAB);
+        Self::write(REG_DATA, divisor as u8);
+        Self::write(REG_INTERRUPT_ENABLE, (divisor >> 8) as u8);
+
+        Self::write(REG_LINE_CONTROL, config.line_control());
+    }
+
+    fn program_modem(&mut self) {
+        let mut modem = ModemControl::DTR | ModemControl::OUT1 | ModemControl::OUT2;
+        modem.set(ModemControl::RTS, !(self.config.flow_control && self.throttled));
+        Self::write(REG_MODEM_CONTROL, modem.bits());
+    }
+
+    /// Ask the other end to stop sending, or to go on, with flow control
+    pub fn throttle(&mut self, throttled: bool) {
+        if self.throttled != throttled {
+            self.throttled = throttled;
+            self.program_modem();
+        }
+    }
+
+    fn set_interrupts(&mut self, interrupts: InterruptEnable) {
+        self.interrupts = interrupts;
+        Self::write(REG_INTERRUPT_ENABLE, interrupts.bits());
+    }
+
+    /// Enable the interrupts needed for now
+    fn update_interrupts(&mut self) {
+        let mut interrupts = InterruptEnable::RECEIVED;
+        interrupts.set(InterruptEnable::MODEM_STATUS, self.config.flow_control);
+        interrupts.set(InterruptEnable::TRANSMIT_EMPTY, self.tx_len > 0 && self.clear_to_send());
+
+        if interrupts != self.interrupts {
+            self.set_interrupts(interrupts);
+        }
+    }
+
+    fn clear_to_send(&self) -> bool {
+        !self.config.flow_control || Self::read(REG_MODEM_STATUS) & MODEM_CTS != 0
+    }
+
+    fn thr_empty() -> bool {
+        Self::read(REG_LINE_STATUS) & STATUS_THR_EMPTY != 0
+    }
+
+    /// Send a byte right away, waiting for the transmitter
+    ///
+    /// Returns false if the byte is dropped, the transmitter did not become
+    /// ready in time.
+    fn send_polled(&mut self, data: u8) -> bool {
+        for _ in 0..TX_TIMEOUT_SPINS {
+            if Self::thr_empty() && self.clear_to_send() {
+                Self::write(REG_DATA, data);
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+        false
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.tx_len == 0 {
+            return None;
         }
-        
-        // 启用接收数据中断
-        unsafe {
-            interrupt_enable.write(0x01_u8);
+        let byte = self.tx[self.tx_head];
+        self.tx_head = (self.tx_head + 1) % TX_BUFFER_SIZE;
+        self.tx_len -= 1;
+        Some(byte)
+    }
+
+    /// Move the bytes queued to the transmit FIFO if it is empty
+    fn pump(&mut self) {
+        if self.tx_len > 0 && self.clear_to_send() && Self::thr_empty() {
+            for _ in 0..TX_FIFO_SIZE {
+                let Some(byte) = self.pop() else {
+                    break;
+                };
+                Self::write(REG_DATA, byte);
+            }
         }
+        self.update_interrupts();
     }
 
     /// Sends a byte on the serial port.
     pub fn send(&mut self, data: u8) {
-        // 等待发送缓冲区为空
-        let mut line_status = PortReadOnly::<u8>::new(BASE_ADDR + 5);
-        // 检查 Line Status Register 的 Transmitter Holding Register Empty 位(第5位)
-        while unsafe { (line_status.read() & 0x20) == 0 } {
-            // 忙等待直到发送缓冲区为空
+        if !self.buffered {
+            self.send_polled(data);
+            return;
         }
-        
-        // 发送字节
-        let mut data_port = Port::new(BASE_ADDR);
-        unsafe {
-            data_port.write(data);
+
+        // 缓冲区满时, 直接发送 (或超时丢弃) 最早的字节腾出空间
+        if self.tx_len == TX_BUFFER_SIZE {
+            let byte = self.pop().unwrap();
+            self.send_polled(byte);
         }
+
+        self.tx[(self.tx_head + self.tx_len) % TX_BUFFER_SIZE] = data;
+        self.tx_len += 1;
+        self.pump();
+    }
+
+    /// Send the bytes queued, waiting for them
+    ///
+    /// The bytes left are dropped if the other end holds CTS low too long.
+    pub fn flush(&mut self) {
+        while let Some(byte) = self.pop() {
+            if !self.send_polled(byte) {
+                self.tx_len = 0;
+                break;
+            }
+        }
+        self.update_interrupts();
+    }
+
+    /// Handle the transmitter and modem status interrupts
+    pub fn handle_irq(&mut self) {
+        // 读取以清除 THRE 和 modem status 中断
+        Self::read(REG_INTERRUPT_ID);
+        Self::read(REG_MODEM_STATUS);
+        self.pump();
     }
 
     /// Receives a byte on the serial port no wait.
     pub fn receive(&mut self) -> Option<u8> {
-        let mut line_status = PortReadOnly::<u8>::new(BASE_ADDR + 5);
         // 检查 Line Status Register 的 Data Ready 位(第0位)
-        if unsafe { (line_status.read() & 0x01) == 0 } {
+        if Self::read(REG_LINE_STATUS) & STATUS_DATA_READY == 0 {
             // 无数据可读
             None
         } else {
             // 有数据可读, 读取数据
-            let mut data_port = Port::new(BASE_ADDR);
-            unsafe {
-                Some(data_port.read())
-            }
+            Some(Self::read(REG_DATA))
         }
     }
-    
+
     /// 发送退格控制符序列 (用于删除一个字符)
     pub fn backspace(&mut self) {
         self.send(0x08); // 后退
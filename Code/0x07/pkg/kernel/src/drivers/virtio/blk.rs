@@ -0,0 +1,313 @@
+//! Virtio Block Device
+//!
+//! Each request is a chain of three buffers: the header, the data and the
+//! status byte. Only one request is in flight per device, the data goes
+//! through a bounce buffer, see `blkdev::dma`.
+//!
+//! reference: https://docs.oasis-open.org/virtio/virtio/v1.1/cs01/virtio-v1.1-cs01.html#x1-2390002
+
+use super::{VirtQueue, VirtioPci, NO_VECTOR, VIRTIO_VENDOR};
+use crate::drivers::blkdev::dma::{self, DeviceLock, DmaDisk, WaitQueue, SECTOR_SIZE};
+use crate::drivers::{blkdev, pci};
+use crate::interrupt::{self, Irq};
+use crate::memory::{get_frame_alloc_for_sure, physical_to_virtual, FRAME_SIZE};
+use alloc::format;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU8, Ordering};
+use storage::{Block512, BlockDevice};
+use x86_64::structures::paging::FrameAllocator;
+
+/// Frames of the bounce buffer of each device
+const BUFFER_FRAMES: usize = 16;
+
+/// Size of the bounce buffer in bytes
+const BUFFER_SIZE: usize = BUFFER_FRAMES * FRAME_SIZE as usize;
+
+/// The device is read-only
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+
+/// The device has a write cache which is flushed on request
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const MODEL: &str = "VirtIO Block Device";
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestType {
+    In = 0,
+    Out = 1,
+    Flush = 4,
+}
+
+/// Value of the status byte, written by the device
+const STATUS_OK: u8 = 0;
+
+/// Processes waiting for a request to complete or for a device to be released
+static WAIT_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Devices are named vda, vdb... in the order they are found
+static NEXT_INDEX: AtomicU8 = AtomicU8::new(0);
+
+static DRIVER: pci::PciDriver = pci::PciDriver {
+    name: "virtio_blk",
+    // transitional and modern device ids
+    matches: &[
+        pci::PciMatch::Id(VIRTIO_VENDOR, 0x1001),
+        pci::PciMatch::Id(VIRTIO_VENDOR, 0x1042),
+    ],
+    probe,
+};
+
+pub fn init() {
+    pci::register_driver(&DRIVER);
+}
+
+/// Handle the completion interrupt, shared by all devices
+pub fn handle_irq() {
+    WAIT_QUEUE.wake_up_all();
+}
+
+pub(crate) struct Inner {
+    pci: VirtioPci,
+    queue: VirtQueue,
+    /// Physical address of the request header, the status byte follows it
+    header: u64,
+    /// Physical address of the bounce buffer
+    buffer: u64,
+}
+
+#[derive(Clone)]
+pub struct VirtioBlk {
+    inner: Arc<DeviceLock<Inner>>,
+    sectors: u64,
+    read_only: bool,
+    /// The device has a write cache to flush
+    flush: bool,
+    /// Completion interrupts are delivered, otherwise the queue is polled
+    interrupt: bool,
+}
+
+fn probe(dev: &pci::PciDevice) -> bool {
+    match VirtioBlk::new(dev) {
+        Ok(blk) => {
+            let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+            let name = format!("vd{}", (b'a' + index) as char);
+
+            info!(
+                "Virtio block device {}: {} sectors{}",
+                name,
+                blk.sectors,
+                if blk.read_only { ", read-only" } else { "" }
+            );
+
+            blkdev::register_disk(&name, MODEL, Arc::new(blk));
+            true
+        }
+        Err(err) => {
+            warn!("Virtio block device {}: {}", dev.addr, err);
+            false
+        }
+    }
+}
+
+/// Deliver the interrupts of the first queue with MSI-X entry 0,
+/// the table must be in the mapped physical memory.
+fn enable_msix(dev: &pci::PciDevice) -> Option<()> {
+    let msix = dev.msix()?;
+    let (bir, _) = msix.table();
+    let bar = dev.bars.get(bir as usize).copied().flatten()?;
+
+    let (addr, size) = bar.memory()?;
+    if addr + size > u32::MAX as u64 + 1 {
+        return None;
+    }
+
+    let (apic_id, vector) = interrupt::msi_target(Irq::VirtioBlk)?;
+    msix.set_entry(&bar, 0, apic_id, vector)?;
+    msix.enable();
+
+    Some(())
+}
+
+impl VirtioBlk {
+    fn new(dev: &pci::PciDevice) -> Result<Self, &'static str> {
+        dev.addr.enable_bus_master();
+
+        // must be done before the transport is found, as the legacy
+        // registers move when MSI-X is enabled
+        let mut interrupt = enable_msix(dev).is_some();
+
+        let mut pci = VirtioPci::new(dev).ok_or("No usable transport")?;
+        let features = pci.init(VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH)?;
+
+        let queue = VirtQueue::new(pci.queue_size(0)).ok_or("Cannot allocate the queue")?;
+
+        if interrupt && pci.setup_queue(0, &queue, 0).is_err() {
+            debug!("Virtio MSI-X vector not accepted, polling instead");
+            interrupt = false;
+        }
+
+        if !interrupt {
+            pci.setup_queue(0, &queue, NO_VECTOR)?;
+        }
+
+        let (header, buffer) = {
+            let mut alloc = get_frame_alloc_for_sure();
+            let header = alloc.allocate_frame().ok_or("Out of memory")?;
+            let buffer = alloc
+                .allocate_contiguous(BUFFER_FRAMES)
+                .ok_or("Out of memory")?;
+            (
+                header.start_address().as_u64(),
+                buffer.start_address().as_u64(),
+            )
+        };
+
+        pci.driver_ok();
+
+        // capacity in 512-byte sectors, whatever the logical block size is
+        let sectors = pci.config_u64(0);
+
+        Ok(Self {
+            inner: Arc::new(DeviceLock::new(
+                Inner {
+                    pci,
+                    queue,
+                    header,
+                    buffer,
+                },
+                &WAIT_QUEUE,
+            )),
+            sectors,
+            read_only: features & VIRTIO_BLK_F_RO != 0,
+            flush: features & VIRTIO_BLK_F_FLUSH != 0,
+            interrupt,
+        })
+    }
+
+    /// Send a request for `len` bytes of the bounce buffer and wait for it,
+    /// `len` is 0 for requests without data
+    fn request(
+        &self,
+        inner: &mut Inner,
+        kind: RequestType,
+        sector: u64,
+        len: usize,
+    ) -> Result<(), &'static str> {
+        let header = physical_to_virtual(inner.header) as *mut u8;
+
+        unsafe {
+            (header as *mut u32).write_volatile(kind as u32);
+            (header.add(4) as *mut u32).write_volatile(0);
+            (header.add(8) as *mut u64).write_volatile(sector);
+            header.add(16).write_volatile(0xFF);
+        }
+
+        let header_buffer = (inner.header, 16, false);
+        let status_buffer = (inner.header + 16, 1, true);
+
+        // a flush carries no data
+        let head = if len == 0 {
+            inner.queue.add(&[header_buffer, status_buffer])
+        } else {
+            let data_buffer = (inner.buffer, len as u32, kind == RequestType::In);
+            inner.queue.add(&[header_buffer, data_buffer, status_buffer])
+        };
+
+        head.ok_or("Virtio queue full")?;
+        inner.pci.notify(0);
+
+        let queue = &inner.queue;
+        WAIT_QUEUE.wait(self.interrupt, || queue.has_used());
+
+        inner.queue.pop_used();
+
+        match unsafe { header.add(16).read_volatile() } {
+            STATUS_OK => Ok(()),
+            _ => Err("Virtio request failed"),
+        }
+    }
+}
+
+impl VirtioBlk {
+    /// Write the cache of the device to its medium
+    pub fn flush(&self) -> Result<(), &'static str> {
+        if !self.flush {
+            return Ok(());
+        }
+
+        let mut inner = self.inner.lock();
+        self.request(&mut inner, RequestType::Flush, 0, 0)
+    }
+}
+
+impl DmaDisk for VirtioBlk {
+    type Device = Inner;
+
+    fn device(&self) -> &DeviceLock<Inner> {
+        &self.inner
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn blocks(&self) -> u64 {
+        self.sectors
+    }
+
+    fn max_transfer(&self) -> usize {
+        BUFFER_SIZE
+    }
+
+    /// Only used while the device is locked
+    fn bounce_buffer(inner: &mut Inner, len: usize) -> &mut [u8] {
+        let ptr = physical_to_virtual(inner.buffer) as *mut u8;
+        unsafe { core::slice::from_raw_parts_mut(ptr, len.min(BUFFER_SIZE)) }
+    }
+
+    fn transfer(
+        &self,
+        inner: &mut Inner,
+        sector: u64,
+        count: usize,
+        write: bool,
+    ) -> Result<(), &'static str> {
+        if write && self.read_only {
+            return Err("Virtio device is read-only");
+        }
+
+        let kind = if write {
+            RequestType::Out
+        } else {
+            RequestType::In
+        };
+        self.request(inner, kind, sector, count * SECTOR_SIZE)
+    }
+}
+
+impl BlockDevice<Block512> for VirtioBlk {
+    fn block_count(&self) -> storage::FsResult<usize> {
+        dma::block_count(self)
+    }
+
+    fn read_block(&self, offset: usize, block: &mut Block512) -> storage::FsResult {
+        dma::read_block(self, offset, block)
+    }
+
+    fn write_block(&self, offset: usize, block: &Block512) -> storage::FsResult {
+        dma::write_block(self, offset, block)
+    }
+
+    fn read_blocks(&self, offset: usize, blocks: &mut [Block512]) -> storage::FsResult {
+        dma::read_blocks(self, offset, blocks)
+    }
+
+    fn write_blocks(&self, offset: usize, blocks: &[Block512]) -> storage::FsResult {
+        dma::write_blocks(self, offset, blocks)
+    }
+
+    fn flush(&self) -> storage::FsResult {
+        VirtioBlk::flush(self).map_err(|_| storage::DeviceError::WriteError.into())
+    }
+}
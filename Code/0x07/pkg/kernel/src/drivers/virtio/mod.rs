@@ -0,0 +1,350 @@
+//! Virtio over PCI
+//!
+//! Both the legacy transport, with the registers in an I/O BAR, and the
+//! modern one, with the registers found through vendor capabilities in
+//! memory BARs, are supported. The modern one is preferred.
+//!
+//! reference: https://docs.oasis-open.org/virtio/virtio/v1.1/virtio-v1.1.html
+//! reference: https://wiki.osdev.org/Virtio
+
+pub mod blk;
+mod queue;
+
+pub use queue::{Buffer, VirtQueue, MAX_QUEUE_SIZE};
+
+use super::pci::{Bar, PciDevice, CAP_VENDOR_SPECIFIC};
+use crate::memory::physical_to_virtual;
+use x86_64::instructions::port::Port;
+
+/// Vendor id of all virtio devices
+pub const VIRTIO_VENDOR: u16 = 0x1AF4;
+
+/// The device offers the virtio 1.0 interface, only for the modern transport
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// No MSI-X vector is used for the queue or config change
+pub const NO_VECTOR: u16 = 0xFFFF;
+
+bitflags! {
+    /// Device status field
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct DeviceStatus: u8 {
+        const ACKNOWLEDGE        = 1;
+        const DRIVER             = 2;
+        const DRIVER_OK          = 4;
+        const FEATURES_OK        = 8;
+        const DEVICE_NEEDS_RESET = 64;
+        const FAILED             = 128;
+    }
+}
+
+// vendor capability types of the modern transport
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_DEVICE_CFG: u8 = 4;
+
+// registers of the modern common configuration structure
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0C;
+const COMMON_MSIX_CONFIG: usize = 0x10;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_MSIX_VECTOR: usize = 0x1A;
+const COMMON_QUEUE_ENABLE: usize = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1E;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+// registers of the legacy header
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_ADDRESS: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_DEVICE_STATUS: u16 = 0x12;
+const LEGACY_CONFIG_MSIX_VECTOR: u16 = 0x14;
+const LEGACY_QUEUE_MSIX_VECTOR: u16 = 0x16;
+
+/// A memory mapped register block of the modern transport
+#[derive(Debug, Clone, Copy)]
+struct Mmio(u64);
+
+impl Mmio {
+    fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { ((self.0 as usize + offset) as *const T).read_volatile() }
+    }
+
+    fn write<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { ((self.0 as usize + offset) as *mut T).write_volatile(value) }
+    }
+}
+
+#[derive(Debug)]
+enum Transport {
+    Legacy {
+        io_base: u16,
+        /// The device config moves 4 bytes on when MSI-X is enabled
+        msix: bool,
+    },
+    Modern {
+        common: Mmio,
+        notify: Mmio,
+        notify_multiplier: u32,
+        device: Mmio,
+    },
+}
+
+/// The registers of a virtio PCI function
+#[derive(Debug)]
+pub struct VirtioPci {
+    transport: Transport,
+}
+
+impl VirtioPci {
+    /// Find the registers of the function
+    ///
+    /// The modern registers are only used if the BARs holding them are in
+    /// the mapped physical memory, i.e. below 4 GiB.
+    pub fn new(dev: &PciDevice) -> Option<Self> {
+        if let Some(transport) = Self::modern(dev) {
+            return Some(Self { transport });
+        }
+
+        let io_base = dev.bars[0]?.io_port()?;
+        let msix = dev.msix().is_some_and(|msix| msix.is_enabled());
+
+        Some(Self {
+            transport: Transport::Legacy { io_base, msix },
+        })
+    }
+
+    fn modern(dev: &PciDevice) -> Option<Transport> {
+        let mut common = None;
+        let mut notify = None;
+        let mut device = None;
+
+        for cap in dev.capabilities.iter().filter(|cap| cap.id == CAP_VENDOR_SPECIFIC) {
+            let value = dev.addr.read(cap.offset);
+            let cfg_type = (value >> 24) as u8;
+            let bar = dev.addr.read(cap.offset + 4) as u8;
+            let offset = dev.addr.read(cap.offset + 8) as u64;
+
+            let Some(Bar::Memory { addr, size, .. }) = dev.bars.get(bar as usize).copied().flatten()
+            else {
+                continue;
+            };
+
+            if addr + size > u32::MAX as u64 + 1 {
+                debug!("Virtio BAR{} at {:#x} is not mapped", bar, addr);
+                return None;
+            }
+
+            let mmio = Mmio(physical_to_virtual(addr + offset));
+
+            match cfg_type {
+                CAP_COMMON_CFG => common = common.or(Some(mmio)),
+                CAP_NOTIFY_CFG => {
+                    let multiplier = dev.addr.read(cap.offset + 16);
+                    notify = notify.or(Some((mmio, multiplier)));
+                }
+                CAP_DEVICE_CFG => device = device.or(Some(mmio)),
+                _ => {}
+            }
+        }
+
+        let (notify, notify_multiplier) = notify?;
+
+        Some(Transport::Modern {
+            common: common?,
+            notify,
+            notify_multiplier,
+            device: device?,
+        })
+    }
+
+    pub fn is_modern(&self) -> bool {
+        matches!(self.transport, Transport::Modern { .. })
+    }
+
+    /// Reset the device and negotiate the features with it
+    ///
+    /// Returns the features accepted from `wanted`.
+    pub fn init(&mut self, wanted: u64) -> Result<u64, &'static str> {
+        self.set_status(DeviceStatus::empty());
+        self.set_status(DeviceStatus::ACKNOWLEDGE);
+        self.set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+
+        let wanted = if self.is_modern() {
+            wanted | VIRTIO_F_VERSION_1
+        } else {
+            wanted & 0xFFFF_FFFF
+        };
+
+        let features = self.device_features() & wanted;
+        self.set_driver_features(features);
+
+        // the legacy devices do not know about FEATURES_OK
+        if self.is_modern() {
+            let status = DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER;
+            self.set_status(status | DeviceStatus::FEATURES_OK);
+
+            if !self.status().contains(DeviceStatus::FEATURES_OK) {
+                self.set_status(status | DeviceStatus::FAILED);
+                return Err("Features not accepted");
+            }
+        }
+
+        Ok(features)
+    }
+
+    /// Tell the device the driver is ready, after the queues are set up
+    pub fn driver_ok(&mut self) {
+        let status = self.status();
+        self.set_status(status | DeviceStatus::DRIVER_OK);
+    }
+
+    pub fn status(&mut self) -> DeviceStatus {
+        let status = match &self.transport {
+            Transport::Legacy { io_base, .. } => unsafe {
+                Port::<u8>::new(io_base + LEGACY_DEVICE_STATUS).read()
+            },
+            Transport::Modern { common, .. } => common.read(COMMON_DEVICE_STATUS),
+        };
+
+        DeviceStatus::from_bits_truncate(status)
+    }
+
+    pub fn set_status(&mut self, status: DeviceStatus) {
+        match &self.transport {
+            Transport::Legacy { io_base, .. } => unsafe {
+                Port::<u8>::new(io_base + LEGACY_DEVICE_STATUS).write(status.bits())
+            },
+            Transport::Modern { common, .. } => common.write(COMMON_DEVICE_STATUS, status.bits()),
+        }
+    }
+
+    fn device_features(&mut self) -> u64 {
+        match &self.transport {
+            Transport::Legacy { io_base, .. } => unsafe {
+                Port::<u32>::new(io_base + LEGACY_DEVICE_FEATURES).read() as u64
+            },
+            Transport::Modern { common, .. } => {
+                common.write(COMMON_DEVICE_FEATURE_SELECT, 0u32);
+                let low: u32 = common.read(COMMON_DEVICE_FEATURE);
+                common.write(COMMON_DEVICE_FEATURE_SELECT, 1u32);
+                let high: u32 = common.read(COMMON_DEVICE_FEATURE);
+                (high as u64) << 32 | low as u64
+            }
+        }
+    }
+
+    fn set_driver_features(&mut self, features: u64) {
+        match &self.transport {
+            Transport::Legacy { io_base, .. } => unsafe {
+                Port::<u32>::new(io_base + LEGACY_DRIVER_FEATURES).write(features as u32)
+            },
+            Transport::Modern { common, .. } => {
+                common.write(COMMON_DRIVER_FEATURE_SELECT, 0u32);
+                common.write(COMMON_DRIVER_FEATURE, features as u32);
+                common.write(COMMON_DRIVER_FEATURE_SELECT, 1u32);
+                common.write(COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+            }
+        }
+    }
+
+    /// Size to allocate the queue with, 0 if the queue does not exist
+    pub fn queue_size(&mut self, queue: u16) -> u16 {
+        match &self.transport {
+            Transport::Legacy { io_base, .. } => unsafe {
+                Port::<u16>::new(io_base + LEGACY_QUEUE_SELECT).write(queue);
+                Port::<u16>::new(io_base + LEGACY_QUEUE_SIZE).read()
+            },
+            Transport::Modern { common, .. } => {
+                common.write(COMMON_QUEUE_SELECT, queue);
+                common.read::<u16>(COMMON_QUEUE_SIZE).min(MAX_QUEUE_SIZE)
+            }
+        }
+    }
+
+    /// Hand the queue to the device, its interrupts are sent to the
+    /// MSI-X `vector`, which is `NO_VECTOR` if MSI-X is not used.
+    pub fn setup_queue(&mut self, queue: u16, vq: &VirtQueue, vector: u16) -> Result<(), &'static str> {
+        match &self.transport {
+            Transport::Legacy { io_base, msix } => unsafe {
+                Port::<u16>::new(io_base + LEGACY_QUEUE_SELECT).write(queue);
+
+                if *msix {
+                    Port::<u16>::new(io_base + LEGACY_CONFIG_MSIX_VECTOR).write(NO_VECTOR);
+                    Port::<u16>::new(io_base + LEGACY_QUEUE_MSIX_VECTOR).write(vector);
+
+                    if Port::<u16>::new(io_base + LEGACY_QUEUE_MSIX_VECTOR).read() != vector {
+                        return Err("MSI-X vector not accepted");
+                    }
+                }
+
+                // the legacy queue is laid out in consecutive pages
+                let pfn = (vq.desc_addr() / crate::memory::PAGE_SIZE) as u32;
+                Port::<u32>::new(io_base + LEGACY_QUEUE_ADDRESS).write(pfn);
+            },
+            Transport::Modern { common, .. } => {
+                common.write(COMMON_QUEUE_SELECT, queue);
+                common.write(COMMON_QUEUE_SIZE, vq.size());
+                common.write(COMMON_MSIX_CONFIG, NO_VECTOR);
+                common.write(COMMON_QUEUE_MSIX_VECTOR, vector);
+
+                if common.read::<u16>(COMMON_QUEUE_MSIX_VECTOR) != vector {
+                    return Err("MSI-X vector not accepted");
+                }
+
+                common.write(COMMON_QUEUE_DESC, vq.desc_addr());
+                common.write(COMMON_QUEUE_DRIVER, vq.avail_addr());
+                common.write(COMMON_QUEUE_DEVICE, vq.used_addr());
+                common.write(COMMON_QUEUE_ENABLE, 1u16);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tell the device there are new buffers in the queue
+    pub fn notify(&mut self, queue: u16) {
+        match &self.transport {
+            Transport::Legacy { io_base, .. } => unsafe {
+                Port::<u16>::new(io_base + LEGACY_QUEUE_NOTIFY).write(queue)
+            },
+            Transport::Modern {
+                common,
+                notify,
+                notify_multiplier,
+                ..
+            } => {
+                common.write(COMMON_QUEUE_SELECT, queue);
+                let offset: u16 = common.read(COMMON_QUEUE_NOTIFY_OFF);
+                notify.write(offset as usize * *notify_multiplier as usize, queue);
+            }
+        }
+    }
+
+    /// Read a dword of the device specific configuration
+    pub fn config_u32(&mut self, offset: usize) -> u32 {
+        match &self.transport {
+            Transport::Legacy { io_base, msix } => {
+                let base = io_base + if *msix { 0x18 } else { 0x14 };
+                unsafe { Port::<u32>::new(base + offset as u16).read() }
+            }
+            Transport::Modern { device, .. } => device.read(offset),
+        }
+    }
+
+    /// Read a qword of the device specific configuration
+    pub fn config_u64(&mut self, offset: usize) -> u64 {
+        let low = self.config_u32(offset);
+        let high = self.config_u32(offset + 4);
+        (high as u64) << 32 | low as u64
+    }
+}
@@ -0,0 +1,214 @@
+//! Split virtqueue
+//!
+//! The descriptor table, the available ring and the used ring are laid out
+//! in physically contiguous frames as the legacy interface requires, which
+//! also fits the modern one.
+//!
+//! reference: https://docs.oasis-open.org/virtio/virtio/v1.1/cs01/virtio-v1.1-cs01.html#x1-230005
+
+use crate::memory::{get_frame_alloc_for_sure, physical_to_virtual, PAGE_SIZE};
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+
+/// Modern queues are limited to this size even if the device supports more,
+/// the size of legacy queues is fixed by the device.
+pub const MAX_QUEUE_SIZE: u16 = 128;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct DescFlags: u16 {
+        /// The buffer continues in the `next` descriptor
+        const NEXT  = 1;
+        /// The buffer is written by the device
+        const WRITE = 2;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer handed to the device: (physical address, length, written by device)
+pub type Buffer = (u64, u32, bool);
+
+#[derive(Debug)]
+pub struct VirtQueue {
+    size: u16,
+    /// Physical address of the descriptor table
+    desc: u64,
+    /// Physical address of the available ring
+    avail: u64,
+    /// Physical address of the used ring
+    used: u64,
+    /// Descriptors not handed to the device
+    free: Vec<u16>,
+    /// The used ring index seen last
+    last_used: u16,
+}
+
+impl VirtQueue {
+    /// Allocate a queue of `size` entries, which is a power of 2
+    pub fn new(size: u16) -> Option<Self> {
+        if size == 0 || !size.is_power_of_two() {
+            return None;
+        }
+
+        let n = size as u64;
+        let avail_offset = 16 * n;
+        // the used ring is aligned to a page for the legacy interface
+        let used_offset = (avail_offset + 6 + 2 * n).next_multiple_of(PAGE_SIZE);
+        let total = used_offset + 6 + 8 * n;
+        let frames = total.div_ceil(PAGE_SIZE) as usize;
+
+        let desc = get_frame_alloc_for_sure()
+            .allocate_contiguous(frames)?
+            .start_address()
+            .as_u64();
+
+        // the frames are not zeroed by the allocator
+        unsafe {
+            core::ptr::write_bytes(
+                physical_to_virtual(desc) as *mut u8,
+                0,
+                frames * PAGE_SIZE as usize,
+            );
+        }
+
+        Some(Self {
+            size,
+            desc,
+            avail: desc + avail_offset,
+            used: desc + used_offset,
+            free: (0..size).rev().collect(),
+            last_used: 0,
+        })
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn desc_addr(&self) -> u64 {
+        self.desc
+    }
+
+    pub fn avail_addr(&self) -> u64 {
+        self.avail
+    }
+
+    pub fn used_addr(&self) -> u64 {
+        self.used
+    }
+
+    fn descriptor(&self, index: u16) -> *mut Descriptor {
+        (physical_to_virtual(self.desc) as *mut Descriptor).wrapping_add(index as usize)
+    }
+
+    /// The u16 at `index` of a ring, after the flags word
+    fn ring_u16(&self, ring: u64, index: usize) -> *mut u16 {
+        (physical_to_virtual(ring) as *mut u16).wrapping_add(index)
+    }
+
+    /// Chain the buffers into descriptors and make them available,
+    /// the device must still be notified.
+    ///
+    /// Returns the head of the chain, or `None` if the queue is full.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.free.len() {
+            return None;
+        }
+
+        let indices: Vec<u16> = (0..buffers.len())
+            .map(|_| self.free.pop().unwrap())
+            .collect();
+
+        for (i, &(addr, len, writable)) in buffers.iter().enumerate() {
+            let mut flags = DescFlags::empty();
+            if writable {
+                flags |= DescFlags::WRITE;
+            }
+
+            let next = indices.get(i + 1).copied();
+            if next.is_some() {
+                flags |= DescFlags::NEXT;
+            }
+
+            let desc = Descriptor {
+                addr,
+                len,
+                flags: flags.bits(),
+                next: next.unwrap_or(0),
+            };
+
+            unsafe { self.descriptor(indices[i]).write_volatile(desc) };
+        }
+
+        let head = indices[0];
+
+        unsafe {
+            // avail ring: flags, idx, ring[size], used_event
+            let idx = self.ring_u16(self.avail, 1).read_volatile();
+            self.ring_u16(self.avail, 2 + (idx % self.size) as usize)
+                .write_volatile(head);
+
+            // the device must see the entry before the index
+            fence(Ordering::SeqCst);
+            self.ring_u16(self.avail, 1)
+                .write_volatile(idx.wrapping_add(1));
+        }
+
+        fence(Ordering::SeqCst);
+
+        Some(head)
+    }
+
+    /// Returns true if the device has returned a chain
+    pub fn has_used(&self) -> bool {
+        fence(Ordering::SeqCst);
+        let idx = unsafe { self.ring_u16(self.used, 1).read_volatile() };
+        idx != self.last_used
+    }
+
+    /// Take a chain returned by the device and free its descriptors
+    ///
+    /// Returns (head of the chain, bytes written by the device).
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+
+        let slot = (self.last_used % self.size) as usize;
+        let elem = unsafe {
+            // used ring: flags, idx, then the 8-byte elements
+            let ring = (physical_to_virtual(self.used) as *const u8).add(4) as *const UsedElem;
+            ring.add(slot).read_volatile()
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = elem.id as u16;
+        let mut index = head;
+        loop {
+            let desc = unsafe { self.descriptor(index).read_volatile() };
+            self.free.push(index);
+
+            if desc.flags & DescFlags::NEXT.bits() == 0 {
+                break;
+            }
+            index = desc.next;
+        }
+
+        Some((head, elem.len))
+    }
+}
@@ -9,11 +9,15 @@
 //! Reference: [OSDev Wiki](https://wiki.osdev.org/APIC)
 
 pub use ioapic::{IoApic, IOAPIC_ADDR};
+pub use x2apic::X2Apic;
 pub use xapic::{XApic, LAPIC_ADDR};
 
 mod ioapic;
+mod x2apic;
 mod xapic;
 
+use core::sync::atomic::{AtomicU32, Ordering};
+
 pub trait LocalApic {
     /// If this type APIC is supported
     fn support() -> bool;
@@ -33,4 +37,161 @@ pub trait LocalApic {
 
     /// Acknowledge interrupt on the current CPU
     fn eoi(&mut self);
+
+    /// Start the timer counting down from `count`, the timer interrupt is
+    /// raised every time it reaches 0 if `periodic`, only once otherwise
+    ///
+    /// The timer counts at the bus frequency divided by 16.
+    fn set_timer(&mut self, count: u32, periodic: bool);
+
+    /// Current count of the timer
+    fn timer_count(&self) -> u32;
+
+    /// Send an IPI to the CPU with LAPIC ID `dest`, `command` is the low
+    /// half of the ICR
+    fn send_ipi(&mut self, dest: u32, command: u32) {
+        self.set_icr((dest as u64) << 56 | command as u64);
+    }
+
+    /// Send an IPI to every CPU but this one
+    fn broadcast_ipi(&mut self, command: u32) {
+        self.set_icr((ICR_ALL_OTHERS | command) as u64);
+    }
+}
+
+/// The local APIC of the current CPU, in whichever mode was chosen at init
+pub enum Lapic {
+    XApic(XApic),
+    X2Apic(X2Apic),
+}
+
+impl LocalApic for Lapic {
+    fn support() -> bool {
+        XApic::support() || X2Apic::support()
+    }
+
+    fn cpu_init(&mut self) {
+        match self {
+            Lapic::XApic(lapic) => lapic.cpu_init(),
+            Lapic::X2Apic(lapic) => lapic.cpu_init(),
+        }
+    }
+
+    fn id(&self) -> u32 {
+        match self {
+            Lapic::XApic(lapic) => lapic.id(),
+            Lapic::X2Apic(lapic) => lapic.id(),
+        }
+    }
+
+    fn version(&self) -> u32 {
+        match self {
+            Lapic::XApic(lapic) => lapic.version(),
+            Lapic::X2Apic(lapic) => lapic.version(),
+        }
+    }
+
+    fn icr(&self) -> u64 {
+        match self {
+            Lapic::XApic(lapic) => lapic.icr(),
+            Lapic::X2Apic(lapic) => lapic.icr(),
+        }
+    }
+
+    fn set_icr(&mut self, value: u64) {
+        match self {
+            Lapic::XApic(lapic) => lapic.set_icr(value),
+            Lapic::X2Apic(lapic) => lapic.set_icr(value),
+        }
+    }
+
+    fn eoi(&mut self) {
+        match self {
+            Lapic::XApic(lapic) => lapic.eoi(),
+            Lapic::X2Apic(lapic) => lapic.eoi(),
+        }
+    }
+
+    fn set_timer(&mut self, count: u32, periodic: bool) {
+        match self {
+            Lapic::XApic(lapic) => lapic.set_timer(count, periodic),
+            Lapic::X2Apic(lapic) => lapic.set_timer(count, periodic),
+        }
+    }
+
+    fn timer_count(&self) -> u32 {
+        match self {
+            Lapic::XApic(lapic) => lapic.timer_count(),
+            Lapic::X2Apic(lapic) => lapic.timer_count(),
+        }
+    }
+
+    fn send_ipi(&mut self, dest: u32, command: u32) {
+        match self {
+            Lapic::XApic(lapic) => lapic.send_ipi(dest, command),
+            Lapic::X2Apic(lapic) => lapic.send_ipi(dest, command),
+        }
+    }
+
+    fn broadcast_ipi(&mut self, command: u32) {
+        match self {
+            Lapic::XApic(lapic) => lapic.broadcast_ipi(command),
+            Lapic::X2Apic(lapic) => lapic.broadcast_ipi(command),
+        }
+    }
+}
+
+/// Delivery modes and flags of the low half of the ICR
+pub const ICR_INIT: u32 = 0b101 << 8;
+pub const ICR_STARTUP: u32 = 0b110 << 8;
+pub const ICR_ASSERT: u32 = 1 << 14;
+pub const ICR_LEVEL: u32 = 1 << 15;
+const ICR_ALL_OTHERS: u32 = 0b11 << 18;
+
+/// Initial count of the timer found by `init_timer`, the same for all CPUs
+static TIMER_INIT_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Scheduler frequency when the one configured is out of range
+const DEFAULT_TIMER_HZ: u64 = 100;
+/// Length of the calibration against the PIT
+const CALIBRATION_US: u64 = 10_000;
+
+/// Calibrate the timer of `lapic` and the TSC against the PIT, then start
+/// the timer interrupt `hz` times a second and the monotonic clock
+pub fn init_timer(lapic: &mut impl LocalApic, hz: u64) {
+    let hz = if (1..=10_000).contains(&hz) {
+        hz
+    } else {
+        warn!("Invalid timer frequency {} Hz, using {} Hz", hz, DEFAULT_TIMER_HZ);
+        DEFAULT_TIMER_HZ
+    };
+
+    let tsc_start = unsafe { core::arch::x86_64::_rdtsc() };
+    // never reaches 0 meanwhile, no interrupt is raised
+    lapic.set_timer(u32::MAX, false);
+    crate::drivers::pit::wait_us(CALIBRATION_US);
+    let counted = u32::MAX - lapic.timer_count();
+    let tsc_end = unsafe { core::arch::x86_64::_rdtsc() };
+
+    let timer_hz = counted as u64 * 1_000_000 / CALIBRATION_US;
+    let tsc_hz = (tsc_end - tsc_start) * 1_000_000 / CALIBRATION_US;
+    let count = (timer_hz / hz).clamp(1, u32::MAX as u64) as u32;
+
+    crate::utils::time::init(hz, tsc_hz);
+    TIMER_INIT_COUNT.store(count, Ordering::Relaxed);
+    lapic.set_timer(count, true);
+
+    info!(
+        "LAPIC timer: {} kHz, {} Hz with count {}, TSC: {} MHz",
+        timer_hz / 1_000,
+        hz,
+        count,
+        tsc_hz / 1_000_000
+    );
+}
+
+/// Start the timer of an application processor as calibrated on the
+/// bootstrap one
+pub fn start_timer(lapic: &mut impl LocalApic) {
+    lapic.set_timer(TIMER_INIT_COUNT.load(Ordering::Relaxed), true);
 }
@@ -0,0 +1,158 @@
+use super::LocalApic;
+use core::fmt::{Debug, Error, Formatter};
+use x86::cpuid::CpuId;
+use x86_64::registers::model_specific::Msr;
+
+/// IA32_APIC_BASE, where the x2APIC mode is turned on
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_EXTD: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// The IA32_X2APIC_* MSRs are at 0x800 plus the xAPIC register offset / 16
+const MSR_BASE: u32 = 0x800;
+
+const REG_ID: u32 = 0x0020;
+const REG_VERSION: u32 = 0x0030;
+const REG_EOI: u32 = 0x00B0;
+const REG_SVR: u32 = 0x00F0;
+const REG_ESR: u32 = 0x0280;
+/// One 64-bit register in x2APIC mode, the destination is in the high half
+const REG_ICR: u32 = 0x0300;
+const REG_LVT_TIMER: u32 = 0x0320;
+const REG_LVT_PERF: u32 = 0x0340;
+const REG_LVT_LINT0: u32 = 0x0350;
+const REG_LVT_LINT1: u32 = 0x0360;
+const REG_LVT_ERROR: u32 = 0x0370;
+const REG_TIMER_INIT_CNT: u32 = 0x0380;
+const REG_TIMER_CUR_CNT: u32 = 0x0390;
+const REG_TIMER_DIV: u32 = 0x03E0;
+
+const APIC_ENABLE: u64 = 1 << 8;
+const MASKED: u64 = 1 << 16;
+const TIMER_PERIODIC: u64 = 1 << 17;
+/// Divide the bus frequency by 16
+const TIMER_DIV_16: u64 = 0b0011;
+
+const IRQ_BASE: u64 = 32;
+const IRQ_TIMER: u64 = 0;
+const IRQ_ERROR: u64 = 19;
+const IRQ_SPURIOUS: u64 = 31;
+
+/// The local APIC in x2APIC mode, its registers are MSRs and need no
+/// mapping
+pub struct X2Apic;
+
+impl X2Apic {
+    pub unsafe fn new() -> Self {
+        X2Apic
+    }
+
+    unsafe fn read(&self, reg: u32) -> u64 {
+        unsafe { Msr::new(MSR_BASE + (reg >> 4)).read() }
+    }
+
+    unsafe fn write(&mut self, reg: u32, value: u64) {
+        unsafe { Msr::new(MSR_BASE + (reg >> 4)).write(value) }
+    }
+}
+
+impl LocalApic for X2Apic {
+    /// If this type APIC is supported
+    fn support() -> bool {
+        CpuId::new()
+            .get_feature_info()
+            .map(|f| f.has_x2apic())
+            .unwrap_or(false)
+    }
+
+    /// Switch the LAPIC of the current CPU to x2APIC mode and initialize it
+    fn cpu_init(&mut self) {
+        unsafe {
+            // 1. 切换到 x2APIC 模式，之后寄存器只能通过 MSR 访问
+            // 从禁用状态不能直接进入 x2APIC 模式，要先启用 xAPIC
+            let mut base = Msr::new(IA32_APIC_BASE);
+            let mut value = base.read();
+            if value & APIC_BASE_ENABLE == 0 {
+                value |= APIC_BASE_ENABLE;
+                base.write(value);
+            }
+            base.write(value | APIC_BASE_EXTD);
+
+            // 2. 启用本地 APIC 并设置虚假中断向量
+            let svr = self.read(REG_SVR) & !0xFF;
+            self.write(REG_SVR, svr | APIC_ENABLE | (IRQ_BASE + IRQ_SPURIOUS));
+
+            // 3. 配置定时器 - 先屏蔽并停止，校准后由 set_timer 启动
+            self.write(REG_LVT_TIMER, MASKED | (IRQ_BASE + IRQ_TIMER));
+            self.write(REG_TIMER_DIV, TIMER_DIV_16);
+            self.write(REG_TIMER_INIT_CNT, 0);
+
+            // 4. 禁用 LINT0, LINT1 与性能计数器溢出中断
+            self.write(REG_LVT_LINT0, MASKED);
+            self.write(REG_LVT_LINT1, MASKED);
+            self.write(REG_LVT_PERF, MASKED);
+
+            // 5. 映射错误中断，并清除错误状态
+            let lvt_error = self.read(REG_LVT_ERROR) & !0xFF;
+            self.write(REG_LVT_ERROR, lvt_error | (IRQ_BASE + IRQ_ERROR));
+            self.write(REG_ESR, 0);
+
+            // 6. 确认未处理的中断
+            // x2APIC 不支持 INIT Level De-Assert，也不需要它来同步仲裁 ID
+            self.eoi();
+        }
+    }
+
+    fn id(&self) -> u32 {
+        // the whole register, not only the top byte as in xAPIC mode
+        unsafe { self.read(REG_ID) as u32 }
+    }
+
+    fn version(&self) -> u32 {
+        unsafe { self.read(REG_VERSION) as u32 }
+    }
+
+    fn icr(&self) -> u64 {
+        unsafe { self.read(REG_ICR) }
+    }
+
+    fn set_icr(&mut self, value: u64) {
+        // a single write sends the IPI, there is no delivery status to wait on
+        unsafe {
+            self.write(REG_ICR, value);
+        }
+    }
+
+    fn eoi(&mut self) {
+        unsafe {
+            self.write(REG_EOI, 0);
+        }
+    }
+
+    fn set_timer(&mut self, count: u32, periodic: bool) {
+        let mode = if periodic { TIMER_PERIODIC } else { 0 };
+        unsafe {
+            self.write(REG_LVT_TIMER, mode | (IRQ_BASE + IRQ_TIMER));
+            // writing the initial count starts the countdown
+            self.write(REG_TIMER_INIT_CNT, count as u64);
+        }
+    }
+
+    fn timer_count(&self) -> u32 {
+        unsafe { self.read(REG_TIMER_CUR_CNT) as u32 }
+    }
+
+    fn send_ipi(&mut self, dest: u32, command: u32) {
+        self.set_icr((dest as u64) << 32 | command as u64);
+    }
+}
+
+impl Debug for X2Apic {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_struct("X2apic")
+            .field("id", &self.id())
+            .field("version", &self.version())
+            .field("icr", &self.icr())
+            .finish()
+    }
+}
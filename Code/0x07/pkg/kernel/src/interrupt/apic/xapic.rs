@@ -7,6 +7,19 @@ use x86::cpuid::CpuId;
 /// Default physical address of xAPIC
 pub const LAPIC_ADDR: u64 = 0xFEE00000;
 
+const REG_LVT_TIMER: u32 = 0x0320;
+const REG_TIMER_INIT_CNT: u32 = 0x0380;
+const REG_TIMER_CUR_CNT: u32 = 0x0390;
+const REG_TIMER_DIV: u32 = 0x03E0;
+
+const MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide the bus frequency by 16
+const TIMER_DIV_16: u32 = 0b0011;
+
+const IRQ_BASE: u32 = 32;
+const IRQ_TIMER: u32 = 0;
+
 pub struct XApic {
     addr: u64,
 }
@@ -45,28 +58,21 @@ impl LocalApic for XApic {
         const REG_ESR: u32 = 0x0280;
         const REG_ICR_LOW: u32 = 0x0300;
         const REG_ICR_HIGH: u32 = 0x0310;
-        const REG_LVT_TIMER: u32 = 0x0320;
         const REG_LVT_PERF: u32 = 0x0340;
         const REG_LVT_LINT0: u32 = 0x0350;
         const REG_LVT_LINT1: u32 = 0x0360;
         const REG_LVT_ERROR: u32 = 0x0370;
-        const REG_TIMER_INIT_CNT: u32 = 0x0380;
-        const REG_TIMER_DIV: u32 = 0x03E0;
         const REG_SVR: u32 = 0x00F0;
 
         // 定义配置位常量
         const APIC_ENABLE: u32 = 1 << 8;
-        const MASKED: u32 = 1 << 16;
-        const TIMER_PERIODIC: u32 = 1 << 17;
         const BCAST: u32 = 1 << 19; // 广播到所有处理器
         const INIT: u32 = 5 << 8;   // INIT De-assert 模式
         const TMLV: u32 = 1 << 15;  // TM=1, LV=0
         const DS: u32 = 1 << 12;    // 传递状态位
 
         // 假设的中断向量常量 - 实际项目中应使用真实定义
-        const IRQ_BASE: u32 = 32;
         const IRQ_SPURIOUS: u32 = 31;
-        const IRQ_TIMER: u32 = 0;
         const IRQ_ERROR: u32 = 19;
 
         unsafe {
@@ -77,18 +83,11 @@ impl LocalApic for XApic {
             svr |= IRQ_BASE + IRQ_SPURIOUS;
             self.write(REG_SVR, svr);
 
-            // 2. 配置定时器 - 周期模式
-            let mut lvt_timer = self.read(REG_LVT_TIMER);
-            lvt_timer &= !0xFF;  // 清除向量字段
-            lvt_timer |= IRQ_BASE + IRQ_TIMER;
-            lvt_timer &= !MASKED;  // 清除屏蔽位
-            lvt_timer |= TIMER_PERIODIC;  // 设置周期模式
-            self.write(REG_LVT_TIMER, lvt_timer);
-            
-            // 设置分频系数为 1
-            self.write(REG_TIMER_DIV, 0b1011);
-            // 设置初始计数值
-            self.write(REG_TIMER_INIT_CNT, 0x40000);
+            // 2. 配置定时器 - 先屏蔽并停止，校准后由 set_timer 启动
+            self.write(REG_LVT_TIMER, MASKED | (IRQ_BASE + IRQ_TIMER));
+            // 设置分频系数为 16
+            self.write(REG_TIMER_DIV, TIMER_DIV_16);
+            self.write(REG_TIMER_INIT_CNT, 0);
 
             // 3. 禁用逻辑中断线 LINT0, LINT1
             self.write(REG_LVT_LINT0, MASKED);
@@ -146,6 +145,19 @@ impl LocalApic for XApic {
             self.write(0x00B0, 0);
         }
     }
+
+    fn set_timer(&mut self, count: u32, periodic: bool) {
+        let mode = if periodic { TIMER_PERIODIC } else { 0 };
+        unsafe {
+            self.write(REG_LVT_TIMER, mode | (IRQ_BASE + IRQ_TIMER));
+            // writing the initial count starts the countdown
+            self.write(REG_TIMER_INIT_CNT, count);
+        }
+    }
+
+    fn timer_count(&self) -> u32 {
+        unsafe { self.read(REG_TIMER_CUR_CNT) }
+    }
 }
 
 impl Debug for XApic {
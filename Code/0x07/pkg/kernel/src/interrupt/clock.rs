@@ -15,6 +15,11 @@ pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
 
 // 实际的时钟中断处理逻辑
 pub extern "C" fn clock(mut context: ProcessContext) {
+    // 推进单调时钟，每个 CPU 都有自己的定时器，只由 BSP 计时
+    if crate::proc::processor::is_bsp() {
+        crate::utils::time::tick();
+    }
+
     // 在这里调用进程切换函数
     crate::proc::switch(&mut context);
     
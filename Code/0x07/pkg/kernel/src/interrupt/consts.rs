@@ -27,6 +27,10 @@ pub enum Interrupts {
 
     IrqBase = 0x20,
     Syscall = 0x80,
+
+    /// Inter-processor interrupts
+    Reschedule = 0xF0,
+    TlbShootdown = 0xF1,
 }
 
 /// https://www.computerhope.com/jargon/i/irq.htm
@@ -45,4 +49,10 @@ pub enum Irq {
     Ide1 = 15,
     Error = 19,
     Spurious = 31,
+    /// Message signaled, not routed through the IOAPIC
+    VirtioBlk = 32,
+    /// Message signaled, not routed through the IOAPIC
+    Ahci = 33,
+    /// Message signaled, not routed through the IOAPIC
+    Nvme = 34,
 }
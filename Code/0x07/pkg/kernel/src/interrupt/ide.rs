@@ -0,0 +1,19 @@
+use super::consts::*;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
+    idt[Interrupts::IrqBase as u8 + Irq::Ide0 as u8]
+        .set_handler_fn(ide0_handler);
+    idt[Interrupts::IrqBase as u8 + Irq::Ide1 as u8]
+        .set_handler_fn(ide1_handler);
+}
+
+pub extern "x86-interrupt" fn ide0_handler(_st: InterruptStackFrame) {
+    crate::drivers::ata::handle_irq(0);
+    super::ack();
+}
+
+pub extern "x86-interrupt" fn ide1_handler(_st: InterruptStackFrame) {
+    crate::drivers::ata::handle_irq(1);
+    super::ack();
+}
@@ -0,0 +1,106 @@
+//! Inter-processor interrupts
+//!
+//! A reschedule IPI makes an idle CPU look at the ready queue right away,
+//! instead of at its next timer interrupt.
+//!
+//! A TLB shootdown IPI makes the other CPUs drop their TLB once pages they
+//! may use have been unmapped. Each shootdown has a generation, the sender
+//! waits until every other CPU has flushed for it. A CPU waiting for its
+//! own turn to send answers the pending shootdown meanwhile, so two
+//! senders never wait for each other.
+
+use super::consts::*;
+use super::{lapic, LocalApic};
+use crate::memory::gdt::TIMER_IST_INDEX;
+use crate::proc::{processor, ProcessContext};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, Once};
+use x86_64::instructions::{interrupts, tlb};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// Held while a shootdown waits for the answers
+static SHOOTDOWN: Mutex<()> = Mutex::new(());
+/// Generation of the last shootdown sent
+static SHOOTDOWN_GEN: AtomicUsize = AtomicUsize::new(0);
+/// Last generation each CPU flushed its TLB for, indexed by local APIC ID
+static SHOOTDOWN_ACKED: Once<Vec<AtomicUsize>> = Once::new();
+
+pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        // 与时钟中断一样切换进程，使用同一个栈
+        idt[Interrupts::Reschedule as u8]
+            .set_handler_fn(reschedule_ipi_handler)
+            .set_stack_index(TIMER_IST_INDEX);
+    }
+    idt[Interrupts::TlbShootdown as u8].set_handler_fn(tlb_shootdown_handler);
+}
+
+pub extern "C" fn reschedule_ipi(mut context: ProcessContext) {
+    crate::proc::switch(&mut context);
+    super::ack();
+}
+
+as_handler!(reschedule_ipi);
+
+pub extern "x86-interrupt" fn tlb_shootdown_handler(_sf: InterruptStackFrame) {
+    answer_shootdown();
+    super::ack();
+}
+
+fn shootdown_acked() -> &'static [AtomicUsize] {
+    SHOOTDOWN_ACKED.call_once(|| {
+        (0..processor::count())
+            .map(|_| AtomicUsize::new(0))
+            .collect()
+    })
+}
+
+/// Flush the TLB of the current CPU if a shootdown is pending for it
+fn answer_shootdown() {
+    let acked = &shootdown_acked()[processor::apic_id()];
+    // 先读取代数再刷新，确认的代数不会超过实际刷新时的代数
+    let generation = SHOOTDOWN_GEN.load(Ordering::Acquire);
+    if acked.load(Ordering::Relaxed) != generation {
+        tlb::flush_all();
+        acked.store(generation, Ordering::Release);
+    }
+}
+
+/// Make the CPU with LAPIC ID `cpu` switch to the next ready process
+pub fn reschedule(cpu: u32) {
+    lapic().send_ipi(cpu, Interrupts::Reschedule as u32);
+}
+
+/// Flush the TLB of the other CPUs, after pages of an address space they
+/// may run have been unmapped
+pub fn tlb_shootdown() {
+    let others = processor::online_count().saturating_sub(1);
+    if others == 0 {
+        return;
+    }
+
+    interrupts::without_interrupts(|| {
+        // 等待时回应正在进行的 shootdown，它的发送者在等这个 CPU
+        let _guard = loop {
+            if let Some(guard) = SHOOTDOWN.try_lock() {
+                break guard;
+            }
+            answer_shootdown();
+            core::hint::spin_loop();
+        };
+
+        // 之后上线的 CPU 还没有运行过用户进程，不用等它
+        let others = processor::online_others();
+        let generation = SHOOTDOWN_GEN.fetch_add(1, Ordering::AcqRel) + 1;
+        lapic().broadcast_ipi(Interrupts::TlbShootdown as u32);
+
+        let acked = shootdown_acked();
+        while others
+            .iter()
+            .any(|&cpu| acked[cpu].load(Ordering::Acquire) != generation)
+        {
+            core::hint::spin_loop();
+        }
+    });
+}
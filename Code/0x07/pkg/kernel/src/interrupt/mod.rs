@@ -2,12 +2,25 @@ mod apic;
 mod consts;
 pub mod clock;
 mod serial;  // 添加 serial 模块
+mod keyboard; // PS/2 键盘中断
+mod rtc;      // 实时时钟中断
 mod exceptions;
+mod ide;      // 硬盘中断
+mod virtio;   // virtio 设备中断
+mod ahci;     // AHCI 控制器中断
+mod nvme;     // NVMe 控制器中断
+mod ipi;      // 处理器间中断
+pub mod smp;  // 启动其他 CPU
 pub mod syscall;
 
 use apic::*;
+pub use consts::Irq;
+pub use ipi::{reschedule, tlb_shootdown};
 use x86_64::structures::idt::InterruptDescriptorTable;
+use crate::drivers::acpi::{AcpiInfo, Madt};
 use crate::memory::physical_to_virtual;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Once;
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
@@ -16,28 +29,75 @@ lazy_static! {
             exceptions::register_idt(&mut idt);
             clock::register_idt(&mut idt);
             serial::register_idt(&mut idt);  // 注册串口中断
+            keyboard::register_idt(&mut idt); // 注册键盘中断
+            rtc::register_idt(&mut idt);     // 注册实时时钟中断
+            ide::register_idt(&mut idt);     // 注册硬盘中断
+            virtio::register_idt(&mut idt);  // 注册 virtio 设备中断
+            ahci::register_idt(&mut idt);    // 注册 AHCI 控制器中断
+            nvme::register_idt(&mut idt);    // 注册 NVMe 控制器中断
             syscall::register_idt(&mut idt); // 注册系统调用中断
+            ipi::register_idt(&mut idt);     // 注册处理器间中断
         }
         idt
     };
 }
 
-/// init interrupts system
-pub fn init() {
+/// The LAPIC and IOAPICs and the wiring of the ISA IRQs, from the ACPI
+/// MADT if there is one
+static MADT: Once<Option<&'static Madt>> = Once::new();
+
+fn madt() -> Option<&'static Madt> {
+    MADT.get().copied().flatten()
+}
+
+/// Whether the LAPICs are used in x2APIC mode, chosen by the bootstrap
+/// processor for all CPUs
+static X2APIC: AtomicBool = AtomicBool::new(false);
+
+/// The LAPIC of the current CPU
+fn lapic() -> Lapic {
+    if X2APIC.load(Ordering::Relaxed) {
+        return Lapic::X2Apic(unsafe { X2Apic::new() });
+    }
+
+    let addr = madt().map_or(LAPIC_ADDR, |madt| madt.local_apic_addr);
+    Lapic::XApic(unsafe { XApic::new(physical_to_virtual(addr)) })
+}
+
+/// init interrupts system, the timer interrupt comes `timer_hz` times a second
+pub fn init(timer_hz: u64, acpi: Option<&'static AcpiInfo>) {
     IDT.load();
 
-    // 初始化APIC
-    if XApic::support() {
-        let mut lapic = unsafe { XApic::new(physical_to_virtual(LAPIC_ADDR)) };
+    MADT.call_once(|| acpi.and_then(|acpi| acpi.madt.as_ref()));
+    if madt().is_none() {
+        warn!("No MADT, using the default LAPIC and IOAPIC addresses");
+    }
+
+    // 初始化APIC，支持时优先使用 x2APIC 模式
+    X2APIC.store(X2Apic::support(), Ordering::Relaxed);
+    if Lapic::support() {
+        let mut lapic = lapic();
         lapic.cpu_init();
-        
-        // 启用计时器中断
-        enable_irq(consts::Irq::Timer as u8, lapic.id() as u8);
-        
+
+        // 校准并启动计时器，LAPIC 定时器不经过 IOAPIC
+        init_timer(&mut lapic, timer_hz);
+
         // 启用串口中断
-        enable_irq(consts::Irq::Serial0 as u8, lapic.id() as u8);
+        enable_irq(consts::Irq::Serial0 as u8, lapic.id());
+        enable_irq(consts::Irq::Serial1 as u8, lapic.id());
+
+        // 启用键盘中断
+        enable_irq(consts::Irq::Keyboard as u8, lapic.id());
+
+        // 启用实时时钟中断
+        enable_irq(consts::Irq::RealTimeClock as u8, lapic.id());
+
+        // 启用硬盘中断
+        enable_irq(consts::Irq::Ide0 as u8, lapic.id());
+        enable_irq(consts::Irq::Ide1 as u8, lapic.id());
         
-        info!("APIC initialized.");
+        let mode = if X2APIC.load(Ordering::Relaxed) { "x2APIC" } else { "xAPIC" };
+        info!("APIC initialized in {} mode.", mode);
     } else {
         warn!("APIC not supported!");
     }
@@ -45,14 +105,57 @@ pub fn init() {
     info!("Interrupts Initialized.");
 }
 
-#[inline(always)]
-pub fn enable_irq(irq: u8, cpuid: u8) {
-    let mut ioapic = unsafe { IoApic::new(physical_to_virtual(IOAPIC_ADDR)) };
-    ioapic.enable(irq, cpuid);
+/// The destination of an interrupt sent to the CPU with LAPIC ID `cpuid`
+///
+/// The IOAPIC and MSI only hold 8-bit destinations, the x2APIC IDs beyond
+/// would need interrupt remapping, so they cannot be reached.
+fn irq_destination(cpuid: u32) -> Option<u8> {
+    u8::try_from(cpuid).ok()
+}
+
+/// Route ISA `irq` to the CPU with LAPIC ID `cpuid`, through the IOAPIC
+/// its interrupt source override says
+///
+/// The IRQ is left masked if the CPU cannot be reached.
+pub fn enable_irq(irq: u8, cpuid: u32) {
+    let Some(cpuid) = irq_destination(cpuid) else {
+        warn!("IRQ {} cannot reach LAPIC ID {} without interrupt remapping", irq, cpuid);
+        return;
+    };
+    let Some(madt) = madt() else {
+        let mut ioapic = unsafe { IoApic::new(physical_to_virtual(IOAPIC_ADDR)) };
+        ioapic.enable(irq, cpuid);
+        return;
+    };
+
+    let wiring = madt.isa_irq(irq);
+    let Some(entry) = madt.io_apic_for(wiring.gsi) else {
+        warn!("No IOAPIC for IRQ {} (GSI {})", irq, wiring.gsi);
+        return;
+    };
+
+    let mut ioapic = unsafe { IoApic::new(physical_to_virtual(entry.addr)) };
+    ioapic.route(
+        (wiring.gsi - entry.gsi_base) as u8,
+        consts::Interrupts::IrqBase as u8 + irq,
+        cpuid,
+        wiring.active_low,
+        wiring.level_triggered,
+    );
+}
+
+/// (local APIC id, vector) a message signaled interrupt is sent to,
+/// which is the current CPU
+///
+/// None if the CPU cannot be reached, the device is polled then.
+pub fn msi_target(irq: Irq) -> Option<(u8, u8)> {
+    let apic_id = irq_destination(lapic().id())?;
+    Some((apic_id, consts::Interrupts::IrqBase as u8 + irq as u8))
 }
 
 #[inline(always)]
 pub fn ack() {
-    let mut lapic = unsafe { XApic::new(physical_to_virtual(LAPIC_ADDR)) };
-    lapic.eoi();
+    // 中断到来的时间作为熵
+    crate::drivers::random::add_interrupt_timing();
+    lapic().eoi();
 }
@@ -0,0 +1,12 @@
+use super::consts::*;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
+    idt[Interrupts::IrqBase as u8 + Irq::Nvme as u8]
+        .set_handler_fn(nvme_handler);
+}
+
+pub extern "x86-interrupt" fn nvme_handler(_st: InterruptStackFrame) {
+    crate::drivers::nvme::handle_irq();
+    super::ack();
+}
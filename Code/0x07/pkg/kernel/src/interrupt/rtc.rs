@@ -0,0 +1,12 @@
+use super::consts::*;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
+    idt[Interrupts::IrqBase as u8 + Irq::RealTimeClock as u8]
+        .set_handler_fn(rtc_handler);
+}
+
+pub extern "x86-interrupt" fn rtc_handler(_st: InterruptStackFrame) {
+    crate::drivers::rtc::handle_irq();
+    super::ack();
+}
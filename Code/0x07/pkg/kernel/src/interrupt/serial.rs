@@ -1,78 +1,114 @@
 use super::consts::*;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use crate::drivers::tty::Tty;
+use crate::drivers::serial::{wait_serial, wait_serial2};
 use crate::drivers::uart16550::SerialPort;
 use core::str;
+use spin::{Mutex, MutexGuard};
 
+/// Bytes of a UTF-8 sequence received so far
+struct Utf8Buffer {
+    buf: [u8; 4],
+    len: usize,
+}
 
-// Static buffer for UTF-8 decoding. Unsafe in concurrent contexts.
-static mut UTF8_BUF: [u8; 4] = [0; 4];
-static mut UTF8_LEN: usize = 0;
+impl Utf8Buffer {
+    const fn new() -> Self {
+        Self { buf: [0; 4], len: 0 }
+    }
+}
 
-use crate::drivers::input;
+// 每个串口各自的 UTF-8 解码缓冲区, 只在中断处理中使用
+static COM1_UTF8: Mutex<Utf8Buffer> = Mutex::new(Utf8Buffer::new());
+static COM2_UTF8: Mutex<Utf8Buffer> = Mutex::new(Utf8Buffer::new());
 
 pub unsafe fn register_idt(idt: &mut InterruptDescriptorTable) {
     idt[Interrupts::IrqBase as u8 + Irq::Serial0 as u8]
         .set_handler_fn(serial_handler);
+    idt[Interrupts::IrqBase as u8 + Irq::Serial1 as u8]
+        .set_handler_fn(serial1_handler);
 }
 
 pub extern "x86-interrupt" fn serial_handler(_st: InterruptStackFrame) {
-    receive();
+    receive(wait_serial, &COM1_UTF8, Tty::Console);
     super::ack();
 }
 
-/// 从串口接收字符并放入输入缓冲区
+pub extern "x86-interrupt" fn serial1_handler(_st: InterruptStackFrame) {
+    receive(wait_serial2, &COM2_UTF8, Tty::Serial1);
+    super::ack();
+}
+
+/// 发送缓冲区中的数据, 从串口接收字符并交给终端处理
 /// 在每次中断时调用
 /// Handles UTF-8 decoding.
-fn receive() {
-    // 创建串口实例
-    let mut serial = SerialPort::<0x3F8>::new();
-    
-    while let Some(byte) = serial.receive() {
-        // 注释掉这个日志，避免输入时的干扰
-        // trace!("Serial received byte: {:#02x}", byte);
-        unsafe {
-            const UTF8_BUF_SIZE: usize = 4; // Use a constant for buffer size
-            if UTF8_LEN >= UTF8_BUF_SIZE {
-                // Buffer full, but no valid char yet. This indicates an error or
-                // a character longer than 4 bytes (which shouldn't happen with standard UTF-8).
-                // Push replacement char and reset.
-                input::push_char('\u{FFFD}');
-                UTF8_LEN = 0;
-                // Try processing the current byte as the start of a new sequence
+fn receive<const BASE_ADDR: u16>(
+    serial: fn() -> Option<MutexGuard<'static, SerialPort<BASE_ADDR>>>,
+    utf8: &Mutex<Utf8Buffer>,
+    tty: Tty,
+) {
+    let mut utf8 = utf8.lock();
+
+    loop {
+        // 持有串口锁时只读取字节, 回显时终端还要使用串口
+        let mut bytes = [0u8; 16];
+        let mut count = 0;
+        if let Some(mut serial) = serial() {
+            serial.handle_irq();
+            while count < bytes.len() {
+                let Some(byte) = serial.receive() else {
+                    break;
+                };
+                bytes[count] = byte;
+                count += 1;
             }
+        }
+
+        if count == 0 {
+            break;
+        }
 
-            UTF8_BUF[UTF8_LEN] = byte;
-            UTF8_LEN += 1;
+        for &byte in &bytes[..count] {
+            receive_byte(byte, &mut utf8, tty);
+        }
+    }
+}
 
-            // Copy the relevant part of the static buffer to a local buffer
-            // to avoid creating a shared reference to `static mut`.
-            let current_bytes = &UTF8_BUF[0..UTF8_LEN];
-            match str::from_utf8(current_bytes) {
-                Ok(s) => {
-                    // Successfully decoded a character(s).
-                    // Since we add byte-by-byte, `s` should contain exactly one char when Ok.
-                    if let Some(c) = s.chars().next() {
-                        // 注释掉这个日志，避免输入时的干扰
-                        // trace!("Decoded char: {:?}", c);
-                        match c {
-                            '\r' => input::push_newline(), // Treat carriage return as newline
-                            '\x08' | '\x7f' => input::push_backspace(), // Backspace or Delete
-                            _ => input::push_char(c),
-                        }
-                    }
-                    UTF8_LEN = 0; // Reset buffer for next character
-                }
-                Err(e) => {
-                    if e.error_len().is_none() {
-                        // Incomplete sequence, need more bytes. Continue loop.
-                        // 注释掉这个日志，避免输入时的干扰
-                        // trace!("Incomplete UTF-8 sequence: {:?}", current_bytes);
-                    } else {
-                        // Invalid sequence found. Push replacement char and reset.
-                        input::push_char('\u{FFFD}');
-                        UTF8_LEN = 0;
-                    }
-                }
+fn receive_byte(byte: u8, utf8: &mut Utf8Buffer, tty: Tty) {
+    if utf8.len >= utf8.buf.len() {
+        // Buffer full, but no valid char yet. This indicates an error or
+        // a character longer than 4 bytes (which shouldn't happen with standard UTF-8).
+        // Push replacement char and reset.
+        tty.receive('\u{FFFD}');
+        utf8.len = 0;
+        // Try processing the current byte as the start of a new sequence
+    }
+
+    let len = utf8.len;
+    utf8.buf[len] = byte;
+    utf8.len += 1;
+
+    match str::from_utf8(&utf8.buf[..utf8.len]) {
+        Ok(s) => {
+            // Successfully decoded a character(s).
+            // Since we add byte-by-byte, `s` should contain exactly one char when Ok.
+            if let Some(c) = s.chars().next() {
+                // 注释掉这个日志，避免输入时的干扰
+                // trace!("Decoded char: {:?}", c);
+                // 行编辑和回显由终端的行规程处理
+                tty.receive(c);
+            }
+            utf8.len = 0; // Reset buffer for next character
+        }
+        Err(e) => {
+            if e.error_len().is_none() {
+                // Incomplete sequence, need more bytes. Continue loop.
+                // 注释掉这个日志，避免输入时的干扰
+                // trace!("Incomplete UTF-8 sequence: {:?}", &utf8.buf[..utf8.len]);
+            } else {
+                // Invalid sequence found. Push replacement char and reset.
+                tty.receive('\u{FFFD}');
+                utf8.len = 0;
             }
         }
     }
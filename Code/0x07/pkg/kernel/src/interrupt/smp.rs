@@ -0,0 +1,261 @@
+//! Application processors
+//!
+//! The bootstrap processor starts every other enabled CPU of the MADT with
+//! INIT-SIPI-SIPI, one at a time. An AP starts in real mode at the
+//! trampoline copied below 1 MiB, and goes straight to long mode on the
+//! page table of the trampoline: the kernel's, with the first 2 MiB
+//! identity mapped and executable. It then calls `ap_main` on a stack of
+//! its own, which loads the kernel page table, its own GDT and TSS, and
+//! starts its LAPIC timer. From then on it takes processes from the shared
+//! ready queue, and halts in its idle process when there are none.
+//!
+//! reference: https://wiki.osdev.org/Symmetric_Multiprocessing
+//! reference: https://wiki.osdev.org/Entering_Long_Mode_Directly
+
+use super::apic::*;
+use crate::drivers::pit;
+use crate::memory::{low_memory, physical_to_virtual, LOW_MEMORY_PAGES};
+use crate::proc::processor;
+use alloc::vec::Vec;
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::registers::control::{Cr0, Cr3, Cr3Flags, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{PageTable, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Stack an AP starts on, its idle process keeps it
+const AP_STACK_SIZE: usize = 0x4000;
+
+/// Pages of the low memory: the code, then the page tables
+const PAGE_P4: u64 = 1;
+const PAGE_P3: u64 = 2;
+const PAGE_P2: u64 = 3;
+const _: () = assert!(PAGE_P2 < LOW_MEMORY_PAGES);
+
+const INIT_DELAY_US: u64 = 10_000;
+const STARTUP_DELAY_US: u64 = 200;
+/// How long an AP has to reach `ap_main`, in milliseconds
+const START_TIMEOUT_MS: u64 = 100;
+
+/// Set by the AP being started once it is ready to run processes
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+/// Physical address of the kernel page table, loaded by the APs
+static KERNEL_CR3: AtomicU64 = AtomicU64::new(0);
+
+/// What the trampoline reads, at its end
+#[repr(C)]
+struct TrampolineData {
+    gdt: [u64; 3],
+    /// Limit and base of `gdt` for LGDT
+    gdtr: [u16; 4],
+    /// 32-bit offset and selector of the 64-bit code for the far jump
+    far_jump: [u16; 4],
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    cr0: u64,
+    stack: u64,
+    entry: u64,
+}
+
+core::arch::global_asm!(
+    ".code16",
+    ".global ap_trampoline_start",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    // CS is the page of the trampoline, IP is 0
+    "mov ax, cs",
+    "mov ds, ax",
+    "mov eax, dword ptr [ap_trampoline_data - ap_trampoline_start + {cr4}]",
+    "mov cr4, eax",
+    "mov eax, dword ptr [ap_trampoline_data - ap_trampoline_start + {cr3}]",
+    "mov cr3, eax",
+    "mov ecx, 0xC0000080",
+    "mov eax, dword ptr [ap_trampoline_data - ap_trampoline_start + {efer}]",
+    "mov edx, dword ptr [ap_trampoline_data - ap_trampoline_start + {efer} + 4]",
+    "wrmsr",
+    "lgdt [ap_trampoline_data - ap_trampoline_start + {gdtr}]",
+    // PE and PG at once, the CPU is in long mode after the far jump
+    "mov eax, dword ptr [ap_trampoline_data - ap_trampoline_start + {cr0}]",
+    "mov cr0, eax",
+    // jmp far dword ptr [far_jump]
+    ".byte 0x66, 0xff, 0x2e",
+    ".word ap_trampoline_data - ap_trampoline_start + {far_jump}",
+    ".code64",
+    ".global ap_trampoline_long",
+    "ap_trampoline_long:",
+    "xor eax, eax",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov rsp, qword ptr [rip + ap_trampoline_data + {stack}]",
+    "call qword ptr [rip + ap_trampoline_data + {entry}]",
+    "ud2",
+    ".balign 8",
+    ".global ap_trampoline_data",
+    "ap_trampoline_data:",
+    ".space {size}",
+    ".global ap_trampoline_end",
+    "ap_trampoline_end:",
+    cr4 = const offset_of!(TrampolineData, cr4),
+    cr3 = const offset_of!(TrampolineData, cr3),
+    efer = const offset_of!(TrampolineData, efer),
+    gdtr = const offset_of!(TrampolineData, gdtr),
+    cr0 = const offset_of!(TrampolineData, cr0),
+    far_jump = const offset_of!(TrampolineData, far_jump),
+    stack = const offset_of!(TrampolineData, stack),
+    entry = const offset_of!(TrampolineData, entry),
+    size = const size_of::<TrampolineData>(),
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_long: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Start the other enabled CPUs of the MADT, called on the bootstrap
+/// processor once the processes can be scheduled
+pub fn init() {
+    let Some(madt) = super::madt() else {
+        return;
+    };
+
+    let current = processor::apic_id() as u32;
+    let aps: Vec<u32> = madt
+        .processors
+        .iter()
+        .filter(|p| p.enabled && p.apic_id != current)
+        .map(|p| p.apic_id)
+        .collect();
+
+    if aps.is_empty() || !Lapic::support() {
+        return;
+    }
+
+    let Some(low) = low_memory() else {
+        warn!("No free memory below 1 MiB, the other CPUs are not started");
+        return;
+    };
+
+    let data = prepare_trampoline(low);
+    let mut lapic = super::lapic();
+
+    for &id in aps.iter() {
+        if !start_ap(&mut lapic, id, low, data) {
+            warn!("CPU {} did not start", id);
+        }
+    }
+
+    info!("SMP: {} of {} CPUs online", processor::online_count(), aps.len() + 1);
+}
+
+/// Copy the trampoline to `low` and build its page tables, returns its data
+fn prepare_trampoline(low: PhysFrame) -> &'static mut TrampolineData {
+    let base = low.start_address().as_u64();
+    let page = |i: u64| physical_to_virtual(base + i * 0x1000);
+
+    let start = &raw const ap_trampoline_start as u64;
+    let long = &raw const ap_trampoline_long as u64 - start;
+    let data_offset = &raw const ap_trampoline_data as u64 - start;
+    let len = &raw const ap_trampoline_end as u64 - start;
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(start as *const u8, page(0) as *mut u8, len as usize);
+    }
+
+    // 内核页表之外，再恒等映射前 2 MiB，让实模式代码开启分页后继续执行
+    let (kernel, _) = Cr3::read();
+    KERNEL_CR3.store(kernel.start_address().as_u64(), Ordering::Relaxed);
+
+    let table = |i: u64| unsafe { &mut *(page(i) as *mut PageTable) };
+    let kernel_p4 = unsafe { &*(physical_to_virtual(kernel.start_address().as_u64()) as *const PageTable) };
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let p4 = table(PAGE_P4);
+    *p4 = kernel_p4.clone();
+    p4[0].set_addr(PhysAddr::new(base + PAGE_P3 * 0x1000), flags);
+
+    let p3 = table(PAGE_P3);
+    p3.zero();
+    p3[0].set_addr(PhysAddr::new(base + PAGE_P2 * 0x1000), flags);
+
+    let p2 = table(PAGE_P2);
+    p2.zero();
+    p2[0].set_addr(PhysAddr::new(0), flags | PageTableFlags::HUGE_PAGE);
+
+    let gdt = base + data_offset + offset_of!(TrampolineData, gdt) as u64;
+    let long = base + long;
+
+    let data = unsafe { &mut *((page(0) + data_offset) as *mut TrampolineData) };
+    *data = TrampolineData {
+        // null, 64-bit code, data
+        gdt: [0, 0x00AF_9A00_0000_FFFF, 0x00CF_9200_0000_FFFF],
+        gdtr: [23, gdt as u16, (gdt >> 16) as u16, 0],
+        far_jump: [long as u16, (long >> 16) as u16, 0x08, 0],
+        cr3: base + PAGE_P4 * 0x1000,
+        // PCID can only be turned on in long mode, the trampoline does not need it
+        cr4: Cr4::read_raw() & !Cr4Flags::PCID.bits(),
+        efer: Efer::read_raw() & !EferFlags::LONG_MODE_ACTIVE.bits(),
+        cr0: Cr0::read_raw(),
+        stack: 0,
+        entry: ap_main as extern "C" fn() -> ! as usize as u64,
+    };
+
+    data
+}
+
+/// INIT-SIPI-SIPI the CPU with LAPIC ID `id`, returns whether it started
+fn start_ap(lapic: &mut impl LocalApic, id: u32, low: PhysFrame, data: &mut TrampolineData) -> bool {
+    let stack = alloc::vec![0u8; AP_STACK_SIZE].leak();
+    let stack_top = VirtAddr::from_ptr(stack.as_ptr()) + AP_STACK_SIZE as u64;
+    data.stack = stack_top.align_down(16u64).as_u64();
+
+    AP_STARTED.store(false, Ordering::Release);
+
+    lapic.send_ipi(id, ICR_INIT | ICR_ASSERT | ICR_LEVEL);
+    pit::wait_us(INIT_DELAY_US);
+
+    // the AP starts at the page given as vector, the second SIPI is
+    // ignored if the first one worked
+    let vector = (low.start_address().as_u64() >> 12) as u32;
+    for _ in 0..2 {
+        lapic.send_ipi(id, ICR_STARTUP | vector);
+        pit::wait_us(STARTUP_DELAY_US);
+    }
+
+    for _ in 0..START_TIMEOUT_MS {
+        if AP_STARTED.load(Ordering::Acquire) {
+            return true;
+        }
+        pit::wait_us(1_000);
+    }
+
+    AP_STARTED.load(Ordering::Acquire)
+}
+
+/// Where an AP lands from the trampoline
+extern "C" fn ap_main() -> ! {
+    let kernel = PhysFrame::containing_address(PhysAddr::new(KERNEL_CR3.load(Ordering::Relaxed)));
+    unsafe { Cr3::write(kernel, Cr3Flags::empty()) };
+
+    crate::memory::gdt::init_ap();
+    super::IDT.load();
+
+    let mut lapic = super::lapic();
+    lapic.cpu_init();
+    start_timer(&mut lapic);
+
+    crate::proc::init_ap();
+    info!("CPU {} online.", lapic.id());
+    AP_STARTED.store(true, Ordering::Release);
+
+    // 第一次时钟中断到来时，这里成为本 CPU 的空闲进程
+    x86_64::instructions::interrupts::enable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
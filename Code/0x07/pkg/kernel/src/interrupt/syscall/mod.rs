@@ -96,6 +96,10 @@ pub fn dispatcher(context: &mut ProcessContext) {
         Syscall::Write => {
             context.set_rax(sys_write(&args));
         },
+        // fd: arg0 as u8, request: arg1, arg: arg2 -> ret: isize
+        Syscall::Ioctl => {
+            context.set_rax(sys_ioctl(&args));
+        },
         // None -> pid: u16
         Syscall::GetPid => {
             context.set_rax(sys_getpid(&args));
@@ -130,7 +134,35 @@ pub fn dispatcher(context: &mut ProcessContext) {
             sys_fork(context);
         },
 
+        // buf: &mut [u8] (ptr: arg0 as *mut u8, len: arg1), level: arg2 -> count: usize
+        Syscall::Dmesg => {
+            context.set_rax(sys_dmesg(&args));
+        },
+
+        // clock: arg0, time: arg1 as *mut TimeSpec -> status: 0 on success
+        Syscall::ClockGetTime => {
+            context.set_rax(sys_clock_gettime(&args));
+        },
+
+        // buf: &mut [u8] (ptr: arg0 as *mut u8, len: arg1) -> count: usize
+        Syscall::GetRandom => {
+            context.set_rax(sys_getrandom(&args));
+        },
+
+        // None -> status: 0 on success
+        Syscall::Sync => {
+            context.set_rax(sys_sync(&args));
+        },
+
         // path: &str (ptr: arg0 as *const u8, len: arg1)
+        Syscall::ListPci => {
+            crate::drivers::pci::list();
+            context.set_rax(0);
+        },
+        Syscall::ListBlk => {
+            crate::drivers::blkdev::list();
+            context.set_rax(0);
+        },
         Syscall::ListDir => {
             list_dir(&args);
             context.set_rax(0);
@@ -143,6 +175,10 @@ pub fn dispatcher(context: &mut ProcessContext) {
         Syscall::Close => {
             context.set_rax(sys_close(&args));
         },
+        // fd: arg0 as u8, op: arg1 as usize -> status: isize
+        Syscall::Flock => {
+            sys_flock(&args, context);
+        },
 
         // ----------------------------------------------------
         // NOTE: following syscall examples are implemented
@@ -170,6 +206,12 @@ pub fn dispatcher(context: &mut ProcessContext) {
             // context.set_rax(ysos_syscall::SysErr::NotSupported as usize); // Example
         }
     }
+
+    // 在系统调用中被 Ctrl-C 的进程，回到用户态之前结束
+    let manager = get_process_manager();
+    if manager.kill_pending(context) {
+        manager.switch_next(context);
+    }
 }
 
 impl SyscallArgs {
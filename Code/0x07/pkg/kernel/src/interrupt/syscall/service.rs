@@ -66,15 +66,12 @@ pub fn sys_read(args: &SyscallArgs) -> usize {
     // 将指针和长度转换为可变切片
     let buf = unsafe { core::slice::from_raw_parts_mut(ptr, len) };
     
-    // 调用进程的read函数
-    let result = read(fd, buf);
-    
-    // 如果结果为负数，返回0，否则返回读取的字节数
-    if result.is_negative() {
-        0
-    } else {
-        result as usize
-    }
+    // 调用进程的read函数，出错时返回负的错误码
+    read(fd, buf) as usize
+}
+
+pub fn sys_ioctl(args: &SyscallArgs) -> usize {
+    ioctl(args.arg0 as u8, args.arg1, args.arg2) as usize
 }
 
 pub fn exit_process(args: &SyscallArgs, context: &mut ProcessContext) {
@@ -137,6 +134,9 @@ pub fn sys_waitpid(args: &SyscallArgs) -> usize {
             Some(code) => code as usize,
             None => 0, // 进程不存在或已被回收
         }
+    } else if is_stopped(pid) {
+        // 进程被 Ctrl-Z 暂停
+        ysos_syscall::WAIT_STOPPED as usize
     } else {
         // 进程仍在运行，返回特殊值表示正在运行
         usize::MAX  // 使用最大的usize值表示进程仍在运行
@@ -199,6 +199,11 @@ pub fn sys_close(args: &SyscallArgs) -> usize {
     }
 }
 
+pub fn sys_flock(args: &SyscallArgs, context: &mut ProcessContext) {
+    // 阻塞时会切换进程，由flock函数设置返回值
+    flock(args.arg0 as u8, args.arg1, context);
+}
+
 pub fn sys_brk(args: &SyscallArgs) -> usize {
     let new_heap_end = if args.arg0 == 0 {
         None
@@ -210,3 +215,70 @@ pub fn sys_brk(args: &SyscallArgs) -> usize {
         None => !0,
     }
 }
+
+pub fn sys_dmesg(args: &SyscallArgs) -> usize {
+    use log::LevelFilter;
+
+    let ptr = args.arg0 as *mut u8;
+    let len = args.arg1;
+
+    if ptr.is_null() || len == 0 {
+        return 0;
+    }
+
+    let level = match args.arg2 {
+        ysos_syscall::LOG_ERROR => LevelFilter::Error,
+        ysos_syscall::LOG_WARN => LevelFilter::Warn,
+        ysos_syscall::LOG_INFO => LevelFilter::Info,
+        ysos_syscall::LOG_DEBUG => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    let buf = unsafe { core::slice::from_raw_parts_mut(ptr, len) };
+    crate::utils::logger::read(level, buf)
+}
+
+pub fn sys_clock_gettime(args: &SyscallArgs) -> usize {
+    use crate::utils::time;
+    use ysos_syscall::{TimeSpec, CLOCK_MONOTONIC, CLOCK_REALTIME};
+
+    let ptr = args.arg1 as *mut TimeSpec;
+    if ptr.is_null() {
+        return 1;
+    }
+
+    let time = match args.arg0 {
+        CLOCK_REALTIME => time::realtime(),
+        CLOCK_MONOTONIC => time::monotonic(),
+        _ => return 1,
+    };
+
+    unsafe {
+        ptr.write(TimeSpec {
+            tv_sec: time.as_secs() as i64,
+            tv_nsec: time.subsec_nanos() as i64,
+        })
+    };
+    0
+}
+
+pub fn sys_getrandom(args: &SyscallArgs) -> usize {
+    let ptr = args.arg0 as *mut u8;
+    let len = args.arg1;
+
+    if ptr.is_null() {
+        return 0;
+    }
+
+    let buf = unsafe { core::slice::from_raw_parts_mut(ptr, len) };
+    crate::drivers::random::fill(buf);
+    len
+}
+
+pub fn sys_sync(_args: &SyscallArgs) -> usize {
+    if crate::drivers::blkdev::sync() {
+        0 // 成功
+    } else {
+        1 // 失败
+    }
+}
@@ -41,24 +41,34 @@ pub fn init(boot_info: &'static BootInfo) {
         uefi::table::set_system_table(boot_info.system_table.cast().as_ptr());
     }
 
-    serial::init(); // init serial output
-    logger::init(boot_info.log_level); // 使用从 bootloader 传递的日志级别
+    serial::init(boot_info.serial); // init serial output
+    logger::init(boot_info.log_level, boot_info.console_loglevel); // 使用从 bootloader 传递的日志级别
+    tty::init(boot_info.com2); // COM2 的用途
     memory::address::init(boot_info);
+    console::init(boot_info.graphic_info.as_ref()); // init framebuffer console
     memory::gdt::init(); // init gdt
     memory::allocator::init(); // init kernel heap allocator
-    interrupt::init(); // init interrupts
+    memory::gdt::init_syscall_stacks(); // 系统调用栈池需要内核堆
+    drivers::acpi::init(); // find the ACPI tables
+    interrupt::init(boot_info.timer_hz, drivers::acpi::info()); // init interrupts
     memory::init(boot_info); // init memory manager
+    drivers::rtc::init(); // init wall clock
+    drivers::random::init(); // seed the entropy pool
     
     proc::init(boot_info); // 初始化进程管理器，在内存初始化之后，启用中断之前
 
     x86_64::instructions::interrupts::enable();
     info!("Interrupts Enabled.");
+    serial::enable_buffering(); // 串口输出改由中断发送
 
-    // Test ATA drive
-    test_ata_drive();
+    drivers::keyboard::init(boot_info.keyboard_layout);
+    drivers::pci::init();
+    drivers::blkdev::init();
 
     // Initialize filesystem
-    drivers::filesystem::init();
+    drivers::filesystem::init(boot_info.root_device);
+
+    interrupt::smp::init(); // 启动其他 CPU
 
     info!("Test stack grow.");
     grow_stack();
@@ -67,60 +77,6 @@ pub fn init(boot_info: &'static BootInfo) {
     info!("YatSenOS initialized.");
 }
 
-fn test_ata_drive() {
-    info!("Testing ATA drive...");
-    match drivers::ata::AtaDrive::open(0, 0) {
-        Some(drive) => {
-            info!("ATA drive test successful!");
-
-            // Test reading the first sector (MBR)
-            info!("Testing MBR read...");
-            let mut buffer = [0u8; 512];
-            match drive.read_block_raw(0, &mut buffer) {
-                Ok(()) => {
-                    info!("MBR read successful!");
-                    // Check MBR signature
-                    if buffer[510] == 0x55 && buffer[511] == 0xAA {
-                        info!("Valid MBR signature found!");
-
-                        // Parse first partition entry using our MbrPartition implementation
-                        let partition_offset = 446;
-                        let partition_data: [u8; 16] = buffer[partition_offset..partition_offset + 16]
-                            .try_into()
-                            .expect("Failed to extract partition data");
-
-                        // Use our existing MbrPartition implementation
-                        let partition = storage::mbr::MbrPartition::parse(&partition_data);
-
-                        info!("First partition info (using MbrPartition):");
-                        info!("  Active: {}", partition.is_active());
-                        info!("  Type: 0x{:02X}", partition.partition_type());
-                        info!("  Start LBA: {}", partition.begin_lba());
-                        info!("  Size (sectors): {}", partition.total_lba());
-                        info!("  Size (MB): {}", (partition.total_lba() as u64 * 512) / (1024 * 1024));
-                        info!("  CHS Begin: C{}/H{}/S{}",
-                              partition.begin_cylinder(),
-                              partition.begin_head(),
-                              partition.begin_sector());
-                        info!("  CHS End: C{}/H{}/S{}",
-                              partition.end_cylinder(),
-                              partition.end_head(),
-                              partition.end_sector());
-                    } else {
-                        warn!("Invalid MBR signature: 0x{:02X}{:02X}", buffer[511], buffer[510]);
-                    }
-                }
-                Err(e) => {
-                    warn!("MBR read failed: {}", e);
-                }
-            }
-        }
-        None => {
-            warn!("ATA drive test failed - no drive found");
-        }
-    }
-}
-
 pub fn wait(init: proc::ProcessId) {
     loop {
         if proc::still_alive(init) {
@@ -134,6 +90,13 @@ pub fn wait(init: proc::ProcessId) {
 
 pub fn shutdown() -> ! {
     info!("YatSenOS shutting down.");
+
+    // the data in the drive caches would be lost on power off
+    if !drivers::blkdev::sync() {
+        warn!("Failed to flush the block devices.");
+    }
+
+    serial::flush();
     uefi::runtime::reset(ResetType::SHUTDOWN, Status::SUCCESS, None);
 }
 
@@ -10,7 +10,9 @@ boot::entry_point!(kernel_main);
 
 pub fn kernel_main(boot_info: &'static boot::BootInfo) -> ! {
     ysos::init(boot_info);
-    ysos::wait(spawn_init()); 
+    let init = spawn_init();
+    spawn_com2_shell();
+    ysos::wait(init);
     ysos::shutdown();
 }
 
@@ -24,3 +26,13 @@ pub fn spawn_init() -> proc::ProcessId {
     // 如果失败，会自动回退到bootloader应用
     proc::spawn("/shell").or_else(|| proc::spawn("shell")).unwrap()
 }
+
+/// A second shell session on COM2 if asked for, it is not waited for
+pub fn spawn_com2_shell() {
+    use ysos::tty::{com2_usage, Com2Usage, Tty};
+
+    if com2_usage() == Com2Usage::Shell {
+        proc::spawn_on_tty("/shell", Tty::Serial1)
+            .or_else(|| proc::spawn_on_tty("shell", Tty::Serial1));
+    }
+}
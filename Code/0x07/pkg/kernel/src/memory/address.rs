@@ -9,6 +9,16 @@ pub fn init(boot_info: &'static boot::BootInfo) {
     info!("Physical Offset  : {:#x}", PHYSICAL_OFFSET.get().unwrap());
 }
 
+/// End of the lower half of the address space, where the user programs live
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Whether `len` bytes at `addr` are all in the user half
+#[inline]
+pub fn is_user_range(addr: u64, len: usize) -> bool {
+    addr.checked_add(len as u64)
+        .is_some_and(|end| end <= USER_SPACE_END)
+}
+
 /// Convert a virtual address to a physical address.
 #[inline(always)]
 pub fn physical_to_virtual(addr: u64) -> u64 {
@@ -3,6 +3,7 @@ use boot::{MemoryMap, MemoryType};
 use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
 use x86_64::PhysAddr;
 use alloc::vec::Vec;
+use spin::Once;
 
 once_mutex!(pub FRAME_ALLOCATOR: BootInfoFrameAllocator);
 
@@ -10,13 +11,36 @@ guard_access_fn! {
     pub get_frame_alloc(FRAME_ALLOCATOR: BootInfoFrameAllocator)
 }
 
+/// The frame allocator, waiting while another CPU holds it
+///
+/// For the processes, which may run on several CPUs at once.
+pub fn wait_frame_alloc() -> spin::MutexGuard<'static, BootInfoFrameAllocator> {
+    FRAME_ALLOCATOR
+        .get()
+        .expect("FRAME_ALLOCATOR has not been initialized")
+        .lock()
+}
+
 type BootInfoFrameIter = Box<dyn Iterator<Item = PhysFrame> + Send>;
 
+/// Frames kept out of the allocator below 1 MiB, for the real mode code
+/// that starts the application processors and its page tables
+pub const LOW_MEMORY_PAGES: u64 = 4;
+
+static LOW_MEMORY: Once<Option<PhysFrame>> = Once::new();
+
+/// The first of the frames kept below 1 MiB, if the memory map had room
+pub fn low_memory() -> Option<PhysFrame> {
+    LOW_MEMORY.get().copied().flatten()
+}
+
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
     size: usize,
     used: usize,
     frames: BootInfoFrameIter,
+    /// Never used frames passed over by `allocate_contiguous`
+    skipped: Vec<PhysFrame>,
     recycled: Vec<PhysFrame>,
 }
 
@@ -27,10 +51,13 @@ impl BootInfoFrameAllocator {
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
     pub unsafe fn init(memory_map: &MemoryMap, size: usize) -> Self {
+        LOW_MEMORY.call_once(|| find_low_memory(memory_map));
+
         BootInfoFrameAllocator {
             size,
             frames: create_frame_iter(memory_map),
             used: 0,
+            skipped: Vec::new(),
             recycled: Vec::new(),
         }
     }
@@ -46,6 +73,31 @@ impl BootInfoFrameAllocator {
     pub fn frames_recycled(&self) -> usize {
         self.recycled.len()
     }
+
+    /// Allocate `count` physically contiguous frames, e.g. for DMA buffers.
+    ///
+    /// Returns the first frame of the run. Frames skipped while looking
+    /// for a contiguous run are handed out by `allocate_frame` later, and
+    /// only counted as used then.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        let mut run: Vec<PhysFrame> = Vec::with_capacity(count);
+
+        while run.len() < count {
+            let Some(frame) = self.frames.next() else {
+                self.skipped.append(&mut run);
+                return None;
+            };
+
+            if run.last().is_some_and(|&last| last + 1 != frame) {
+                self.skipped.append(&mut run);
+            }
+
+            run.push(frame);
+        }
+
+        self.used += count;
+        run.first().copied()
+    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
@@ -53,7 +105,7 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
         if let Some(frame) = self.recycled.pop() {
             return Some(frame);
         }
-        match self.frames.next() {
+        match self.skipped.pop().or_else(|| self.frames.next()) {
             Some(frame) => {
                 self.used += 1;
                 Some(frame)
@@ -78,7 +130,27 @@ fn create_frame_iter(memory_map: &MemoryMap) -> BootInfoFrameIter {
         // align to page boundary
         .flat_map(|r| (0..r.page_count).map(move |v| (v * 4096 + r.phys_start)))
         // create `PhysFrame` types from the start addresses
-        .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)));
+        .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+        // leave out the frames kept below 1 MiB
+        .filter(|frame| {
+            low_memory().is_none_or(|low| !(low..low + LOW_MEMORY_PAGES).contains(frame))
+        });
 
     Box::new(iter)
 }
+
+/// The first run of `LOW_MEMORY_PAGES` usable frames below 1 MiB, page 0
+/// is left alone
+fn find_low_memory(memory_map: &MemoryMap) -> Option<PhysFrame> {
+    const LOW_MEMORY_END: u64 = 0x10_0000;
+
+    memory_map
+        .iter()
+        .filter(|r| r.ty == MemoryType::CONVENTIONAL)
+        .find_map(|r| {
+            let start = r.phys_start.max(0x1000);
+            let end = (r.phys_start + r.page_count * 4096).min(LOW_MEMORY_END);
+            (end >= start + LOW_MEMORY_PAGES * 4096)
+                .then(|| PhysFrame::containing_address(PhysAddr::new(start)))
+        })
+}
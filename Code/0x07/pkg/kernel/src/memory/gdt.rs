@@ -1,4 +1,8 @@
-use core::ptr::addr_of_mut;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use x86_64::registers::segmentation::Segment;
 use x86_64::structures::gdt::{
@@ -6,6 +10,7 @@ use x86_64::structures::gdt::{
 };
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
+use spin::{Mutex, Once};
 
 // 设置不同类型中断的栈大小
 const IST_SIZES: [usize; 7] = [
@@ -25,6 +30,115 @@ pub const DOUBLE_FAULT_IST_INDEX: u16 = 4;
 pub const PAGE_FAULT_IST_INDEX: u16 = 5;
 pub const SYSCALL_IST_INDEX: u16 = 1; // 为系统调用定义 IST 索引
 
+// 系统调用栈池：进程在系统调用中睡眠时保留其内核栈，
+// 其余进程的系统调用切换到池中的空闲栈，没有空闲栈时在内核堆上分配
+const SYSCALL_STACK_SIZE: usize = 0x4000;
+const SYSCALL_STACK_COUNT: usize = 4;
+
+/// Size of each stack of an application processor
+const AP_STACK_SIZE: usize = 0x4000;
+
+static mut SYSCALL_STACKS: [[u8; SYSCALL_STACK_SIZE]; SYSCALL_STACK_COUNT] =
+    [[0; SYSCALL_STACK_SIZE]; SYSCALL_STACK_COUNT];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StackState {
+    Free,
+    /// Used by the syscall IST of the CPU with this local APIC ID
+    Current(u32),
+    /// Held by a syscall that is sleeping
+    Parked,
+    /// Given back by a syscall that still returns on it, only the CPU it
+    /// runs on can take it again
+    Released(u32),
+}
+
+struct SyscallStacks {
+    /// Bottom of each stack
+    start: Vec<u64>,
+    state: Vec<StackState>,
+}
+
+impl SyscallStacks {
+    const fn new() -> Self {
+        Self {
+            start: Vec::new(),
+            state: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, start: VirtAddr, state: StackState) -> usize {
+        self.start.push(start.as_u64());
+        self.state.push(state);
+        self.start.len() - 1
+    }
+
+    /// The stack `addr` is in
+    fn find(&self, addr: VirtAddr) -> Option<usize> {
+        let addr = addr.as_u64();
+        self.start
+            .iter()
+            .position(|&start| (start..start + SYSCALL_STACK_SIZE as u64).contains(&addr))
+    }
+
+    /// A stack the CPU `cpu` can switch its syscall IST to, a new one if
+    /// all are in use
+    fn find_free(&mut self, cpu: u32) -> usize {
+        let free = self
+            .state
+            .iter()
+            .position(|&state| state == StackState::Free || state == StackState::Released(cpu));
+
+        free.unwrap_or_else(|| self.add(alloc_stack(SYSCALL_STACK_SIZE).0, StackState::Free))
+    }
+
+    fn end(&self, idx: usize) -> VirtAddr {
+        VirtAddr::new(self.start[idx] + SYSCALL_STACK_SIZE as u64)
+    }
+}
+
+static SYSCALL_STACK_POOL: Mutex<SyscallStacks> = Mutex::new(SyscallStacks::new());
+
+/// TSS of each application processor, indexed by local APIC ID like the
+/// processors, the bootstrap processor uses `TSS`
+static CPU_TSS: Once<Vec<AtomicU64>> = Once::new();
+
+fn syscall_stack_range(idx: usize) -> (VirtAddr, VirtAddr) {
+    let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(SYSCALL_STACKS[idx]) });
+    (stack_start, stack_start + SYSCALL_STACK_SIZE as u64)
+}
+
+/// A stack on the kernel heap, never freed, returns its bottom and top
+fn alloc_stack(size: usize) -> (VirtAddr, VirtAddr) {
+    let layout = Layout::from_size_align(size, 16).unwrap();
+    let stack = unsafe { alloc::alloc::alloc_zeroed(layout) };
+    if stack.is_null() {
+        alloc::alloc::handle_alloc_error(layout);
+    }
+    let stack_start = VirtAddr::from_ptr(stack);
+    (stack_start, stack_start + size as u64)
+}
+
+/// The TSS slot of the CPU with local APIC ID `cpu`
+fn tss_slot(cpu: usize) -> &'static AtomicU64 {
+    let slots = CPU_TSS.call_once(|| {
+        (0..crate::proc::processor::count())
+            .map(|_| AtomicU64::new(0))
+            .collect()
+    });
+    slots
+        .get(cpu)
+        .unwrap_or_else(|| panic!("No TSS slot for local APIC ID {}", cpu))
+}
+
+fn current_tss() -> *mut TaskStateSegment {
+    let cpu = crate::proc::processor::apic_id();
+    match tss_slot(cpu).load(Ordering::Relaxed) {
+        0 => &*TSS as *const TaskStateSegment as *mut TaskStateSegment,
+        tss => tss as *mut TaskStateSegment,
+    }
+}
+
 lazy_static! {
     // 设置TSS，存放中断栈表
     static ref TSS: TaskStateSegment = {
@@ -85,10 +199,7 @@ lazy_static! {
         };
 
         tss.interrupt_stack_table[SYSCALL_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = IST_SIZES[1];
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(addr_of_mut!(STACK));
-            let stack_end = stack_start + STACK_SIZE as u64;
+            let (stack_start, stack_end) = syscall_stack_range(0);
             info!(
                 "Syscall IST       : 0x{:016x}-0x{:016x}",
                 stack_start.as_u64(),
@@ -102,27 +213,29 @@ lazy_static! {
 }
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, KernelSelectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        
-        // 添加Ring 3的代码段和数据段选择子
-        let user_code_selector = gdt.append(Descriptor::user_code_segment());
-        let user_data_selector = gdt.append(Descriptor::user_data_segment());
-        
-        (
-            gdt,
-            KernelSelectors {
-                code_selector,
-                data_selector,
-                tss_selector,
-                user_code_selector,
-                user_data_selector,
-            },
-        )
-    };
+    static ref GDT: (GlobalDescriptorTable, KernelSelectors) = build_gdt(&TSS);
+}
+
+fn build_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, KernelSelectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let data_selector = gdt.append(Descriptor::kernel_data_segment());
+    let tss_selector = gdt.append(Descriptor::tss_segment(tss));
+    
+    // 添加Ring 3的代码段和数据段选择子
+    let user_code_selector = gdt.append(Descriptor::user_code_segment());
+    let user_data_selector = gdt.append(Descriptor::user_data_segment());
+    
+    (
+        gdt,
+        KernelSelectors {
+            code_selector,
+            data_selector,
+            tss_selector,
+            user_code_selector,
+            user_data_selector,
+        },
+    )
 }
 
 #[derive(Debug)]
@@ -134,21 +247,25 @@ pub struct KernelSelectors {
     pub user_data_selector: SegmentSelector,
 }
 
-pub fn init() {
+fn load(gdt: &'static (GlobalDescriptorTable, KernelSelectors)) {
     use x86_64::instructions::segmentation::{CS, DS, ES, FS, GS, SS};
     use x86_64::instructions::tables::load_tss;
     use x86_64::PrivilegeLevel;
 
-    GDT.0.load();
+    gdt.0.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        DS::set_reg(GDT.1.data_selector);
+        CS::set_reg(gdt.1.code_selector);
+        DS::set_reg(gdt.1.data_selector);
         SS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
         ES::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
         FS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
         GS::set_reg(SegmentSelector::new(0, PrivilegeLevel::Ring0));
-        load_tss(GDT.1.tss_selector);
+        load_tss(gdt.1.tss_selector);
     }
+}
+
+pub fn init() {
+    load(&GDT);
 
     let mut size = 0;
 
@@ -156,10 +273,95 @@ pub fn init() {
         size += s;
     }
 
+    size += SYSCALL_STACK_SIZE * SYSCALL_STACK_COUNT;
+
     let (size, unit) = crate::humanized_size(size as u64);
     info!("Total IST size   : {} {}", size, unit);
 }
 
+/// Put the static syscall stacks of the bootstrap processor in the pool,
+/// once the kernel heap is ready
+pub fn init_syscall_stacks() {
+    let cpu = crate::proc::processor::apic_id();
+
+    let mut pool = SYSCALL_STACK_POOL.lock();
+    for idx in 0..SYSCALL_STACK_COUNT {
+        let state = if idx == 0 {
+            StackState::Current(cpu as u32)
+        } else {
+            StackState::Free
+        };
+        pool.add(syscall_stack_range(idx).0, state);
+    }
+}
+
+/// Load a GDT and a TSS of its own on an application processor, its
+/// stacks are on the kernel heap
+pub fn init_ap() {
+    let cpu = crate::proc::processor::apic_id();
+
+    let mut tss = TaskStateSegment::new();
+    tss.privilege_stack_table[0] = alloc_stack(AP_STACK_SIZE).1;
+    for index in [TIMER_IST_INDEX, DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX] {
+        tss.interrupt_stack_table[index as usize] = alloc_stack(AP_STACK_SIZE).1;
+    }
+
+    // 一个用于本 CPU 的系统调用，另一个在系统调用睡眠时切换过去
+    let (current, current_end) = alloc_stack(SYSCALL_STACK_SIZE);
+    let (spare, _) = alloc_stack(SYSCALL_STACK_SIZE);
+    let mut pool = SYSCALL_STACK_POOL.lock();
+    pool.add(current, StackState::Current(cpu as u32));
+    pool.add(spare, StackState::Free);
+    drop(pool);
+    tss.interrupt_stack_table[SYSCALL_IST_INDEX as usize] = current_end;
+
+    let tss: &'static mut TaskStateSegment = Box::leak(Box::new(tss));
+    tss_slot(cpu).store(tss as *mut TaskStateSegment as u64, Ordering::Relaxed);
+
+    load(Box::leak(Box::new(build_gdt(tss))));
+}
+
+/// Keep the syscall stack in use by the caller for a sleeping syscall
+///
+/// If the syscall IST of this CPU still points to this stack, it is
+/// switched to a free one, so the next syscall does not overwrite the
+/// sleeping one.
+/// Returns the index of the kept stack, or `None` if the caller is not
+/// running on a syscall stack.
+pub fn park_syscall_stack() -> Option<usize> {
+    let rsp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    }
+    let cpu = crate::proc::processor::apic_id() as u32;
+
+    let mut pool = SYSCALL_STACK_POOL.lock();
+    let idx = pool.find(VirtAddr::new(rsp))?;
+
+    if pool.state[idx] == StackState::Current(cpu) {
+        let next = pool.find_free(cpu);
+
+        // the CPU reads the IST from the TSS on every interrupt
+        unsafe {
+            (*current_tss()).interrupt_stack_table[SYSCALL_IST_INDEX as usize] = pool.end(next);
+        }
+
+        pool.state[next] = StackState::Current(cpu);
+    }
+
+    pool.state[idx] = StackState::Parked;
+    Some(idx)
+}
+
+/// Release a stack kept by `park_syscall_stack` once the syscall has woken up
+///
+/// Must be called with interrupts disabled, the stack is still in use
+/// until the syscall returns, so only this CPU can take it again.
+pub fn unpark_syscall_stack(idx: usize) {
+    let cpu = crate::proc::processor::apic_id() as u32;
+    SYSCALL_STACK_POOL.lock().state[idx] = StackState::Released(cpu);
+}
+
 pub fn get_selector() -> &'static KernelSelectors {
     &GDT.1
 }
@@ -1,6 +1,7 @@
 use volatile::{access::ReadOnly, Volatile};
 use x86_64::{registers::rflags::RFlags, structures::idt::InterruptStackFrameValue, VirtAddr};
 use x86_64::structures::gdt::SegmentSelector;
+use x86_64::PrivilegeLevel;
 
 use crate::RegistersValue;
 
@@ -33,6 +34,12 @@ impl ProcessContext {
         self.value.regs.rax = value;
     }
 
+    /// Whether the context was saved while running in user mode
+    #[inline]
+    pub fn is_user(&self) -> bool {
+        self.value.stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3
+    }
+
     #[inline]
     pub fn set_rsp(&mut self, value: VirtAddr) {
         self.value.stack_frame.stack_pointer = value;
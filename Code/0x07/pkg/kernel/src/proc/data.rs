@@ -2,7 +2,7 @@ use alloc::{collections::BTreeMap, sync::Arc};
 use spin::RwLock;
 use super::sync::SemaphoreSet;
 
-use crate::utils::ResourceSet;
+use crate::utils::{ResourceSet, resource::FILE_WAITERS};
 
 use super::*;
 
@@ -37,6 +37,22 @@ impl ProcessData {
         Self::default()
     }
 
+    /// Data of a process whose stdio is the terminal `tty`
+    pub fn with_tty(tty: crate::drivers::tty::Tty) -> Self {
+        Self {
+            resources: Arc::new(RwLock::new(ResourceSet::with_tty(tty))),
+            ..Self::default()
+        }
+    }
+
+    /// Data of a child process, which gets the stdio of `parent`
+    pub fn inherit_stdio(parent: &ProcessData) -> Self {
+        Self {
+            resources: Arc::new(RwLock::new(ResourceSet::inherit_stdio(&parent.resources.read()))),
+            ..Self::default()
+        }
+    }
+
     // Updates memory usage based on loaded ELF segments and stack size
     pub(super) fn update_memory_usage(&mut self, code_bytes: u64, stack_pages: u64) {
         self.code_bytes = code_bytes;
@@ -66,7 +82,38 @@ impl ProcessData {
     
     // 添加读取资源的方法
     pub fn read(&self, fd: u8, buf: &mut [u8]) -> isize {
-        self.resources.read().read(fd, buf)
+        // 读终端会睡眠到有输入为止，不能持有资源表的锁
+        let (tty, nonblocking) = {
+            let resources = self.resources.read();
+            (resources.tty(fd), resources.is_nonblocking(fd))
+        };
+        if let Some(tty) = tty {
+            return tty.read(buf, nonblocking);
+        }
+
+        // 读文件可能睡眠等待磁盘，先把文件从表中取出
+        loop {
+            let file = self.resources.read().take_file(fd);
+            match file {
+                Ok(mut file) => {
+                    let count = file.read(buf).map_or(-1, |count| count as isize);
+                    self.resources.read().put_back_file(fd, file);
+
+                    let manager = get_process_manager();
+                    for pid in core::mem::take(&mut *FILE_WAITERS.lock()) {
+                        manager.wake_up(pid, None);
+                    }
+                    return count;
+                }
+                // 其他进程正在读同一个文件，等它放回
+                Err(true) => {
+                    if !sleep_on(&FILE_WAITERS, || !self.resources.read().file_in_use(fd)) {
+                        return -1;
+                    }
+                }
+                Err(false) => return self.resources.read().read(fd, buf),
+            }
+        }
     }
 
     // 添加写入资源的方法
@@ -83,4 +130,22 @@ impl ProcessData {
     pub fn close_resource(&self, fd: u8) -> bool {
         self.resources.write().close(fd)
     }
+
+    pub fn is_nonblocking(&self, fd: u8) -> bool {
+        self.resources.read().is_nonblocking(fd)
+    }
+
+    pub fn set_nonblocking(&self, fd: u8, nonblocking: bool) {
+        self.resources.write().set_nonblocking(fd, nonblocking);
+    }
+
+    // 获取打开的终端
+    pub fn tty(&self, fd: u8) -> Option<crate::drivers::tty::Tty> {
+        self.resources.read().tty(fd)
+    }
+
+    // 获取文件锁的键
+    pub fn file_key(&self, fd: u8) -> Option<crate::utils::flock::FileKey> {
+        self.resources.read().file_key(fd)
+    }
 }
@@ -1,5 +1,5 @@
 use super::{vm, *};
-use crate::memory::{get_frame_alloc_for_sure, PAGE_SIZE};
+use crate::memory::{wait_frame_alloc, PAGE_SIZE};
 use crate::utils::humanized_size;
 use alloc::{collections::*, format, sync::Arc};
 use alloc::sync::Weak;
@@ -58,9 +58,41 @@ impl ProcessManager {
         self.app_list
     }
 
+    /// Queue a process that became ready, and wake up an idle CPU to run it
     #[inline]
     pub fn push_ready(&self, pid: ProcessId) {
-        self.ready_queue.lock().push_back(pid);
+        self.enqueue(pid);
+
+        // 内核进程只在 BSP 上运行
+        if let Some(cpu) = (pid != KERNEL_PID).then(processor::idle_cpu).flatten() {
+            crate::interrupt::reschedule(cpu);
+        }
+    }
+
+    /// Put back a process taken off the CPU at the end of the ready queue
+    #[inline]
+    pub(super) fn requeue(&self, pid: ProcessId) {
+        self.enqueue(pid);
+    }
+
+    /// Queue `pid` unless it is in the queue already
+    ///
+    /// A process woken up in `sleep_on` before its CPU switched away from
+    /// it is queued by the wake-up, then again when its context is saved.
+    fn enqueue(&self, pid: ProcessId) {
+        let mut ready_queue = self.ready_queue.lock();
+        if !ready_queue.contains(&pid) {
+            ready_queue.push_back(pid);
+        }
+    }
+
+    /// Whether the current CPU can take `pid` from the ready queue
+    ///
+    /// The kernel process stays on the bootstrap processor. A process
+    /// woken up before another CPU switched away from it is left there
+    /// until its context is saved.
+    fn runnable_here(pid: ProcessId) -> bool {
+        (pid != KERNEL_PID || processor::is_bsp()) && !processor::running_elsewhere(pid)
     }
 
     #[inline]
@@ -88,18 +120,45 @@ impl ProcessManager {
         proc_inner.save(context);
         
         // 如果进程状态不是Dead，将其加入就绪队列
-        if proc_inner.status() == ProgramStatus::Ready {
+        if proc_inner.status() == ProgramStatus::Ready && !processor::is_idle(current_pid) {
             drop(proc_inner); // 提前释放锁，避免死锁
-            self.push_ready(current_pid);
+            self.requeue(current_pid);
         }
     }
 
     pub fn switch_next(&self, context: &mut ProcessContext) -> ProcessId {
+        let prev_pid = processor::get_pid();
+        let next_pid = self.restore_next(context);
+
+        // 在其他 CPU 上被杀死或刚刚退出的进程，切换到新页表后再释放其资源
+        if let Some(prev) = self.get_proc(&prev_pid).filter(|_| prev_pid != next_pid) {
+            let mut prev_inner = prev.write();
+            if prev_inner.status() == ProgramStatus::Dead {
+                prev_inner.release();
+            }
+        }
+
+        next_pid
+    }
+
+    fn restore_next(&self, context: &mut ProcessContext) -> ProcessId {
         // 获取就绪队列的互斥锁
         let mut ready_queue = self.ready_queue.lock();
-        
+        // 不能在这个 CPU 上运行的进程放回队尾，每个进程最多看一次
+        let mut remaining = ready_queue.len();
+
         // 从就绪队列中取出下一个进程
-        while let Some(next_pid) = ready_queue.pop_front() {
+        while remaining > 0 {
+            let Some(next_pid) = ready_queue.pop_front() else {
+                break;
+            };
+            remaining -= 1;
+
+            if !Self::runnable_here(next_pid) {
+                ready_queue.push_back(next_pid);
+                continue;
+            }
+
             // 释放就绪队列的锁，以避免死锁
             drop(ready_queue);
             
@@ -108,8 +167,8 @@ impl ProcessManager {
                 // 检查进程状态
                 let mut next_inner = next_proc.write();
                 
-                // 如果进程已经就绪，则恢复其上下文
-                if next_inner.status() == ProgramStatus::Ready {
+                // 如果进程已经就绪且未被暂停，则恢复其上下文
+                if next_inner.status() == ProgramStatus::Ready && !next_inner.is_stopped() {
                     // 恢复进程上下文和页表
                     next_inner.restore(context);
                     
@@ -121,36 +180,42 @@ impl ProcessManager {
                     return next_pid;
                 }
                 
-                // 如果进程不是就绪状态（可能是死亡或阻塞），则继续寻找下一个进程
+                // 如果进程不是就绪状态（可能是死亡、阻塞或被暂停），则继续寻找下一个进程
                 drop(next_inner);
             }
             
             // 重新获取就绪队列的锁
             ready_queue = self.ready_queue.lock();
         }
+        drop(ready_queue);
+
+        // 没有可运行的进程，运行这个 CPU 的空闲进程，它自己会 hlt
+        let idle_pid = processor::idle_pid();
+        let idle = self.get_proc(&idle_pid).expect("Idle process not found");
+        let mut idle_inner = idle.write();
         
-        // 如果就绪队列为空，获取当前PID
-        let current_pid = processor::get_pid();
-        
-        // 检查当前是否已经是内核进程
-        if current_pid == KERNEL_PID {
-            // 如果当前已经是内核进程，使用hlt指令让CPU空闲一会儿
-            // 这样可以减少内核进程的执行频率，让其他进程有更多机会被调度
-            x86_64::instructions::hlt();
-        }
-        
-        // 获取内核进程
-        let kernel = self.get_proc(&KERNEL_PID).expect("Kernel process not found");
-        let mut kernel_inner = kernel.write();
-        
-        // 恢复内核进程上下文
-        kernel_inner.restore(context);
+        // 恢复空闲进程上下文
+        idle_inner.restore(context);
         
         // 更新当前处理器的PID
-        processor::set_pid(KERNEL_PID);
+        processor::set_pid(idle_pid);
         
-        // 返回内核进程PID
-        KERNEL_PID
+        idle_pid
+    }
+
+    /// Add the idle process of an application processor, it takes the
+    /// context of the code running when the first timer interrupt comes
+    pub fn add_idle(&self, name: String) -> ProcessId {
+        let kproc = self.get_proc(&KERNEL_PID).expect("Kernel process not found");
+        let page_table = kproc.read().clone_page_table();
+        let proc_vm = Some(ProcessVm::new(page_table, true));
+        let proc = Process::new(name, Some(Arc::downgrade(&kproc)), proc_vm, None);
+
+        let pid = proc.pid();
+        proc.write().resume();
+        self.add_proc(pid, proc);
+
+        pid
     }
 
     pub fn spawn_kernel_thread(
@@ -288,6 +353,11 @@ impl ProcessManager {
 
         proc.kill(ret);
 
+        // 释放进程持有的文件锁，并唤醒获得锁的进程
+        for pid in crate::utils::flock::release_process(pid) {
+            self.wake_up(pid, Some(0));
+        }
+
         // Wake up processes waiting for this one, using variable names from the document
         if let Some(pids) = self.wait_queue.lock().remove(&pid) { // 'pid' is the one being killed
             for pid_to_wake_up in pids { // 'pid_to_wake_up' (from the set) is called 'pid' in the document snippet
@@ -296,6 +366,49 @@ impl ProcessManager {
         }
     }
 
+    /// Kill a process on a signal
+    ///
+    /// A process in the middle of a syscall is only marked, it is woken
+    /// up if sleeping and killed before it goes back to user mode.
+    pub fn interrupt(&self, pid: ProcessId, ret: isize) {
+        let Some(proc) = self.get_proc(&pid) else {
+            return;
+        };
+
+        let mut inner = proc.write();
+        if inner.status() == ProgramStatus::Dead {
+            return;
+        }
+
+        if processor::running_on(pid).is_none() && inner.context.is_user() {
+            drop(inner);
+            self.kill(pid, ret);
+            return;
+        }
+
+        inner.set_pending_kill(ret);
+        drop(inner);
+        self.wake_up(pid, None);
+    }
+
+    /// Kill the current process if a signal is pending for it, `context`
+    /// is where it would go on
+    ///
+    /// Returns whether it was killed, then it must be switched away from.
+    pub fn kill_pending(&self, context: &ProcessContext) -> bool {
+        let pid = processor::get_pid();
+        let current = self.current();
+        let pending = current.read().pending_kill();
+
+        match pending {
+            Some(ret) if context.is_user() && current.read().status() != ProgramStatus::Dead => {
+                self.kill(pid, ret);
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn get_exit_code(&self, pid: ProcessId) -> Option<isize> {
         // 获取进程对象
         if let Some(proc) = self.get_proc(&pid) {
@@ -394,7 +507,7 @@ impl ProcessManager {
             .map(|p| p.read().vm().memory_usage())
             .sum();
 
-        let alloc = get_frame_alloc_for_sure();
+        let alloc = wait_frame_alloc();
         let frames_total = alloc.frames_total();
         let total = frames_total * PAGE_SIZE as usize;
 
@@ -13,6 +13,7 @@ use process::*;
 use vm::ProcessVm;
 
 use alloc::string::{String, ToString};
+use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use alloc::sync::Arc;
 pub use context::ProcessContext;
@@ -21,10 +22,14 @@ pub use data::ProcessData;
 pub use pid::ProcessId;
 use xmas_elf::ElfFile;
 use storage::FileSystem;
+use crate::drivers::tty::{Tty, TtyMode, TtySignal};
+use spin::Mutex;
 
 use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::VirtAddr;
 pub const KERNEL_PID: ProcessId = ProcessId(1);
+/// Exit code of a process interrupted by Ctrl-C, 128 + SIGINT as in shells
+pub const INTERRUPTED: isize = 130;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ProgramStatus {
@@ -36,6 +41,13 @@ pub enum ProgramStatus {
 
 /// init process manager
 pub fn init(boot_info: &'static boot::BootInfo) {
+    // 按 MADT 中最大的 APIC ID 分配处理器
+    let max_apic_id = crate::drivers::acpi::info()
+        .and_then(|acpi| acpi.madt.as_ref())
+        .and_then(|madt| madt.max_apic_id())
+        .unwrap_or(0);
+    processor::init(max_apic_id as usize);
+
     let proc_vm = ProcessVm::new(PageTableContext::new(), true).init_kernel_vm(&boot_info.kernel_pages);
 
     trace!("Init kernel vm: {:#?}", proc_vm);
@@ -67,12 +79,26 @@ pub fn init(boot_info: &'static boot::BootInfo) {
     info!("Process Manager Initialized.");
 }
 
+/// Register the current application processor, the code calling this
+/// becomes its idle process
+pub fn init_ap() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let name = alloc::format!("idle/{}", processor::apic_id());
+        let idle = get_process_manager().add_idle(name);
+        processor::set_online(idle);
+    });
+}
+
 pub fn switch(context: &mut ProcessContext) {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let process_manager = get_process_manager();
         // 保存当前进程上下文
         process_manager.save_current(context);
-        
+
+        // 处理终端上的 Ctrl-C / Ctrl-Z，在内核中被 Ctrl-C 的进程回到用户态前结束
+        deliver_tty_signals();
+        process_manager.kill_pending(context);
+
         // 获取当前进程，检查状态并添加到就绪队列
         let pro = process_manager.current();
         let current_pid = pro.pid();
@@ -82,15 +108,17 @@ pub fn switch(context: &mut ProcessContext) {
         
         // 如果进程不是Dead或Blocked状态，将其添加到就绪队列
         if status != ProgramStatus::Dead && status != ProgramStatus::Blocked {
-            // 将进程加入就绪队列
-            process_manager.push_ready(current_pid);
+            // 将进程加入就绪队列，空闲进程除外
+            if !processor::is_idle(current_pid) {
+                process_manager.requeue(current_pid);
+            }
             
             // 获取写锁并修改状态
             let mut proc_guard = pro.write();
             proc_guard.pause(); // 设置为Ready状态
             proc_guard.tick();  // 更新进程的时间片计数
         }
-        
+
         // 切换到下一个进程
         let next_pid = process_manager.switch_next(context);
         
@@ -108,6 +136,30 @@ pub fn switch(context: &mut ProcessContext) {
     });
 }
 
+/// Deliver the signals raised by the terminals to their foreground jobs
+fn deliver_tty_signals() {
+    let manager = get_process_manager();
+
+    for tty in Tty::ALL {
+        let Some(signal) = tty.take_signal() else {
+            continue;
+        };
+        let Some(pid) = tty.foreground() else {
+            continue;
+        };
+
+        match signal {
+            TtySignal::Interrupt => manager.interrupt(pid, INTERRUPTED),
+            TtySignal::Suspend => {
+                if let Some(proc) = manager.get_proc(&pid) {
+                    proc.write().stop();
+                    tty.stop_job(pid);
+                }
+            }
+        }
+    }
+}
+
 pub fn spawn_kernel_thread(entry: fn() -> !, name: String, data: Option<ProcessData>) -> ProcessId {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let entry = VirtAddr::new(entry as usize as u64);
@@ -185,10 +237,21 @@ pub fn list_app() {
 }
 
 pub fn spawn(path: &str) -> Option<ProcessId> {
+    spawn_with(path, None)
+}
+
+/// Spawn the program at `path` with the terminal `tty` as its stdio
+pub fn spawn_on_tty(path: &str, tty: Tty) -> Option<ProcessId> {
+    spawn_with(path, Some(ProcessData::with_tty(tty)))
+}
+
+/// Spawn the program at `path`, the stdio of the current process is
+/// inherited if `data` is None
+fn spawn_with(path: &str, data: Option<ProcessData>) -> Option<ProcessId> {
     use alloc::boxed::Box;
 
     // 首先尝试从文件路径加载
-    if let Ok(mut file_handle) = crate::drivers::filesystem::get_rootfs().open_file(path) {
+    if let Ok(mut file_handle) = crate::drivers::filesystem::get_fs(path).open_file(path) {
         // 获取文件大小并分配缓冲区
         let file_size = file_handle.meta.len;
         let mut buffer = alloc::vec![0u8; file_size];
@@ -212,7 +275,7 @@ pub fn spawn(path: &str) -> Option<ProcessId> {
                     }
 
                     if entry_point != 0 {
-                        return elf_spawn(process_name, &elf);
+                        return elf_spawn(process_name, &elf, data);
                     }
                 }
             }
@@ -234,17 +297,28 @@ pub fn spawn(path: &str) -> Option<ProcessId> {
         info!("ELF header bytes: {:02x?}", &app.elf.input[0..16]);
     }
 
-    elf_spawn(path.to_string(), &app.elf)
+    elf_spawn(path.to_string(), &app.elf, data)
 }
 
 
 
-pub fn elf_spawn(name: String, elf: &ElfFile) -> Option<ProcessId> {
+pub fn elf_spawn(name: String, elf: &ElfFile, data: Option<ProcessData>) -> Option<ProcessId> {
     let pid = x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
         let process_name = name.to_lowercase();
-        let parent = Arc::downgrade(&manager.current());
-        let pid = manager.spawn(elf, name, Some(parent), None);
+        let current = manager.current();
+        // 用户进程启动的程序在其终端的前台运行
+        let job_tty = match data {
+            None if current.pid() != KERNEL_PID => current.read().proc_data().and_then(|d| d.tty(0)),
+            _ => None,
+        };
+        let data = data.or_else(|| current.read().proc_data().map(ProcessData::inherit_stdio));
+        let parent = Arc::downgrade(&current);
+        let pid = manager.spawn(elf, name, Some(parent), data);
+
+        if let Some(tty) = job_tty {
+            tty.push_job(pid);
+        }
 
         debug!("Spawned process: {}#{}", process_name, pid);
         pid
@@ -254,22 +328,140 @@ pub fn elf_spawn(name: String, elf: &ElfFile) -> Option<ProcessId> {
 }
 
 pub fn read(fd: u8, buf: &mut [u8]) -> isize {
-    x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().current().read().read(fd, buf))
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // 读文件可能在系统调用中睡眠，不能持有进程的锁
+        let proc_data = get_process_manager().current().read().proc_data().unwrap().clone();
+        proc_data.read(fd, buf)
+    })
 }
 
 pub fn write(fd: u8, buf: &[u8]) -> isize {
-    x86_64::instructions::interrupts::without_interrupts(|| get_process_manager().current().read().write(fd, buf))
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let proc_data = get_process_manager().current().read().proc_data().unwrap().clone();
+        proc_data.write(fd, buf)
+    })
+}
+
+/// Why a process stopped sleeping before its condition held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepError {
+    /// The process cannot sleep here, the caller should fall back to
+    /// polling
+    Unable,
+    /// The process was killed by a signal, the syscall should return
+    Interrupted,
+}
+
+/// Sleep inside a syscall until `cond` holds
+///
+/// The current process is blocked and queued on `queue`, whoever makes
+/// `cond` true should wake up the processes in it. The syscall stack is
+/// kept aside while sleeping, so other processes can still make syscalls.
+///
+/// The kernel process is never blocked, it just halts until `cond` holds.
+///
+/// Returns `false` if the process cannot sleep here, the caller should
+/// fall back to polling.
+pub fn sleep_on(queue: &Mutex<VecDeque<ProcessId>>, cond: impl Fn() -> bool) -> bool {
+    sleep(queue, cond, false).is_ok()
+}
+
+/// `sleep_on`, but a process killed by Ctrl-C stops sleeping
+pub fn sleep_on_interruptible(
+    queue: &Mutex<VecDeque<ProcessId>>,
+    cond: impl Fn() -> bool,
+) -> Result<(), SleepError> {
+    sleep(queue, cond, true)
+}
+
+fn sleep(
+    queue: &Mutex<VecDeque<ProcessId>>,
+    cond: impl Fn() -> bool,
+    interruptible: bool,
+) -> Result<(), SleepError> {
+    use crate::memory::gdt;
+    use x86_64::instructions::interrupts;
+
+    if cond() {
+        return Ok(());
+    }
+
+    let manager = get_process_manager();
+    let pid = processor::get_pid();
+
+    if pid == KERNEL_PID {
+        let enabled = interrupts::are_enabled();
+        while !cond() {
+            interrupts::enable_and_hlt();
+            interrupts::disable();
+        }
+        if enabled {
+            interrupts::enable();
+        }
+        return Ok(());
+    }
+
+    let Some(stack) = gdt::park_syscall_stack() else {
+        return Err(SleepError::Unable);
+    };
+
+    let current = manager.current();
+    let mut result = Ok(());
+
+    while !cond() {
+        queue.lock().push_back(pid);
+        current.write().block();
+
+        // 被 Ctrl-C 的进程会被唤醒，阻塞之后再检查以免错过唤醒
+        if interruptible && current.read().pending_kill().is_some() {
+            result = Err(SleepError::Interrupted);
+            break;
+        }
+
+        // 时钟中断会切换到其他进程，被唤醒并重新调度后从这里继续
+        interrupts::enable_and_hlt();
+        interrupts::disable();
+    }
+
+    queue.lock().retain(|&p| p != pid);
+
+    // 可能在被调度出去之前就已经被唤醒
+    if current.read().status() != ProgramStatus::Running {
+        current.write().resume();
+    }
+
+    gdt::unpark_syscall_stack(stack);
+
+    result
 }
 
 pub fn open_file(path: &str) -> Result<u8, ()> {
     x86_64::instructions::interrupts::without_interrupts(|| {
+        // 终端设备文件
+        if let Some(tty) = Tty::from_path(path) {
+            if !tty.is_present() {
+                return Err(());
+            }
+            let current_proc = get_process_manager().current();
+            let proc_data = current_proc.read().proc_data().unwrap().clone();
+            return Ok(proc_data.open_resource(crate::utils::Resource::Tty(tty)));
+        }
+
+        // 随机数设备文件
+        if crate::drivers::random::is_device(path) {
+            let current_proc = get_process_manager().current();
+            let proc_data = current_proc.read().proc_data().unwrap().clone();
+            return Ok(proc_data.open_resource(crate::utils::Resource::Random));
+        }
+
         // 尝试打开文件
-        match crate::drivers::filesystem::get_rootfs().open_file(path) {
+        match crate::drivers::filesystem::get_fs(path).open_file(path) {
             Ok(file_handle) => {
                 // 获取当前进程并添加文件到资源集合
                 let current_proc = get_process_manager().current();
                 let proc_data = current_proc.read().proc_data().unwrap().clone();
-                let fd = proc_data.open_resource(crate::utils::Resource::File(file_handle));
+                let key = crate::utils::flock::FileKey::new(path);
+                let fd = proc_data.open_resource(crate::utils::Resource::File(file_handle, key));
                 Ok(fd)
             }
             Err(_) => Err(()),
@@ -285,6 +477,144 @@ pub fn close_file(fd: u8) -> bool {
     })
 }
 
+pub fn flock(fd: u8, op: usize, context: &mut ProcessContext) {
+    use crate::utils::flock::{self, FileLockResult, LockKind};
+    use ysos_syscall::{LOCK_EX, LOCK_NB, LOCK_SH, LOCK_UN};
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let manager = get_process_manager();
+        let pid = processor::get_pid();
+        let key = manager.current().read().proc_data().and_then(|data| data.file_key(fd));
+
+        let Some(key) = key else {
+            context.set_rax(1);
+            return;
+        };
+
+        let nonblock = op & LOCK_NB != 0;
+        let (ret, woken) = match op & !LOCK_NB {
+            LOCK_SH => flock::lock(&key, pid, LockKind::Shared, nonblock),
+            LOCK_EX => flock::lock(&key, pid, LockKind::Exclusive, nonblock),
+            LOCK_UN => (FileLockResult::Ok, flock::unlock(&key, pid)),
+            _ => {
+                context.set_rax(1);
+                return;
+            }
+        };
+
+        for pid in woken {
+            manager.wake_up(pid, Some(0));
+        }
+
+        match ret {
+            FileLockResult::Ok => context.set_rax(0),
+            FileLockResult::WouldBlock => context.set_rax(2),
+            FileLockResult::Block(pid) => {
+                manager.save_current(context);
+                manager.block(pid);
+                manager.switch_next(context);
+            }
+        }
+    })
+}
+
+/// Control the terminal opened as `fd`
+///
+/// Returns -1 if `fd` is not a terminal or the request is unknown, -EFAULT
+/// if `arg` does not point to user memory and -EINVAL if the settings of
+/// `TTY_SET_ATTR` are not valid.
+pub fn ioctl(fd: u8, request: usize, arg: usize) -> isize {
+    use ysos_syscall::{
+        EFAULT, EINVAL, SerialAttr, TTY_CONTINUE, TTY_GET_ATTR, TTY_GET_MODE, TTY_NONBLOCK,
+        TTY_SET_ATTR, TTY_SET_MODE,
+    };
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let manager = get_process_manager();
+        let Some(proc_data) = manager.current().read().proc_data().cloned() else {
+            return -1;
+        };
+
+        let Some(tty) = proc_data.tty(fd) else {
+            return -1;
+        };
+
+        match request {
+            TTY_GET_MODE => {
+                let nonblock = if proc_data.is_nonblocking(fd) { TTY_NONBLOCK } else { 0 };
+                (tty.mode().bits() | nonblock) as isize
+            }
+            // 非阻塞属于文件描述符，其余的位属于终端
+            TTY_SET_MODE => match TtyMode::from_bits(arg & !TTY_NONBLOCK) {
+                Some(mode) => {
+                    tty.set_mode(mode);
+                    proc_data.set_nonblocking(fd, arg & TTY_NONBLOCK != 0);
+                    0
+                }
+                None => -1,
+            },
+            TTY_CONTINUE => {
+                let Some(pid) = tty.continue_job() else {
+                    return 0;
+                };
+                if let Some(proc) = manager.get_proc(&pid) {
+                    let mut inner = proc.write();
+                    inner.cont();
+                    // 阻塞的进程之后会被唤醒
+                    if inner.status() == ProgramStatus::Ready {
+                        drop(inner);
+                        manager.push_ready(pid);
+                    }
+                }
+                pid.0 as isize
+            }
+            TTY_GET_ATTR | TTY_SET_ATTR
+                if arg == 0 || !crate::memory::is_user_range(arg as u64, size_of::<SerialAttr>()) =>
+            {
+                -EFAULT
+            }
+            TTY_GET_ATTR => match tty.serial_attr() {
+                Some(attr) => {
+                    unsafe { (arg as *mut SerialAttr).write_unaligned(attr) };
+                    0
+                }
+                None => -1,
+            },
+            TTY_SET_ATTR => {
+                let bytes = unsafe { (arg as *const [u8; size_of::<SerialAttr>()]).read_unaligned() };
+                match read_serial_attr(&bytes) {
+                    Some(attr) if tty.set_serial_attr(&attr) => 0,
+                    _ => -EINVAL,
+                }
+            }
+            _ => -1,
+        }
+    })
+}
+
+/// Decode a `SerialAttr` written by a user program, which may hold any
+/// byte where a `bool` is expected
+fn read_serial_attr(bytes: &[u8; size_of::<ysos_syscall::SerialAttr>()]) -> Option<ysos_syscall::SerialAttr> {
+    use core::mem::offset_of;
+    use ysos_syscall::SerialAttr;
+
+    let byte = |offset: usize| bytes[offset];
+    let baud = offset_of!(SerialAttr, baud_rate);
+
+    Some(SerialAttr {
+        baud_rate: u32::from_ne_bytes(bytes[baud..baud + 4].try_into().unwrap()),
+        data_bits: byte(offset_of!(SerialAttr, data_bits)),
+        parity: byte(offset_of!(SerialAttr, parity)),
+        stop_bits: byte(offset_of!(SerialAttr, stop_bits)),
+        fifo_trigger: byte(offset_of!(SerialAttr, fifo_trigger)),
+        flow_control: match byte(offset_of!(SerialAttr, flow_control)) {
+            0 => false,
+            1 => true,
+            _ => return None,
+        },
+    })
+}
+
 pub fn exit(ret: isize, context: &mut ProcessContext) {
     x86_64::instructions::interrupts::without_interrupts(|| {
         let manager = get_process_manager();
@@ -306,6 +636,15 @@ pub fn still_alive(pid: ProcessId) -> bool {
     })
 }
 
+/// Whether the process is stopped by Ctrl-Z
+pub fn is_stopped(pid: ProcessId) -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        get_process_manager()
+            .get_proc(&pid)
+            .is_some_and(|proc| proc.read().is_stopped())
+    })
+}
+
 pub fn brk(addr: Option<VirtAddr>) -> Option<VirtAddr> {
     x86_64::instructions::interrupts::without_interrupts(|| {
         // NOTE: `brk` does not need to get write lock
@@ -34,7 +34,7 @@ impl PageTableContext {
     /// Create a new page table object based on current page table.
     pub fn clone_level_4(&self) -> Self {
         // 1. alloc new page table
-        let mut frame_alloc = crate::memory::get_frame_alloc_for_sure();
+        let mut frame_alloc = crate::memory::wait_frame_alloc();
         let page_table_addr = frame_alloc
             .allocate_frame()
             .expect("Cannot alloc page table for new process.");
@@ -18,6 +18,11 @@ pub struct ProcessInner {
     children: Vec<Arc<Process>>,
     ticks_passed: usize,
     pub status: ProgramStatus,
+    /// Stopped by Ctrl-Z, not scheduled until continued
+    stopped: bool,
+    /// Killed by Ctrl-C while in the kernel, the exit code to use once it
+    /// is about to go back to user mode
+    pending_kill: Option<isize>,
     pub context: ProcessContext,
     exit_code: Option<isize>,
     proc_data: Option<ProcessData>,
@@ -56,6 +61,8 @@ impl Process {
             name,
             parent,
             status: ProgramStatus::Ready,
+            stopped: false,
+            pending_kill: None,
             context: ProcessContext::default(),
             ticks_passed: 0,
             exit_code: None,
@@ -88,6 +95,8 @@ impl Process {
             name,
             parent,
             status: ProgramStatus::Ready,
+            stopped: false,
+            pending_kill: None,
             context: ProcessContext::default(),
             ticks_passed: 0,
             exit_code: None,
@@ -116,6 +125,15 @@ impl Process {
         );
 
         inner.kill(ret);
+
+        // 仍在某个 CPU 上运行的进程，等它切换出去后再释放内存和数据
+        match processor::running_on(self.pid) {
+            None => inner.release(),
+            Some(cpu) if processor::running_elsewhere(self.pid) => {
+                crate::interrupt::reschedule(cpu);
+            }
+            Some(_) => {}
+        }
     }
 
     pub fn fork(self: &Arc<Self>) -> Arc<Self> {
@@ -155,16 +173,45 @@ impl ProcessInner {
         self.status
     }
 
+    // 进程可能已经被其他 CPU 杀死，死亡的进程不再改变状态
     pub fn pause(&mut self) {
-        self.status = ProgramStatus::Ready;
+        if self.status != ProgramStatus::Dead {
+            self.status = ProgramStatus::Ready;
+        }
     }
 
     pub fn resume(&mut self) {
-        self.status = ProgramStatus::Running;
+        if self.status != ProgramStatus::Dead {
+            self.status = ProgramStatus::Running;
+        }
     }
 
     pub fn block(&mut self) {
-        self.status = ProgramStatus::Blocked;
+        if self.status != ProgramStatus::Dead {
+            self.status = ProgramStatus::Blocked;
+        }
+    }
+
+    /// Keep the process off the CPU, whatever its status is
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Undo `stop`, the caller puts it back to the ready queue if ready
+    pub fn cont(&mut self) {
+        self.stopped = false;
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn set_pending_kill(&mut self, ret: isize) {
+        self.pending_kill = Some(ret);
+    }
+
+    pub fn pending_kill(&self) -> Option<isize> {
+        self.pending_kill
     }
 
     pub fn exit_code(&self) -> Option<isize> {
@@ -221,6 +268,10 @@ impl ProcessInner {
 
         self.exit_code = Some(ret);
         self.status = ProgramStatus::Dead;
+    }
+
+    /// Free the data and the memory of a dead process, once no CPU runs it
+    pub fn release(&mut self) {
         self.proc_data = None;
 
         // consume the Option<ProcessVm> and drop it
@@ -267,6 +318,8 @@ impl ProcessInner {
             children: Vec::new(),
             ticks_passed: 0,
             status: ProgramStatus::Ready,
+            stopped: false,
+            pending_kill: None,
             context: child_context,
             exit_code: None,
             proc_data: Some(child_proc_data),
@@ -1,31 +1,143 @@
-use core::sync::atomic::{AtomicU16, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering};
 
-use crate::proc::ProcessId;
+use crate::proc::{ProcessId, KERNEL_PID};
 use alloc::{string::String, vec::Vec};
+use spin::Once;
 use x86::cpuid::CpuId;
 
-const MAX_CPU_COUNT: usize = 4;
+/// The processors, indexed by their local APIC ID
+static PROCESSORS: Once<Vec<Processor>> = Once::new();
 
-#[allow(clippy::declare_interior_mutable_const)]
-const EMPTY: Processor = Processor::new(); // means no process
+/// Local APIC ID of the bootstrap processor
+static BSP_ID: AtomicUsize = AtomicUsize::new(0);
 
-static PROCESSORS: [Processor; MAX_CPU_COUNT] = [EMPTY; MAX_CPU_COUNT];
+/// Local APIC ID of the current CPU
+///
+/// The 32-bit x2APIC ID of CPUID leaf 0xB if there is one, the 8-bit ID
+/// of leaf 1 only holds its low byte.
+pub fn apic_id() -> usize {
+    let cpuid = CpuId::new();
+    match cpuid.get_extended_topology_info().and_then(|mut levels| levels.next()) {
+        Some(level) => level.x2apic_id() as usize,
+        None => cpuid.get_feature_info().unwrap().initial_local_apic_id() as usize,
+    }
+}
+
+/// Make room for the processors with a local APIC ID up to `max_apic_id`,
+/// called by the bootstrap processor
+pub fn init(max_apic_id: usize) {
+    let id = apic_id();
+    // the current one at least, even if the MADT missed it
+    let count = max_apic_id.max(id) + 1;
+    PROCESSORS.call_once(|| (0..count).map(|_| Processor::new()).collect());
+
+    BSP_ID.store(id, Ordering::Relaxed);
+    current().online.store(true, Ordering::Release);
+}
+
+/// Number of processor slots, the largest local APIC ID plus one
+pub fn count() -> usize {
+    processors().len()
+}
+
+fn processors() -> &'static [Processor] {
+    PROCESSORS.get().expect("Processors not initialized")
+}
 
 /// Returns the current processor based on the current APIC ID
 fn current() -> &'static Processor {
-    let cpuid = CpuId::new()
-        .get_feature_info()
-        .unwrap()
-        .initial_local_apic_id() as usize;
+    &processors()[apic_id()]
+}
+
+/// Whether the current CPU is the bootstrap processor
+#[inline]
+pub fn is_bsp() -> bool {
+    apic_id() == BSP_ID.load(Ordering::Relaxed)
+}
+
+/// An application processor is ready to run processes, `idle` runs
+/// whenever there is none
+pub fn set_online(idle: ProcessId) {
+    let processor = current();
+    processor.idle.store(idle.0, Ordering::Relaxed);
+    processor.set_pid(idle);
+    processor.online.store(true, Ordering::Release);
+}
 
-    &PROCESSORS[cpuid]
+/// Number of CPUs running processes
+pub fn online_count() -> usize {
+    processors()
+        .iter()
+        .filter(|p| p.online.load(Ordering::Acquire))
+        .count()
+}
+
+/// Local APIC IDs of the other CPUs running processes
+pub fn online_others() -> Vec<usize> {
+    let current = apic_id();
+    processors()
+        .iter()
+        .enumerate()
+        .filter(|&(id, p)| id != current && p.online.load(Ordering::Acquire))
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// The process the current CPU runs when the ready queue is empty, the
+/// kernel process on the bootstrap processor
+pub fn idle_pid() -> ProcessId {
+    match current().idle.load(Ordering::Relaxed) {
+        0 => KERNEL_PID,
+        pid => ProcessId(pid),
+    }
+}
+
+/// Whether `pid` is the idle process of an application processor, which
+/// never goes to the ready queue
+pub fn is_idle(pid: ProcessId) -> bool {
+    processors()
+        .iter()
+        .any(|p| p.idle.load(Ordering::Relaxed) == pid.0)
+}
+
+/// Local APIC ID of the CPU running `pid`, if any
+pub fn running_on(pid: ProcessId) -> Option<u32> {
+    processors()
+        .iter()
+        .position(|p| p.get_pid() == Some(pid))
+        .map(|id| id as u32)
+}
+
+/// Whether `pid` is on another CPU, its context is not saved yet then
+pub fn running_elsewhere(pid: ProcessId) -> bool {
+    running_on(pid).is_some_and(|id| id as usize != apic_id())
+}
+
+/// Local APIC ID of another CPU with nothing to run
+pub fn idle_cpu() -> Option<u32> {
+    let current = apic_id();
+    processors()
+        .iter()
+        .enumerate()
+        .filter(|&(id, p)| id != current && p.online.load(Ordering::Acquire))
+        .find(|(id, p)| {
+            let idle = if *id == BSP_ID.load(Ordering::Relaxed) {
+                KERNEL_PID
+            } else {
+                ProcessId(p.idle.load(Ordering::Relaxed))
+            };
+            p.get_pid() == Some(idle)
+        })
+        .map(|(id, _)| id as u32)
 }
 
 pub fn print_processors() -> String {
     alloc::format!(
         "CPUs   : {}\n",
         PROCESSORS
-            .iter()
+            .get()
+            .into_iter()
+            .flatten()
             .enumerate()
             .filter(|(_, p)| !p.is_free())
             .map(|(i, p)| alloc::format!("[{}: {}]", i, p.get_pid().unwrap()))
@@ -35,11 +147,20 @@ pub fn print_processors() -> String {
 }
 
 /// Processor holds the current process id
-pub struct Processor(AtomicU16);
+pub struct Processor {
+    pid: AtomicU16,
+    /// Idle process of an application processor, 0 on the bootstrap one
+    idle: AtomicU16,
+    online: AtomicBool,
+}
 
 impl Processor {
     pub const fn new() -> Self {
-        Self(AtomicU16::new(0))
+        Self {
+            pid: AtomicU16::new(0),
+            idle: AtomicU16::new(0),
+            online: AtomicBool::new(false),
+        }
     }
 }
 
@@ -56,17 +177,17 @@ pub fn get_pid() -> ProcessId {
 impl Processor {
     #[inline]
     pub fn is_free(&self) -> bool {
-        self.0.load(Ordering::Relaxed) == 0
+        self.pid.load(Ordering::Relaxed) == 0
     }
 
     #[inline]
     pub fn set_pid(&self, pid: ProcessId) {
-        self.0.store(pid.0, Ordering::Relaxed);
+        self.pid.store(pid.0, Ordering::Relaxed);
     }
 
     #[inline]
     pub fn get_pid(&self) -> Option<ProcessId> {
-        let pid = self.0.load(Ordering::Relaxed);
+        let pid = self.pid.load(Ordering::Relaxed);
         if pid == 0 {
             None
         } else {
@@ -2,7 +2,9 @@ use core::sync::atomic::{AtomicU64, Ordering};
 
 use alloc::sync::Arc;
 use x86_64::{
-    structures::paging::{mapper::UnmapError, FrameDeallocator, FrameAllocator, Mapper, Page},
+    structures::paging::{
+        mapper::UnmapError, FrameDeallocator, FrameAllocator, Mapper, Page, Size4KiB,
+    },
     VirtAddr,
 };
 
@@ -46,13 +48,16 @@ impl Heap {
         }
     }
 
+    /// Move the end of the heap, the frames of the pages unmapped go to
+    /// `dealloc`
     pub fn brk(
         &self,
         new_end: Option<VirtAddr>,
         mapper: MapperRef,
         alloc: FrameAllocatorRef,
+        dealloc: &mut impl FrameDeallocator<Size4KiB>,
     ) -> Option<VirtAddr> {
-        use x86_64::structures::paging::{PageTableFlags, Page, Size4KiB};
+        use x86_64::structures::paging::PageTableFlags;
         use core::sync::atomic::Ordering;
         
         // 如果参数为 None，返回当前的堆区结束地址
@@ -92,7 +97,7 @@ impl Heap {
                 for page in Page::range_inclusive(start_page, end_page) {
                     if let Ok((frame, flusher)) = mapper.unmap(page) {
                         unsafe {
-                            alloc.deallocate_frame(frame);
+                            dealloc.deallocate_frame(frame);
                         }
                         flusher.flush();
                     }
@@ -111,7 +116,7 @@ impl Heap {
             for page in Page::range_inclusive(start_page, end_page) {
                 if let Ok((frame, flusher)) = mapper.unmap(page) {
                     unsafe {
-                        alloc.deallocate_frame(frame);
+                        dealloc.deallocate_frame(frame);
                     }
                     flusher.flush();
                 }
@@ -32,6 +32,25 @@ impl elf::FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
+/// Frames unmapped from a page table other CPUs may be using, they are
+/// freed once the TLB shootdown is done
+#[derive(Default)]
+struct UnmappedFrames(Vec<PhysFrame>);
+
+impl FrameDeallocator<Size4KiB> for UnmappedFrames {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.0.push(frame);
+    }
+}
+
+impl UnmappedFrames {
+    fn free(self, dealloc: FrameAllocatorRef) {
+        for frame in self.0 {
+            unsafe { FrameDeallocator::deallocate_frame(dealloc, frame) };
+        }
+    }
+}
+
 pub struct ProcessVm {
     // page table is shared by parent and child
     pub(super) page_table: PageTableContext,
@@ -84,17 +103,26 @@ impl ProcessVm {
     }
 
     pub fn brk(&self, addr: Option<VirtAddr>) -> Option<VirtAddr> {
-        self.heap.brk(
-            addr,
-            &mut self.page_table.mapper(),
-            &mut get_frame_alloc_for_sure(),
-        )
+        let mut unmapped = UnmappedFrames::default();
+        let ret = {
+            let alloc = &mut *wait_frame_alloc();
+            self.heap.brk(addr, &mut self.page_table.mapper(), alloc, &mut unmapped)
+        };
+
+        // 共享页表的进程可能在其他 CPU 上，释放的页在分配出去之前要从它们的 TLB 中清除，
+        // 等待时不持有帧分配器的锁
+        if !unmapped.0.is_empty() && self.page_table.using_count() > 1 {
+            crate::interrupt::tlb_shootdown();
+        }
+        unmapped.free(&mut wait_frame_alloc());
+
+        ret
     }
 
     pub fn load_elf(&mut self, elf: &ElfFile) {
         let mapper = &mut self.page_table.mapper();
 
-        let alloc = &mut *get_frame_alloc_for_sure();
+        let alloc = &mut *wait_frame_alloc();
 
         self.load_elf_code(elf, mapper, alloc);
         self.stack.init(mapper, alloc);
@@ -117,7 +145,7 @@ impl ProcessVm {
         let owned_page_table = self.page_table.fork();
         let mapper = &mut owned_page_table.mapper();
 
-        let alloc = &mut *get_frame_alloc_for_sure();
+        let alloc = &mut *wait_frame_alloc();
 
         Self {
             page_table: owned_page_table,
@@ -133,7 +161,7 @@ impl ProcessVm {
 
     pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
         let mapper = &mut self.page_table.mapper();
-        let alloc = &mut *get_frame_alloc_for_sure();
+        let alloc = &mut *wait_frame_alloc();
 
         self.stack.handle_page_fault(addr, mapper, alloc)
     }
@@ -146,15 +174,24 @@ impl ProcessVm {
         debug!("ProcessVm::clean_up called, page table using_count: {}", self.page_table.using_count());
 
         let mapper = &mut self.page_table.mapper();
-        let dealloc = &mut *get_frame_alloc_for_sure();
+
+        // 1. 释放栈区：调用 Stack 的 clean_up 函数
+        let mut unmapped = UnmappedFrames::default();
+        self.stack.clean_up(mapper, &mut unmapped)?;
+
+        // 共享页表的其他进程可能正在其他 CPU 上运行，等待时不持有帧分配器的锁
+        if self.page_table.using_count() > 1 {
+            crate::interrupt::tlb_shootdown();
+        }
+
+        let dealloc = &mut *wait_frame_alloc();
 
         // statistics for logging and debugging
         // NOTE: you may need to implement `frames_recycled` by yourself
         let start_count = dealloc.frames_recycled();
         debug!("Starting cleanup with {} recycled frames", start_count);
 
-        // 1. 释放栈区：调用 Stack 的 clean_up 函数
-        self.stack.clean_up(mapper, dealloc)?;
+        unmapped.free(dealloc);
 
         // 2. 如果当前页表被引用次数为 1，则进行共享内存的释放，否则跳过至第 7 步
         if self.page_table.using_count() == 1 {
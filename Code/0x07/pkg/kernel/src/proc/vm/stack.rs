@@ -306,7 +306,7 @@ impl Stack {
     pub fn clean_up(
         &mut self,
         mapper: MapperRef,
-        dealloc: FrameAllocatorRef,
+        dealloc: &mut impl FrameDeallocator<Size4KiB>,
     ) -> Result<(), UnmapError> {
         if self.usage == 0 {
             warn!("Stack is empty, no need to clean up.");
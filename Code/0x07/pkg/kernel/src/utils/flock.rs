@@ -0,0 +1,198 @@
+//! Advisory file locks
+//!
+//! `flock`-style shared/exclusive locks. Locks are keyed by the identity of
+//! the opened file, so every handle on the same file contends on the same
+//! lock, and are owned by a (process, handle) pair.
+
+use crate::proc::ProcessId;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+static FILE_LOCKS: Mutex<BTreeMap<FileId, FileLock>> = Mutex::new(BTreeMap::new());
+
+/// Identity of a file on the root filesystem
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileId(String);
+
+impl FileId {
+    /// FAT16 names are case-insensitive, so the path is normalized to
+    /// upper case with empty components removed.
+    pub fn new(path: &str) -> Self {
+        let path = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Self(path.to_ascii_uppercase())
+    }
+}
+
+/// Lock key of an opened file
+#[derive(Debug, Clone)]
+pub struct FileKey {
+    pub id: FileId,
+    /// Unique for every `open`, so closing a handle only drops its own locks
+    pub handle: u64,
+}
+
+impl FileKey {
+    pub fn new(path: &str) -> Self {
+        Self {
+            id: FileId::new(path),
+            handle: NEXT_HANDLE.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+/// File lock result
+#[derive(Debug)]
+pub enum FileLockResult {
+    Ok,
+    WouldBlock,
+    Block(ProcessId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LockOwner {
+    pid: ProcessId,
+    handle: u64,
+}
+
+#[derive(Debug, Default)]
+struct FileLock {
+    holders: Vec<(LockOwner, LockKind)>,
+    wait_queue: VecDeque<(LockOwner, LockKind)>,
+}
+
+impl FileLock {
+    fn can_grant(&self, owner: LockOwner, kind: LockKind) -> bool {
+        self.holders
+            .iter()
+            .all(|&(o, k)| o == owner || (kind == LockKind::Shared && k == LockKind::Shared))
+    }
+
+    /// Hand the lock over to waiters in FIFO order,
+    /// stopping at the first one that still conflicts
+    fn grant_waiters(&mut self) -> Vec<ProcessId> {
+        let mut woken = Vec::new();
+
+        while let Some(&(owner, kind)) = self.wait_queue.front() {
+            if !self.can_grant(owner, kind) {
+                break;
+            }
+
+            self.wait_queue.pop_front();
+            self.holders.push((owner, kind));
+            woken.push(owner.pid);
+        }
+
+        woken
+    }
+
+    fn release(&mut self, f: impl Fn(&LockOwner) -> bool) -> Vec<ProcessId> {
+        self.holders.retain(|(o, _)| !f(o));
+        self.wait_queue.retain(|(o, _)| !f(o));
+        self.grant_waiters()
+    }
+
+    fn is_idle(&self) -> bool {
+        self.holders.is_empty() && self.wait_queue.is_empty()
+    }
+}
+
+/// Acquire or convert the lock on `key` for the given process
+///
+/// Like `flock(2)`, converting an existing lock is not atomic: the old lock
+/// is dropped first, which may wake up other waiters.
+///
+/// Returns the lock result and the processes that now hold the lock and
+/// should be woken up.
+pub fn lock(
+    key: &FileKey,
+    pid: ProcessId,
+    kind: LockKind,
+    nonblock: bool,
+) -> (FileLockResult, Vec<ProcessId>) {
+    let mut locks = FILE_LOCKS.lock();
+    let lock = locks.entry(key.id.clone()).or_default();
+    let owner = LockOwner {
+        pid,
+        handle: key.handle,
+    };
+
+    let mut woken = Vec::new();
+
+    if let Some(idx) = lock.holders.iter().position(|&(o, _)| o == owner) {
+        if lock.holders[idx].1 == kind {
+            return (FileLockResult::Ok, woken);
+        }
+
+        lock.holders.remove(idx);
+        woken = lock.grant_waiters();
+    }
+
+    let ret = if lock.wait_queue.is_empty() && lock.can_grant(owner, kind) {
+        lock.holders.push((owner, kind));
+        FileLockResult::Ok
+    } else if nonblock {
+        FileLockResult::WouldBlock
+    } else {
+        lock.wait_queue.push_back((owner, kind));
+        FileLockResult::Block(pid)
+    };
+
+    if lock.is_idle() {
+        locks.remove(&key.id);
+    }
+
+    trace!("File Lock: <{:?}> {:?} by #{} -> {:?}", key.id, kind, pid, ret);
+
+    (ret, woken)
+}
+
+/// Release the lock held on `key` by the given process
+pub fn unlock(key: &FileKey, pid: ProcessId) -> Vec<ProcessId> {
+    let owner = LockOwner {
+        pid,
+        handle: key.handle,
+    };
+
+    release(Some(&key.id), |o| *o == owner)
+}
+
+/// Release all locks taken through a handle, called when it is closed
+pub fn release_handle(key: &FileKey) -> Vec<ProcessId> {
+    release(Some(&key.id), |o| o.handle == key.handle)
+}
+
+/// Release all locks held or waited for by a process, called when it is killed
+pub fn release_process(pid: ProcessId) -> Vec<ProcessId> {
+    release(None, |o| o.pid == pid)
+}
+
+fn release(id: Option<&FileId>, f: impl Fn(&LockOwner) -> bool) -> Vec<ProcessId> {
+    let mut locks = FILE_LOCKS.lock();
+    let mut woken = Vec::new();
+
+    for (fid, lock) in locks.iter_mut() {
+        if id.is_none_or(|id| id == fid) {
+            woken.extend(lock.release(&f));
+        }
+    }
+
+    locks.retain(|_, lock| !lock.is_idle());
+
+    woken
+}
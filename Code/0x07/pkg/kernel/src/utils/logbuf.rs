@@ -0,0 +1,208 @@
+//! Kernel log buffer
+//!
+//! The log records are kept in a fixed-size ring of bytes, the oldest ones
+//! are dropped to make room for the new ones. Nothing is allocated, so the
+//! records are kept from the very start of the boot.
+//!
+//! A record is a header, the module and then the message:
+//!
+//! | offset | size | field             |
+//! |--------|------|-------------------|
+//! | 0      | 1    | level             |
+//! | 1      | 1    | module length     |
+//! | 2      | 2    | message length    |
+//! | 4      | 8    | timestamp, in ns  |
+
+use core::fmt::{self, Write};
+use log::Level;
+
+/// Bytes of the ring
+pub const LOG_BUF_SIZE: usize = 16 * 1024;
+/// Longer modules and messages are truncated
+pub const MAX_MODULE: usize = 48;
+pub const MAX_MESSAGE: usize = 256;
+
+const HEADER_SIZE: usize = 12;
+
+/// A string of at most `N` bytes, the text beyond is dropped
+pub struct FixedStr<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedStr<N> {
+    pub const fn new() -> Self {
+        Self { buf: [0; N], len: 0 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole characters are pushed
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn set_bytes(&mut self, bytes: &[u8]) {
+        self.len = bytes.len().min(N);
+        self.buf[..self.len].copy_from_slice(&bytes[..self.len]);
+    }
+}
+
+impl<const N: usize> Default for FixedStr<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Write for FixedStr<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = c.len_utf8();
+            if self.len + len > N {
+                break;
+            }
+            c.encode_utf8(&mut self.buf[self.len..]);
+            self.len += len;
+        }
+        Ok(())
+    }
+}
+
+/// A record read from the buffer
+pub struct LogRecord {
+    pub level: Level,
+    pub timestamp: u64,
+    pub module: FixedStr<MAX_MODULE>,
+    pub message: FixedStr<MAX_MESSAGE>,
+}
+
+pub struct LogBuffer {
+    buf: [u8; LOG_BUF_SIZE],
+    /// Offset of the oldest record
+    head: usize,
+    /// Bytes used by the records
+    len: usize,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogBuffer {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; LOG_BUF_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, level: Level, timestamp: u64, module: &str, message: &str) {
+        let mut module_str = FixedStr::<MAX_MODULE>::new();
+        let mut message_str = FixedStr::<MAX_MESSAGE>::new();
+        module_str.write_str(module).ok();
+        message_str.write_str(message).ok();
+
+        let module = module_str.as_bytes();
+        let message = message_str.as_bytes();
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[0] = level as u8;
+        header[1] = module.len() as u8;
+        header[2..4].copy_from_slice(&(message.len() as u16).to_le_bytes());
+        header[4..12].copy_from_slice(&timestamp.to_le_bytes());
+
+        let size = HEADER_SIZE + module.len() + message.len();
+        while self.len + size > LOG_BUF_SIZE {
+            self.drop_oldest();
+        }
+
+        let mut offset = (self.head + self.len) % LOG_BUF_SIZE;
+        for part in [&header[..], module, message] {
+            self.write_at(offset, part);
+            offset = (offset + part.len()) % LOG_BUF_SIZE;
+        }
+        self.len += size;
+    }
+
+    /// Call `f` on the records, from the oldest to the newest
+    pub fn for_each(&self, mut f: impl FnMut(&LogRecord)) {
+        let mut offset = self.head;
+        let mut left = self.len;
+        let mut record = LogRecord {
+            level: Level::Error,
+            timestamp: 0,
+            module: FixedStr::new(),
+            message: FixedStr::new(),
+        };
+
+        while left > 0 {
+            let header = self.header_at(offset);
+            let (module_len, message_len) = (header[1] as usize, header_message_len(&header));
+
+            record.level = level_from_u8(header[0]);
+            record.timestamp = u64::from_le_bytes(header[4..12].try_into().unwrap());
+
+            offset = (offset + HEADER_SIZE) % LOG_BUF_SIZE;
+            let mut bytes = [0u8; MAX_MESSAGE];
+            self.read_at(offset, &mut bytes[..module_len]);
+            record.module.set_bytes(&bytes[..module_len]);
+
+            offset = (offset + module_len) % LOG_BUF_SIZE;
+            self.read_at(offset, &mut bytes[..message_len]);
+            record.message.set_bytes(&bytes[..message_len]);
+
+            offset = (offset + message_len) % LOG_BUF_SIZE;
+            left -= HEADER_SIZE + module_len + message_len;
+
+            f(&record);
+        }
+    }
+
+    fn drop_oldest(&mut self) {
+        let header = self.header_at(self.head);
+        let size = HEADER_SIZE + header[1] as usize + header_message_len(&header);
+        self.head = (self.head + size) % LOG_BUF_SIZE;
+        self.len -= size;
+    }
+
+    fn header_at(&self, offset: usize) -> [u8; HEADER_SIZE] {
+        let mut header = [0u8; HEADER_SIZE];
+        self.read_at(offset, &mut header);
+        header
+    }
+
+    fn read_at(&self, offset: usize, bytes: &mut [u8]) {
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.buf[(offset + i) % LOG_BUF_SIZE];
+        }
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.buf[(offset + i) % LOG_BUF_SIZE] = *byte;
+        }
+    }
+}
+
+fn header_message_len(header: &[u8; HEADER_SIZE]) -> usize {
+    u16::from_le_bytes([header[2], header[3]]) as usize
+}
+
+fn level_from_u8(level: u8) -> Level {
+    match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
@@ -1,5 +1,9 @@
 use log::{Metadata, Record, Level, LevelFilter};
 use crate::println;
+use super::logbuf::{FixedStr, LogBuffer, MAX_MESSAGE};
+use core::fmt::Write;
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts;
 
 /// 解析日志级别字符串，返回对应的 LevelFilter
 fn parse_log_level(level: &str) -> LevelFilter {
@@ -25,15 +29,65 @@ fn parse_log_level(level: &str) -> LevelFilter {
     }
 }
 
-pub fn init(log_level: &str) {
+/// 内核日志，由 dmesg 读取
+static LOG_BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+/// 打印到控制台的日志级别，记录的级别由 log::max_level 决定
+static CONSOLE_LEVEL: Once<LevelFilter> = Once::new();
+
+pub fn init(log_level: &str, console_loglevel: &str) {
     static LOGGER: Logger = Logger;
     log::set_logger(&LOGGER).unwrap();
     
     // 根据启动配置参数设置日志级别
     let level = parse_log_level(log_level);
     log::set_max_level(level);
+    CONSOLE_LEVEL.call_once(|| parse_log_level(console_loglevel));
     
-    info!("Logger Initialized with level: {}", log_level);
+    info!("Logger Initialized with level: {}, console: {}", log_level, console_loglevel);
+}
+
+/// The time of a record, in nanoseconds since the boot
+fn timestamp() -> u64 {
+    super::time::monotonic_ns()
+}
+
+/// Copy the records at `level` or above to `buf`, one line of text each
+///
+/// Returns the bytes written, the newer records not fitting are left out.
+pub fn read(level: LevelFilter, buf: &mut [u8]) -> usize {
+    let mut count = 0;
+    let mut full = false;
+    let mut line = FixedStr::<384>::new();
+
+    interrupts::without_interrupts(|| {
+        LOG_BUFFER.lock().for_each(|record| {
+            if full || record.level > level {
+                return;
+            }
+
+            line.clear();
+            writeln!(
+                line,
+                "[{:>5}.{:06}] {:<5} {}: {}",
+                record.timestamp / 1_000_000_000,
+                record.timestamp % 1_000_000_000 / 1_000,
+                record.level,
+                record.module.as_str(),
+                record.message.as_str()
+            )
+            .ok();
+
+            let bytes = line.as_bytes();
+            if count + bytes.len() > buf.len() {
+                full = true;
+                return;
+            }
+            buf[count..count + bytes.len()].copy_from_slice(bytes);
+            count += bytes.len();
+        });
+    });
+
+    count
 }
 
 struct Logger;
@@ -47,6 +101,20 @@ impl log::Log for Logger {
     fn log(&self, record: &Record) {
         // 只处理启用的日志记录
         if self.enabled(record.metadata()) {
+            let mut message = FixedStr::<MAX_MESSAGE>::new();
+            message.write_fmt(*record.args()).ok();
+
+            // 记录到日志缓冲区，正在读取时（如 panic 中）丢弃
+            interrupts::without_interrupts(|| {
+                if let Some(mut buffer) = LOG_BUFFER.try_lock() {
+                    buffer.push(record.level(), timestamp(), record.target(), message.as_str());
+                }
+            });
+
+            let console_level = CONSOLE_LEVEL.get().copied().unwrap_or(LevelFilter::Trace);
+            if record.level() > console_level {
+                return;
+            }
             
             // 根据日志级别添加不同的颜色和前缀
             let (color_code, level_str) = match record.level() {
@@ -58,11 +126,12 @@ impl log::Log for Logger {
             };
             
             // 简化输出：只显示级别和消息内容
-            println!(
-                "[{}{}{}] {}",
+            // 输出到 boot.conf 中 com2 选择的终端
+            crate::drivers::tty::log_tty().write_fmt(format_args!(
+                "[{}{}{}] {}\n\r",
                 color_code, level_str, "\x1b[0m",
                 record.args()
-            );
+            ));
         }
     }
 
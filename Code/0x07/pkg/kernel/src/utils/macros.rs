@@ -1,3 +1,4 @@
+use crate::drivers::console::{get_console, CONSOLE};
 use crate::drivers::serial::get_serial;
 use crate::drivers::serial::SERIAL;
 use core::fmt::*;
@@ -61,15 +62,23 @@ pub fn print_internal(args: Arguments) {
         if let Some(mut serial) = get_serial() {
             serial.write_fmt(args).unwrap();
         }
+        if let Some(mut console) = get_console() {
+            console.write_fmt(args).unwrap();
+        }
     });
 }
 
 #[allow(dead_code)]
 #[cfg_attr(target_os = "none", panic_handler)]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    // force unlock serial for panic output
+    // force unlock serial and console for panic output
     unsafe { SERIAL.get().unwrap().force_unlock() };
+    if let Some(console) = CONSOLE.get() {
+        unsafe { console.force_unlock() };
+    }
 
     error!("ERROR: panic!\n\n{:#?}", info);
+    // the interrupts sending the output may never come again
+    crate::drivers::serial::flush();
     loop {}
 }
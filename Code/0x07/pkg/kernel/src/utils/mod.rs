@@ -6,9 +6,12 @@ mod regs;
 // 删除未使用的导入
 // use crate::interrupt::clock;
 
+pub mod flock;
 pub mod func;
+pub mod logbuf;
 pub mod logger;
 pub mod resource; // 添加resource模块
+pub mod time;
 
 pub use macros::*;
 pub use regs::*;
@@ -1,9 +1,14 @@
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
 use alloc::string::String;
 use spin::Mutex;
-use crate::drivers::input;
+use crate::drivers::tty::Tty;
+use crate::proc::ProcessId;
+use crate::utils::flock::{self, FileKey};
 use storage::FileHandle;
 
+/// Processes waiting for a file being read by another process to be put back
+pub static FILE_WAITERS: Mutex<VecDeque<ProcessId>> = Mutex::new(VecDeque::new());
+
 #[derive(Debug, Clone)]
 pub enum StdIO {
     Stdin,
@@ -14,12 +19,15 @@ pub enum StdIO {
 #[derive(Debug)]
 pub struct ResourceSet {
     pub handles: BTreeMap<u8, Mutex<Resource>>,
+    /// Descriptors whose reads return at once if nothing is ready
+    nonblocking: BTreeSet<u8>,
 }
 
 impl Default for ResourceSet {
     fn default() -> Self {
         let mut res = Self {
             handles: BTreeMap::new(),
+            nonblocking: BTreeSet::new(),
         };
 
         res.open(Resource::Console(StdIO::Stdin));
@@ -31,6 +39,33 @@ impl Default for ResourceSet {
 }
 
 impl ResourceSet {
+    /// A set whose standard streams are the terminal `tty`
+    pub fn with_tty(tty: Tty) -> Self {
+        let mut res = Self {
+            handles: BTreeMap::new(),
+            nonblocking: BTreeSet::new(),
+        };
+
+        for _ in 0..3 {
+            res.open(Resource::Tty(tty));
+        }
+
+        res
+    }
+
+    /// A set with the standard streams of `parent`, other handles are not shared
+    pub fn inherit_stdio(parent: &ResourceSet) -> Self {
+        let mut res = Self::default();
+
+        for fd in 0..3 {
+            if let Some(stdio) = parent.handles.get(&fd).and_then(|h| h.lock().stdio_clone()) {
+                res.handles.insert(fd, Mutex::new(stdio));
+            }
+        }
+
+        res
+    }
+
     pub fn open(&mut self, res: Resource) -> u8 {
         let fd = self.handles.len() as u8;
         self.handles.insert(fd, Mutex::new(res));
@@ -38,7 +73,82 @@ impl ResourceSet {
     }
 
     pub fn close(&mut self, fd: u8) -> bool {
-        self.handles.remove(&fd).is_some()
+        self.nonblocking.remove(&fd);
+        match self.handles.remove(&fd) {
+            Some(res) => {
+                // 正在被读取的文件由放回时释放
+                if let Resource::File(_, key) = &*res.lock() {
+                    release_flocks(key);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_nonblocking(&self, fd: u8) -> bool {
+        self.nonblocking.contains(&fd)
+    }
+
+    pub fn set_nonblocking(&mut self, fd: u8, nonblocking: bool) {
+        if nonblocking {
+            self.nonblocking.insert(fd);
+        } else {
+            self.nonblocking.remove(&fd);
+        }
+    }
+
+    /// Take the file opened as `fd` out of its slot, so it can be read
+    /// without holding any lock
+    ///
+    /// Returns `Err(true)` if another process is reading it, `Err(false)`
+    /// if `fd` is not a file.
+    pub fn take_file(&self, fd: u8) -> Result<Resource, bool> {
+        let mut slot = self.handles.get(&fd).ok_or(false)?.lock();
+        match &*slot {
+            Resource::File(_, key) => {
+                let in_use = Resource::InUse(key.clone());
+                Ok(core::mem::replace(&mut *slot, in_use))
+            }
+            Resource::InUse(_) => Err(true),
+            _ => Err(false),
+        }
+    }
+
+    /// Put back a file taken by `take_file`, it is closed if `fd` was
+    /// closed in the meantime
+    pub fn put_back_file(&self, fd: u8, file: Resource) {
+        match self.handles.get(&fd).map(|h| h.lock()) {
+            Some(mut slot) if matches!(*slot, Resource::InUse(_)) => *slot = file,
+            _ => {
+                if let Resource::File(_, key) = &file {
+                    release_flocks(key);
+                }
+            }
+        }
+    }
+
+    /// Whether the file opened as `fd` is taken out by `take_file`
+    pub fn file_in_use(&self, fd: u8) -> bool {
+        self.handles
+            .get(&fd)
+            .is_some_and(|h| matches!(*h.lock(), Resource::InUse(_)))
+    }
+
+    pub fn file_key(&self, fd: u8) -> Option<FileKey> {
+        match &*self.handles.get(&fd)?.lock() {
+            Resource::File(_, key) | Resource::InUse(key) => Some(key.clone()),
+            _ => None,
+        }
+    }
+
+    /// The terminal opened as `fd`, the console stdio included
+    pub fn tty(&self, fd: u8) -> Option<Tty> {
+        match &*self.handles.get(&fd)?.lock() {
+            Resource::Console(_) => Some(Tty::Console),
+            Resource::Tty(tty) => Some(*tty),
+            _ => None,
+        }
     }
 
     pub fn read(&self, fd: u8, buf: &mut [u8]) -> isize {
@@ -58,56 +168,56 @@ impl ResourceSet {
     }
 }
 
+/// Release the file locks taken through a handle being closed
+fn release_flocks(key: &FileKey) {
+    let manager = crate::proc::get_process_manager();
+    for pid in flock::release_handle(key) {
+        manager.wake_up(pid, Some(0));
+    }
+}
+
 #[derive(Debug)]
 pub enum Resource {
     Console(StdIO),
-    File(FileHandle),
+    /// A terminal opened as a device file, or the stdio of its processes
+    Tty(Tty),
+    File(FileHandle, FileKey),
+    /// A file taken out of its slot while a process reads it
+    InUse(FileKey),
+    /// `/dev/random`, writes are mixed into the entropy pool
+    Random,
     Null,
 }
 
 impl Resource {
+    /// A copy of a console or terminal resource, for the stdio of a child
+    fn stdio_clone(&self) -> Option<Resource> {
+        match self {
+            Resource::Console(stdio) => Some(Resource::Console(stdio.clone())),
+            Resource::Tty(tty) => Some(Resource::Tty(*tty)),
+            _ => None,
+        }
+    }
+
     pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
         match self {
             Resource::Console(stdio) => match stdio {
-                StdIO::Stdin => {
-                    // 缓冲区为空则尝试读取一个按键
-                    if buf.is_empty() {
-                        return Some(0);
-                    }
-
-                    // 尝试读取一个按键
-                    if let Some(key) = input::try_pop_key() {
-                        match key {
-                            input::InputKey::Char(c) => {
-                                let bytes = [c as u8];
-                                buf[0] = bytes[0];
-                                return Some(1);
-                            }
-                            input::InputKey::Backspace => {
-                                // 对于退格键，返回退格的ASCII码(8)
-                                buf[0] = 8;
-                                return Some(1);
-                            }
-                            input::InputKey::Newline => {
-                                // 对于换行键，返回回车的ASCII码(13)
-                                buf[0] = b'\n';
-                                return Some(1);
-                            }
-                        }
-                    }
-
-                    // 如果没有可用数据，返回0
-                    Some(0)
-                }
+                StdIO::Stdin => usize::try_from(Tty::Console.read(buf, false)).ok(),
                 _ => None,
             },
-            Resource::File(file_handle) => {
+            Resource::Tty(tty) => usize::try_from(tty.read(buf, false)).ok(),
+            Resource::File(file_handle, _) => {
                 match file_handle.read(buf) {
                     Ok(bytes_read) => Some(bytes_read),
                     Err(_) => None,
                 }
             },
+            Resource::Random => {
+                crate::drivers::random::fill(buf);
+                Some(buf.len())
+            }
             Resource::Null => Some(0),
+            Resource::InUse(_) => None,
         }
     }
 
@@ -124,11 +234,20 @@ impl Resource {
                     Some(buf.len())
                 }
             },
-            Resource::File(_file_handle) => {
+            Resource::Tty(tty) => {
+                tty.write_str(&String::from_utf8_lossy(buf));
+                Some(buf.len())
+            }
+            Resource::File(_file_handle, _) => {
                 // 文件写入暂不实现，根据实验要求可以直接忽略
                 None
             },
+            Resource::Random => {
+                crate::drivers::random::add_bytes(buf);
+                Some(buf.len())
+            }
             Resource::Null => Some(buf.len()),
+            Resource::InUse(_) => None,
         }
     }
 }
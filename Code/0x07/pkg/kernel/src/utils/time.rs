@@ -0,0 +1,95 @@
+//! Kernel clocks
+//!
+//! The monotonic clock counts the ticks of the LAPIC timer, and the TSC
+//! cycles since the last tick for the time in between. Both are calibrated
+//! against the PIT at boot.
+//!
+//! The wall clock is seeded from the RTC at boot, then advanced and
+//! corrected by its update-ended interrupt every second. The monotonic
+//! clock gives the time since the last second.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Timer interrupts since the timer was started
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Nanoseconds between two timer interrupts
+static TICK_NS: AtomicU64 = AtomicU64::new(0);
+/// TSC at the last timer interrupt
+static TICK_TSC: AtomicU64 = AtomicU64::new(0);
+/// Frequency of the TSC, in Hz
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Seconds since the Unix epoch
+static REALTIME: AtomicU64 = AtomicU64::new(0);
+/// Monotonic nanoseconds at which the wall clock last changed second
+static REALTIME_MARK: AtomicU64 = AtomicU64::new(0);
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Start the monotonic clock, the timer interrupt comes `hz` times a
+/// second and the TSC counts `tsc_hz` cycles a second
+pub fn init(hz: u64, tsc_hz: u64) {
+    TSC_HZ.store(tsc_hz, Ordering::Relaxed);
+    TICK_TSC.store(rdtsc(), Ordering::Relaxed);
+    TICK_NS.store(NANOS_PER_SEC / hz, Ordering::Relaxed);
+}
+
+/// Called by the timer interrupt
+pub fn tick() {
+    TICK_TSC.store(rdtsc(), Ordering::Relaxed);
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Nanoseconds since the boot, 0 until the timer is calibrated
+pub fn monotonic_ns() -> u64 {
+    interrupts::without_interrupts(|| {
+        let tick_ns = TICK_NS.load(Ordering::Relaxed);
+        let tsc_hz = TSC_HZ.load(Ordering::Relaxed);
+        let base = TICKS.load(Ordering::Relaxed) * tick_ns;
+
+        if tsc_hz == 0 || tick_ns == 0 {
+            return base;
+        }
+
+        // never past the next tick, or the clock would go back then
+        let cycles = rdtsc().saturating_sub(TICK_TSC.load(Ordering::Relaxed));
+        let since = (cycles as u128 * NANOS_PER_SEC as u128 / tsc_hz as u128) as u64;
+        base + since.min(tick_ns - 1)
+    })
+}
+
+/// Time since the boot, never goes back
+pub fn monotonic() -> Duration {
+    Duration::from_nanos(monotonic_ns())
+}
+
+/// Set the wall clock, in seconds since the Unix epoch
+pub fn set_realtime(secs: u64) {
+    interrupts::without_interrupts(|| {
+        REALTIME.store(secs, Ordering::Relaxed);
+        REALTIME_MARK.store(monotonic_ns(), Ordering::Relaxed);
+    });
+}
+
+/// A second has passed
+pub fn tick_second() {
+    interrupts::without_interrupts(|| {
+        REALTIME.fetch_add(1, Ordering::Relaxed);
+        REALTIME_MARK.store(monotonic_ns(), Ordering::Relaxed);
+    });
+}
+
+/// Time since the Unix epoch
+pub fn realtime() -> Duration {
+    interrupts::without_interrupts(|| {
+        let secs = REALTIME.load(Ordering::Relaxed);
+        let since = monotonic_ns().saturating_sub(REALTIME_MARK.load(Ordering::Relaxed));
+        Duration::new(secs, since.min(NANOS_PER_SEC - 1) as u32)
+    })
+}
@@ -1,6 +1,19 @@
 use crate::*;
 use alloc::string::{String, ToString};
-use alloc::vec;
+use alloc::vec::{self, Vec};
+
+/// Line settings of the serial terminal opened as `fd`, like `tcgetattr`
+pub fn tcgetattr(fd: u8) -> Option<SerialAttr> {
+    let mut attr = SerialAttr::default();
+    sys_ioctl(fd, TTY_GET_ATTR, &mut attr as *mut SerialAttr as usize)?;
+    Some(attr)
+}
+
+/// Change the line settings of the serial terminal opened as `fd`, like
+/// `tcsetattr`, returns false if they are not valid
+pub fn tcsetattr(fd: u8, attr: &SerialAttr) -> bool {
+    sys_ioctl(fd, TTY_SET_ATTR, attr as *const SerialAttr as usize).is_some()
+}
 
 pub struct Stdin;
 pub struct Stdout;
@@ -11,47 +24,49 @@ impl Stdin {
         Self
     }
 
+    /// Make reads of stdin fail with `EAGAIN` instead of waiting if there
+    /// is no input, for this process only
+    ///
+    /// Returns false if stdin is not a terminal.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        let Some(mode) = sys_ioctl(0, TTY_GET_MODE, 0) else {
+            return false;
+        };
+
+        let mode = if nonblocking {
+            mode | TTY_NONBLOCK
+        } else {
+            mode & !TTY_NONBLOCK
+        };
+
+        sys_ioctl(0, TTY_SET_MODE, mode).is_some()
+    }
+
+    /// Read a line, without the trailing newline
+    ///
+    /// The line is edited and echoed by the terminal in canonical mode,
+    /// which hands it over once the line is complete. A non-blocking stdin
+    /// is polled until then.
     pub fn read_line(&self) -> String {
-        // 分配一个String用于存储输入
-        let mut input = String::new();
-        let mut buffer = [0u8; 1];
-        
+        let mut line = Vec::new();
+        let mut buffer = [0u8; 64];
+
         loop {
-            // 读取一个字符
-            if let Some(n) = sys_read(0, &mut buffer) {
-                if n == 0 {
-                    continue;
-                }
-                
-                match buffer[0] {
-                    // 回车键，结束输入
-                    b'\n' | b'\r' => {
-                        stdout().write("\n");
-                        break;
-                    },
-                    // 退格键，删除一个字符
-                    8 | 127 => {
-                        if !input.is_empty() {
-                            input.pop();
-                            // 在终端上显示退格效果（移动光标并清除字符）
-                            stdout().write("\u{8} \u{8}");
-                        }
-                    },
-                    // 普通可打印字符
-                    32..=126 => {
-                        let c = buffer[0] as char;
-                        input.push(c);
-                        // 回显字符
-                        let echo = [c as u8];
-                        stdout().write(unsafe { core::str::from_utf8_unchecked(&echo) });
-                    },
-                    // 其他控制字符忽略
-                    _ => {}
-                }
+            let n = match sys_try_read(0, &mut buffer) {
+                Ok(n) => n,
+                Err(EAGAIN) => continue,
+                Err(_) => break,
+            };
+
+            line.extend_from_slice(&buffer[..n]);
+
+            if line.last() == Some(&b'\n') {
+                line.pop();
+                break;
             }
         }
-        
-        input
+
+        String::from_utf8_lossy(&line).into_owned()
     }
 }
 
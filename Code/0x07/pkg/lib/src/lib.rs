@@ -12,6 +12,8 @@ extern crate syscall_def;
 pub mod io;
 pub mod allocator;
 pub mod sync;
+pub mod time;
+pub mod rand;
 pub extern crate alloc;
 
 mod syscall;
@@ -23,12 +25,33 @@ pub use chrono::*;
 pub use io::*;
 pub use sync::*;
 pub use syscall::*;
+pub use time::{now, uptime};
 
 pub fn init() {
     #[cfg(feature = "brk_alloc")]
     crate::allocator::init();
 }
 
+/// The level of the kernel log records named `name`, for `sys_dmesg`
+pub fn log_level(name: &str) -> Option<usize> {
+    match name {
+        "error" => Some(LOG_ERROR),
+        "warn" => Some(LOG_WARN),
+        "info" => Some(LOG_INFO),
+        "debug" => Some(LOG_DEBUG),
+        "trace" => Some(LOG_TRACE),
+        _ => None,
+    }
+}
+
+/// Print the kernel log records at `level` or above
+pub fn print_dmesg(level: usize) {
+    // large enough for the whole kernel log
+    let mut buf = vec![0u8; 64 * 1024];
+    let len = sys_dmesg(&mut buf, level);
+    print!("{}", string::String::from_utf8_lossy(&buf[..len]));
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
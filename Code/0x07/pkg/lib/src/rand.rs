@@ -0,0 +1,25 @@
+use crate::*;
+
+/// Fill `buf` with random bytes, from the entropy pool of the kernel
+pub fn fill_bytes(buf: &mut [u8]) {
+    sys_getrandom(buf);
+}
+
+pub fn next_u32() -> u32 {
+    let mut bytes = [0; 4];
+    fill_bytes(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// A seed for a pseudo-random generator, e.g. `ChaCha20Rng::from_seed`
+pub fn seed() -> [u8; 32] {
+    let mut seed = [0; 32];
+    fill_bytes(&mut seed);
+    seed
+}
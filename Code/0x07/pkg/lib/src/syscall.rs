@@ -1,4 +1,9 @@
-pub use syscall_def::Syscall;
+pub use syscall_def::{
+    Syscall, LOCK_EX, LOCK_NB, LOCK_SH, LOCK_UN, LOG_DEBUG, LOG_ERROR, LOG_INFO, LOG_TRACE,
+    LOG_WARN, TimeSpec, CLOCK_MONOTONIC, CLOCK_REALTIME, TTY_CANON, TTY_CONTINUE, TTY_ECHO,
+    TTY_GET_MODE, TTY_ISIG, TTY_NONBLOCK, TTY_SET_MODE, WAIT_STOPPED, SerialAttr, TTY_GET_ATTR,
+    TTY_SET_ATTR, PARITY_EVEN, PARITY_NONE, PARITY_ODD, EAGAIN, EINTR,
+};
 
 #[inline(always)]
 pub fn sys_write(fd: u8, buf: &[u8]) -> Option<usize> {
@@ -15,14 +20,32 @@ pub fn sys_write(fd: u8, buf: &[u8]) -> Option<usize> {
     }
 }
 
+/// Read from `fd`, or the error number if it fails, like `EAGAIN` and
+/// `EINTR` of a terminal
 #[inline(always)]
-pub fn sys_read(fd: u8, buf: &mut [u8]) -> Option<usize> {
+pub fn sys_try_read(fd: u8, buf: &mut [u8]) -> Result<usize, isize> {
     let ret = syscall!(
         Syscall::Read,
         fd as u64,
         buf.as_ptr() as u64,
         buf.len() as u64
     ) as isize;
+    if ret.is_negative() {
+        Err(-ret)
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+#[inline(always)]
+pub fn sys_read(fd: u8, buf: &mut [u8]) -> Option<usize> {
+    sys_try_read(fd, buf).ok()
+}
+
+/// Control the terminal opened as `fd`, see the `TTY_*` requests
+#[inline(always)]
+pub fn sys_ioctl(fd: u8, request: usize, arg: usize) -> Option<usize> {
+    let ret = syscall!(Syscall::Ioctl, fd as u64, request as u64, arg as u64) as isize;
     if ret.is_negative() {
         None
     } else {
@@ -34,6 +57,8 @@ pub fn sys_read(fd: u8, buf: &mut [u8]) -> Option<usize> {
 pub fn sys_fork() -> u16 {
     syscall!(Syscall::Fork) as u16
 }
+/// Wait for the process to exit and return its exit code,
+/// or `WAIT_STOPPED` if it is stopped by Ctrl-Z
 #[inline(always)]
 pub fn sys_wait_pid(pid: u16) -> isize {
     // 循环等待直到进程结束
@@ -91,6 +116,50 @@ pub fn sys_exit(code: isize) -> ! {
     unreachable!("This process should be terminated by now.")
 }
 
+/// Write the cached data of all disks to their medium, returns false on error
+#[inline(always)]
+pub fn sys_sync() -> bool {
+    syscall!(Syscall::Sync) == 0
+}
+
+/// Read the kernel log records at `level` or above as lines of text,
+/// returns the bytes read
+#[inline(always)]
+pub fn sys_dmesg(buf: &mut [u8], level: usize) -> usize {
+    syscall!(
+        Syscall::Dmesg,
+        buf.as_ptr() as u64,
+        buf.len() as u64,
+        level as u64
+    )
+}
+
+/// Read the clock `clock`, None if there is no such clock
+#[inline(always)]
+pub fn sys_clock_gettime(clock: usize) -> Option<TimeSpec> {
+    let mut time = TimeSpec::default();
+    match syscall!(Syscall::ClockGetTime, clock as u64, &mut time as *mut TimeSpec as u64) {
+        0 => Some(time),
+        _ => None,
+    }
+}
+
+/// Fill `buf` with random bytes from the entropy pool of the kernel
+#[inline(always)]
+pub fn sys_getrandom(buf: &mut [u8]) -> usize {
+    syscall!(Syscall::GetRandom, buf.as_ptr() as u64, buf.len() as u64)
+}
+
+#[inline(always)]
+pub fn sys_list_pci() {
+    syscall!(Syscall::ListPci);
+}
+
+#[inline(always)]
+pub fn sys_list_blk() {
+    syscall!(Syscall::ListBlk);
+}
+
 #[inline(always)]
 pub fn sys_list_dir(path: &str) {
     syscall!(Syscall::ListDir, path.as_ptr() as u64, path.len() as u64);
@@ -106,6 +175,15 @@ pub fn sys_close(fd: u8) -> isize {
     syscall!(Syscall::Close, fd as u64) as isize
 }
 
+/// Apply or remove an advisory lock on an open file
+///
+/// Returns 0 on success, 1 on bad fd or operation,
+/// and 2 if `LOCK_NB` is set and the lock is held by others.
+#[inline(always)]
+pub fn sys_flock(fd: u8, op: usize) -> isize {
+    syscall!(Syscall::Flock, fd as u64, op as u64) as isize
+}
+
 #[inline(always)]
 pub fn sys_brk(addr: Option<usize>) -> Option<usize> {
     const BRK_FAILED: usize = !0;
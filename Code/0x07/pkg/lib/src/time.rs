@@ -0,0 +1,13 @@
+use crate::*;
+
+/// The current date and time, like `Utc::now` with std
+pub fn now() -> DateTime<Utc> {
+    let time = sys_clock_gettime(CLOCK_REALTIME).unwrap_or_default();
+    DateTime::from_timestamp(time.tv_sec, time.tv_nsec as u32).unwrap_or_default()
+}
+
+/// The time since the boot
+pub fn uptime() -> TimeDelta {
+    let time = sys_clock_gettime(CLOCK_MONOTONIC).unwrap_or_default();
+    TimeDelta::new(time.tv_sec, time.tv_nsec as u32).unwrap_or_default()
+}
@@ -14,8 +14,66 @@ where
     /// Writes a block to the device from the provided buffer
     fn write_block(&self, offset: usize, block: &B) -> FsResult;
 
+    /// Reads consecutive blocks from the device into the provided buffers
+    ///
+    /// Devices that can transfer several blocks at once should override this.
+    fn read_blocks(&self, offset: usize, blocks: &mut [B]) -> FsResult {
+        for (i, block) in blocks.iter_mut().enumerate() {
+            self.read_block(offset + i, block)?;
+        }
+        Ok(())
+    }
+
+    /// Writes consecutive blocks to the device from the provided buffers
+    ///
+    /// Devices that can transfer several blocks at once should override this.
+    fn write_blocks(&self, offset: usize, blocks: &[B]) -> FsResult {
+        for (i, block) in blocks.iter().enumerate() {
+            self.write_block(offset + i, block)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the data cached by the device to its medium
+    ///
+    /// Devices without a volatile write cache need not override this.
+    fn flush(&self) -> FsResult {
+        Ok(())
+    }
+
     /// Returns the block size of the device
     fn block_size(&self) -> usize {
         B::size()
     }
 }
+
+/// Shared block devices, e.g. a disk and the partitions on it
+impl<B, T> BlockDevice<B> for Arc<T>
+where
+    B: BlockTrait,
+    T: BlockDevice<B> + ?Sized,
+{
+    fn block_count(&self) -> FsResult<usize> {
+        (**self).block_count()
+    }
+
+    fn read_block(&self, offset: usize, block: &mut B) -> FsResult {
+        (**self).read_block(offset, block)
+    }
+
+    fn write_block(&self, offset: usize, block: &B) -> FsResult {
+        (**self).write_block(offset, block)
+    }
+
+    fn read_blocks(&self, offset: usize, blocks: &mut [B]) -> FsResult {
+        (**self).read_blocks(offset, blocks)
+    }
+
+    fn write_blocks(&self, offset: usize, blocks: &[B]) -> FsResult {
+        (**self).write_blocks(offset, blocks)
+    }
+
+    fn flush(&self) -> FsResult {
+        (**self).flush()
+    }
+}
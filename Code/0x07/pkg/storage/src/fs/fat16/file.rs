@@ -59,22 +59,29 @@ impl Read for File {
             // Get the sector to read from
             let sector = self.handle.cluster_to_sector(&self.current_cluster) + sector_offset_in_cluster;
 
-            // Read the sector
-            let mut block = Block512::default();
-            self.handle.inner.read_block(sector, &mut block)?;
-
-            // Calculate how much to copy from this sector
-            let bytes_remaining_in_sector = BLOCK_SIZE - byte_offset_in_sector;
+            // Read all the sectors needed from this cluster at once
             let bytes_remaining_to_read = bytes_to_read - bytes_read;
-            let bytes_to_copy = bytes_remaining_in_sector.min(bytes_remaining_to_read);
+            let sectors_remaining_in_cluster =
+                self.handle.bpb.sectors_per_cluster() as usize - sector_offset_in_cluster;
+            let count = (byte_offset_in_sector + bytes_remaining_to_read)
+                .div_ceil(BLOCK_SIZE)
+                .min(sectors_remaining_in_cluster);
+
+            let mut blocks = vec![Block512::default(); count];
+            self.handle.inner.read_blocks(sector, &mut blocks)?;
+
+            // Calculate how much to copy from these sectors
+            let bytes_remaining_in_sectors = count * BLOCK_SIZE - byte_offset_in_sector;
+            let bytes_to_copy = bytes_remaining_in_sectors.min(bytes_remaining_to_read);
 
-            // Copy data from sector to buffer
-            let src_start = byte_offset_in_sector;
-            let src_end = src_start + bytes_to_copy;
+            // Copy data from sectors to buffer
             let dst_start = bytes_read;
             let dst_end = dst_start + bytes_to_copy;
+            let src = blocks.iter().flat_map(|block| block.iter()).skip(byte_offset_in_sector);
 
-            buf[dst_start..dst_end].copy_from_slice(&block.as_ref()[src_start..src_end]);
+            for (dst, src) in buf[dst_start..dst_end].iter_mut().zip(src) {
+                *dst = *src;
+            }
 
             bytes_read += bytes_to_copy;
             current_offset += bytes_to_copy;
@@ -112,6 +119,6 @@ impl Write for File {
     }
 
     fn flush(&mut self) -> FsResult {
-        unimplemented!()
+        self.handle.inner.flush()
     }
 }
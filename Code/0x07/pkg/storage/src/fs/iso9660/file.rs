@@ -0,0 +1,80 @@
+//! File
+//!
+//! reference: <https://wiki.osdev.org/ISO_9660#Directories>
+
+use super::*;
+
+#[derive(Debug, Clone)]
+pub struct File {
+    /// The current offset in the file
+    offset: usize,
+    /// DirRecord of this file
+    record: DirRecord,
+    /// The file system handle that contains this file
+    handle: Iso9660Handle,
+}
+
+impl File {
+    pub fn new(handle: Iso9660Handle, record: DirRecord) -> Self {
+        Self {
+            offset: 0,
+            record,
+            handle,
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        self.record.size as usize
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> FsResult<usize> {
+        if self.offset >= self.length() {
+            return Ok(0);
+        }
+
+        // The data of a file is recorded in consecutive blocks
+        let bytes_to_read = buf.len().min(self.length() - self.offset);
+        let block = self.record.extent as usize + self.offset / BLOCK_SIZE;
+        let byte_offset = self.offset % BLOCK_SIZE;
+        let count = (byte_offset + bytes_to_read).div_ceil(BLOCK_SIZE);
+
+        let mut blocks = vec![Block2048::default(); count];
+        self.handle.inner.read_blocks(block, &mut blocks)?;
+
+        let src = blocks.iter().flat_map(|block| block.iter()).skip(byte_offset);
+
+        for (dst, src) in buf[..bytes_to_read].iter_mut().zip(src) {
+            *dst = *src;
+        }
+
+        self.offset += bytes_to_read;
+
+        Ok(bytes_to_read)
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> FsResult<usize> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.offset.checked_add_signed(offset),
+        };
+
+        self.offset = offset.ok_or(FsError::InvalidOffset)?;
+
+        Ok(self.offset)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn flush(&mut self) -> FsResult {
+        Err(FsError::ReadOnly)
+    }
+}
@@ -0,0 +1,144 @@
+use super::*;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+impl Iso9660Impl {
+    pub fn new(inner: impl BlockDevice<Block2048>) -> FsResult<Self> {
+        let mut block = Block2048::default();
+        let mut offset = PrimaryVolumeDescriptor::FIRST_BLOCK;
+
+        // Scan the volume descriptor set for the primary one
+        let pvd = loop {
+            inner.read_block(offset, &mut block)?;
+
+            match PrimaryVolumeDescriptor::type_of(block.as_ref()) {
+                Some(PrimaryVolumeDescriptor::TYPE_PRIMARY) => {
+                    break PrimaryVolumeDescriptor::new(block.as_ref())?;
+                }
+                Some(PrimaryVolumeDescriptor::TYPE_TERMINATOR) | None => {
+                    return Err(FsError::InvalidOperation);
+                }
+                Some(_) => offset += 1,
+            }
+        };
+
+        trace!("Loading ISO 9660 Volume: {:#?}", pvd);
+
+        if pvd.logical_block_size() as usize != BLOCK_SIZE {
+            warn!(
+                "Unsupported ISO 9660 logical block size: {}",
+                pvd.logical_block_size()
+            );
+            return Err(FsError::NotSupported);
+        }
+
+        let root = DirRecord::parse(pvd.root_directory_record())?;
+
+        Ok(Self {
+            inner: Box::new(inner),
+            pvd,
+            root,
+        })
+    }
+
+    /// Read all directory records of a directory
+    ///
+    /// Records never cross a block boundary, the rest of a block is
+    /// zero-filled when the next record does not fit in it.
+    pub fn read_dir_records(&self, dir: &DirRecord) -> FsResult<Vec<DirRecord>> {
+        if !dir.is_directory() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let count = (dir.size as usize).div_ceil(BLOCK_SIZE);
+        let mut blocks = vec![Block2048::default(); count];
+        self.inner.read_blocks(dir.extent as usize, &mut blocks)?;
+
+        let mut records = Vec::new();
+
+        for block in blocks.iter() {
+            let data = block.as_ref();
+            let mut offset = 0;
+
+            while offset < BLOCK_SIZE && data[offset] != 0 {
+                let len = data[offset] as usize;
+                let record = DirRecord::parse(&data[offset..])?;
+
+                if !record.is_special() && !record.flags.contains(FileFlags::ASSOCIATED) {
+                    records.push(record);
+                }
+
+                offset += len;
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Find a directory record by name in the given directory
+    pub fn find_dir_record(&self, dir: &DirRecord, name: &str) -> FsResult<DirRecord> {
+        self.read_dir_records(dir)?
+            .into_iter()
+            .find(|record| record.matches(name))
+            .ok_or(FsError::FileNotFound)
+    }
+
+    /// Parse a path and navigate to the target file or directory
+    pub fn parse_path(&self, path: &str) -> FsResult<DirRecord> {
+        let mut current = self.root.clone();
+
+        for component in path.split(PATH_SEPARATOR).filter(|s| !s.is_empty()) {
+            if !current.is_directory() {
+                return Err(FsError::NotADirectory);
+            }
+
+            current = self.find_dir_record(&current, component)?;
+        }
+
+        Ok(current)
+    }
+}
+
+impl FileSystem for Iso9660 {
+    fn read_dir(&self, path: &str) -> FsResult<Box<dyn Iterator<Item = Metadata> + Send>> {
+        let dir = self.handle.parse_path(path)?;
+
+        let records = self.handle.read_dir_records(&dir)?;
+        let metadata_vec: Vec<Metadata> = records.iter().map(|record| record.into()).collect();
+
+        Ok(Box::new(metadata_vec.into_iter()))
+    }
+
+    fn open_file(&self, path: &str) -> FsResult<FileHandle> {
+        let record = self.handle.parse_path(path)?;
+
+        if record.is_directory() {
+            return Err(FsError::NotAFile);
+        }
+
+        let metadata = Metadata::from(&record);
+        let file = File::new(self.handle.clone(), record);
+
+        Ok(FileHandle::new(metadata, Box::new(file)))
+    }
+
+    fn metadata(&self, path: &str) -> FsResult<Metadata> {
+        let record = self.handle.parse_path(path)?;
+        let mut metadata = Metadata::from(&record);
+
+        // the root directory is recorded as "."
+        if record.is_special() {
+            metadata.name = "/".to_string();
+        }
+
+        Ok(metadata)
+    }
+
+    fn exists(&self, path: &str) -> FsResult<bool> {
+        match self.handle.parse_path(path) {
+            Ok(_) => Ok(true),
+            Err(FsError::FileNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! ISO 9660 filesystem, read-only
+//!
+//! reference: <https://wiki.osdev.org/ISO_9660>
+
+pub mod file;
+pub mod impls;
+pub mod record;
+pub mod volume;
+
+use crate::*;
+use file::File;
+use record::*;
+use volume::PrimaryVolumeDescriptor;
+
+const BLOCK_SIZE: usize = 2048;
+
+/// Identifies an ISO 9660 filesystem on the disk.
+pub struct Iso9660 {
+    handle: Iso9660Handle,
+}
+
+impl Iso9660 {
+    pub fn new(inner: impl BlockDevice<Block2048>) -> FsResult<Self> {
+        Ok(Self {
+            handle: Arc::new(Iso9660Impl::new(inner)?),
+        })
+    }
+}
+
+type Iso9660Handle = Arc<Iso9660Impl>;
+
+/// The ISO 9660 filesystem.
+///
+/// The first 16 logical blocks are the system area, followed by the
+/// volume descriptors. The primary volume descriptor holds the directory
+/// record of the root directory.
+///
+/// [ System Area ] [ Volume Descriptors ] [ Data ]
+pub struct Iso9660Impl {
+    pub(crate) inner: Box<dyn BlockDevice<Block2048>>,
+    pub pvd: PrimaryVolumeDescriptor,
+    pub root: DirRecord,
+}
+
+impl core::fmt::Debug for Iso9660 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Iso9660")
+            .field("pvd", &self.handle.pvd)
+            .finish()
+    }
+}
+
+impl core::fmt::Debug for Iso9660Impl {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Iso9660Impl").field("pvd", &self.pvd).finish()
+    }
+}
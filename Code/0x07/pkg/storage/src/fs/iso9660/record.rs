@@ -0,0 +1,179 @@
+//! Directory Record
+//!
+//! reference: <https://wiki.osdev.org/ISO_9660#Directories>
+
+use crate::*;
+use bitflags::bitflags;
+use chrono::LocalResult::Single;
+use chrono::{FixedOffset, TimeZone, Utc};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DirRecord {
+    pub name: String,
+    /// Logical block of the data
+    pub extent: u32,
+    /// Length of the data in bytes
+    pub size: u32,
+    pub flags: FileFlags,
+    pub recorded: Option<FsTime>,
+}
+
+bitflags! {
+    /// File Flags
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct FileFlags: u8 {
+        const HIDDEN      = 0x01;
+        const DIRECTORY   = 0x02;
+        const ASSOCIATED  = 0x04;
+        const RECORD      = 0x08;
+        const PROTECTION  = 0x10;
+        const MULTI_EXTENT = 0x80;
+    }
+}
+
+impl DirRecord {
+    /// Length of a record without its file identifier
+    pub const MIN_LEN: usize = 33;
+
+    /// Parse a directory record, `data` starts with the length of the record
+    ///
+    /// Numbers are stored in both byte orders, only the little endian half is read.
+    pub fn parse(data: &[u8]) -> FsResult<DirRecord> {
+        let len = *data.first().ok_or(FsError::InvalidOperation)? as usize;
+        let name_len = *data.get(32).ok_or(FsError::InvalidOperation)? as usize;
+
+        if len < Self::MIN_LEN || len > data.len() || Self::MIN_LEN + name_len > len {
+            return Err(FsError::InvalidOperation);
+        }
+
+        let extent = u32::from_le_bytes(data[2..6].try_into().unwrap());
+        let size = u32::from_le_bytes(data[10..14].try_into().unwrap());
+        let recorded = parse_datetime(&data[18..25]);
+        let flags = FileFlags::from_bits_truncate(data[25]);
+        let name = parse_name(&data[33..33 + name_len])?;
+
+        Ok(DirRecord {
+            name,
+            extent,
+            size,
+            flags,
+            recorded,
+        })
+    }
+
+    /// Check if this record represents a directory
+    pub fn is_directory(&self) -> bool {
+        self.flags.contains(FileFlags::DIRECTORY)
+    }
+
+    /// Check if this record is the current or the parent directory
+    pub fn is_special(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
+
+    /// File identifiers are upper case d-characters, so names are matched
+    /// case-insensitively.
+    pub fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+/// The identifiers of the current and the parent directory are a single
+/// byte 0x00 and 0x01, file identifiers end with the version ";1".
+fn parse_name(data: &[u8]) -> FsResult<String> {
+    match data {
+        [0x00] => return Ok(String::from(".")),
+        [0x01] => return Ok(String::from("..")),
+        _ => {}
+    }
+
+    let name = core::str::from_utf8(data).map_err(|_| FilenameError::Utf8Error)?;
+    let name = name.split(';').next().unwrap_or(name);
+
+    // a file without extension may still be recorded with the separator
+    Ok(String::from(name.trim_end_matches('.')))
+}
+
+/// Recording date and time: years since 1900, month, day, hour, minute,
+/// second, and the offset from GMT in 15 minute intervals.
+fn parse_datetime(data: &[u8]) -> Option<FsTime> {
+    if data.iter().all(|&b| b == 0) {
+        return None;
+    }
+
+    let year = data[0] as i32 + 1900;
+    let offset = FixedOffset::east_opt(data[6] as i8 as i32 * 15 * 60)?;
+
+    if let Single(datetime) = offset.with_ymd_and_hms(
+        year,
+        data[1] as u32,
+        data[2] as u32,
+        data[3] as u32,
+        data[4] as u32,
+        data[5] as u32,
+    ) {
+        Some(datetime.with_timezone(&Utc))
+    } else {
+        None
+    }
+}
+
+impl From<&DirRecord> for Metadata {
+    fn from(record: &DirRecord) -> Metadata {
+        Metadata {
+            entry_type: if record.is_directory() {
+                FileType::Directory
+            } else {
+                FileType::File
+            },
+            name: record.name.clone(),
+            len: if record.is_directory() {
+                0
+            } else {
+                record.size as usize
+            },
+            created: record.recorded,
+            accessed: None,
+            modified: record.recorded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir_record() {
+        let data = hex_literal::hex!(
+            "2e 00 14 00 00 00 00 00 00 14 34 12 00 00 00 00
+             12 34 7c 05 11 0c 22 38 20 00 00 00 01 00 00 01
+             0c 52 45 41 44 4d 45 2e 54 58 54 3b 31 00"
+        );
+
+        let res = DirRecord::parse(&data).unwrap();
+
+        assert_eq!(res.name, "README.TXT");
+        assert!(res.matches("readme.txt"));
+        assert_eq!(res.extent, 0x14);
+        assert_eq!(res.size, 0x1234);
+        assert_eq!(res.flags, FileFlags::empty());
+        assert_eq!(
+            res.recorded,
+            Some(Utc.with_ymd_and_hms(2024, 5, 17, 4, 34, 56).unwrap())
+        );
+
+        let mut root = data;
+        root[25] = FileFlags::DIRECTORY.bits();
+        root[32] = 1;
+        root[33] = 0x00;
+
+        let res = DirRecord::parse(&root).unwrap();
+
+        assert_eq!(res.name, ".");
+        assert!(res.is_directory());
+        assert!(res.is_special());
+
+        println!("{:#?}", res);
+    }
+}
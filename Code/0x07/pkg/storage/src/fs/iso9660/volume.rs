@@ -0,0 +1,67 @@
+//! ISO 9660 Volume Descriptors
+//!
+//! reference: <https://wiki.osdev.org/ISO_9660#Volume_Descriptors>
+
+use crate::*;
+
+/// Represents a Primary Volume Descriptor.
+///
+/// Volume descriptors start at logical block 16, each of them takes a
+/// whole block, and the set ends with a terminator.
+pub struct PrimaryVolumeDescriptor {
+    data: [u8; 2048],
+}
+
+impl PrimaryVolumeDescriptor {
+    /// Logical block of the first volume descriptor
+    pub const FIRST_BLOCK: usize = 16;
+
+    pub const TYPE_PRIMARY: u8 = 1;
+    pub const TYPE_TERMINATOR: u8 = 255;
+
+    /// Attempt to parse a Primary Volume Descriptor from a 2048 byte block.
+    pub fn new(data: &[u8]) -> FsResult<PrimaryVolumeDescriptor> {
+        let data = data.try_into().map_err(|_| FsError::InvalidOperation)?;
+        let pvd = PrimaryVolumeDescriptor { data };
+
+        if pvd.identifier() != b"CD001" || pvd.type_code() != Self::TYPE_PRIMARY {
+            return Err(FsError::InvalidOperation);
+        }
+
+        Ok(pvd)
+    }
+
+    /// Returns the type code of a volume descriptor block
+    pub fn type_of(data: &[u8]) -> Option<u8> {
+        (data.get(1..6)? == b"CD001").then_some(data[0])
+    }
+
+    // Numbers are stored in both byte orders, the little endian half goes first
+    define_field!(u8, 0, type_code);
+    define_field!([u8; 5], 1, identifier);
+    define_field!(u8, 6, version);
+    define_field!([u8; 32], 8, system_identifier);
+    define_field!([u8; 32], 40, volume_identifier);
+    define_field!(u32, 80, volume_space_size);
+    define_field!(u16, 120, volume_set_size);
+    define_field!(u16, 124, volume_sequence_number);
+    define_field!(u16, 128, logical_block_size);
+    define_field!(u32, 132, path_table_size);
+    define_field!([u8; 34], 156, root_directory_record);
+    define_field!([u8; 128], 318, publisher_identifier);
+    define_field!([u8; 128], 574, application_identifier);
+}
+
+impl core::fmt::Debug for PrimaryVolumeDescriptor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Primary Volume Descriptor")
+            .field("System Identifier", &self.system_identifier_str().trim_end())
+            .field("Volume Identifier", &self.volume_identifier_str().trim_end())
+            .field("Volume Space Size", &self.volume_space_size())
+            .field("Volume Set Size", &self.volume_set_size())
+            .field("Volume Sequence Number", &self.volume_sequence_number())
+            .field("Logical Block Size", &self.logical_block_size())
+            .field("Path Table Size", &self.path_table_size())
+            .finish()
+    }
+}
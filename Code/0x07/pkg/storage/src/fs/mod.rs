@@ -1 +1,2 @@
 pub mod fat16;
+pub mod iso9660;
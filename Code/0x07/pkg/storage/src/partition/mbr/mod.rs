@@ -24,6 +24,25 @@ where
     _block: PhantomData<B>,
 }
 
+impl<T, B> MbrTable<T, B>
+where
+    T: BlockDevice<B> + Clone,
+    B: BlockTrait,
+{
+    /// The partition in each of the 4 slots, `None` where the type is 0
+    pub fn slots(&self) -> [Option<Partition<T, B>>; 4] {
+        self.partitions.map(|part| {
+            (part.partition_type() != 0).then(|| {
+                Partition::new(
+                    self.inner.clone(),
+                    part.begin_lba() as usize,
+                    part.total_lba() as usize,
+                )
+            })
+        })
+    }
+}
+
 impl<T, B> PartitionTable<T, B> for MbrTable<T, B>
 where
     T: BlockDevice<B> + Clone,
@@ -65,7 +65,7 @@ where
     B: BlockTrait,
 {
     fn block_count(&self) -> FsResult<usize> {
-        self.inner.block_count()
+        Ok(self.size)
     }
 
     fn read_block(&self, offset: usize, block: &mut B) -> FsResult {
@@ -87,4 +87,24 @@ where
         // FIXME: write to the inner device
         self.inner.write_block(self.offset + offset, block)
     }
+
+    fn read_blocks(&self, offset: usize, blocks: &mut [B]) -> FsResult {
+        if offset + blocks.len() > self.size {
+            return Err(FsError::InvalidOffset);
+        }
+
+        self.inner.read_blocks(self.offset + offset, blocks)
+    }
+
+    fn write_blocks(&self, offset: usize, blocks: &[B]) -> FsResult {
+        if offset + blocks.len() > self.size {
+            return Err(FsError::InvalidOffset);
+        }
+
+        self.inner.write_blocks(self.offset + offset, blocks)
+    }
+
+    fn flush(&self) -> FsResult {
+        self.inner.flush()
+    }
 }
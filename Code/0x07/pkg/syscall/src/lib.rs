@@ -4,6 +4,89 @@ use num_enum::FromPrimitive;
 
 pub mod macros;
 
+/// `flock` operations, `LOCK_NB` can be or-ed with `LOCK_SH` / `LOCK_EX`
+pub const LOCK_SH: usize = 1;
+pub const LOCK_EX: usize = 2;
+pub const LOCK_NB: usize = 4;
+pub const LOCK_UN: usize = 8;
+
+/// Error numbers, returned negated by the system calls that document them
+pub const EINTR: isize = 4;
+pub const EAGAIN: isize = 11;
+pub const EFAULT: isize = 14;
+pub const EINVAL: isize = 22;
+
+/// `ioctl` requests on a terminal
+///
+/// `TTY_GET_MODE` returns the mode bits, `TTY_SET_MODE` sets them from the
+/// argument, and `TTY_CONTINUE` resumes the last job stopped by Ctrl-Z in
+/// the foreground, returning its pid or 0 if there is none.
+pub const TTY_GET_MODE: usize = 0;
+pub const TTY_SET_MODE: usize = 1;
+pub const TTY_CONTINUE: usize = 2;
+/// `TTY_GET_ATTR` and `TTY_SET_ATTR` read and change the line settings of a
+/// serial terminal, like `tcgetattr` / `tcsetattr`, the argument points to
+/// a `SerialAttr`
+pub const TTY_GET_ATTR: usize = 3;
+pub const TTY_SET_ATTR: usize = 4;
+
+/// Terminal mode bits, a raw terminal has none of them
+///
+/// `TTY_CANON` reads line by line with line editing, `TTY_ECHO` echoes the
+/// input, and `TTY_ISIG` turns Ctrl-C / Ctrl-Z into interrupt and suspend.
+/// Reads wait for the input and return `-EINTR` if Ctrl-C comes meanwhile.
+/// `TTY_NONBLOCK` belongs to the file descriptor rather than the terminal,
+/// reads through it return `-EAGAIN` at once if nothing is ready.
+pub const TTY_CANON: usize = 1;
+pub const TTY_ECHO: usize = 2;
+pub const TTY_ISIG: usize = 4;
+pub const TTY_NONBLOCK: usize = 8;
+
+/// Parity of `SerialAttr`
+pub const PARITY_NONE: u8 = 0;
+pub const PARITY_ODD: u8 = 1;
+pub const PARITY_EVEN: u8 = 2;
+
+/// Line settings of a serial terminal
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SerialAttr {
+    /// Must divide 115200
+    pub baud_rate: u32,
+    /// 5 to 8
+    pub data_bits: u8,
+    pub parity: u8,
+    /// 1 or 2
+    pub stop_bits: u8,
+    /// Bytes in the receive FIFO raising an interrupt: 1, 4, 8 or 14
+    pub fifo_trigger: u8,
+    /// RTS/CTS hardware flow control
+    pub flow_control: bool,
+}
+
+/// Levels of the kernel log records, `Dmesg` reads the ones at a level or
+/// above, i.e. more severe
+pub const LOG_ERROR: usize = 1;
+pub const LOG_WARN: usize = 2;
+pub const LOG_INFO: usize = 3;
+pub const LOG_DEBUG: usize = 4;
+pub const LOG_TRACE: usize = 5;
+
+/// Clocks of `ClockGetTime`, the wall clock and the time since the boot
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+/// A time read by `ClockGetTime`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+/// Returned by `WaitPid` when the process is stopped by Ctrl-Z
+pub const WAIT_STOPPED: isize = isize::MIN;
+
 #[repr(usize)]
 #[derive(Clone, Debug, FromPrimitive)]
 pub enum Syscall {
@@ -11,6 +94,7 @@ pub enum Syscall {
     Write = 1,
 Sem = 2,
     Brk = 12,
+    Ioctl = 16,
     GetPid = 39,
 
     Fork = 58,
@@ -20,7 +104,15 @@ Sem = 2,
 
     Open = 62,
     Close = 63,
+    Flock = 73,
+    Dmesg = 103,
+
+    Sync = 162,
+    ClockGetTime = 228,
+    GetRandom = 318,
 
+    ListPci = 65528,
+    ListBlk = 65529,
     ListDir = 65530,
     ListApp = 65531,
     Stat = 65532,
@@ -23,6 +23,16 @@ parser.add_argument('--dry-run', action='store_true', help='Enable dry run')
 parser.add_argument('--bios', type=str,
                     default=os.path.join('assets', 'OVMF.fd'), help='Set BIOS path')
 parser.add_argument('--boot', type=str, default='esp', help='Set boot path')
+parser.add_argument('--cdrom', type=str, default=None,
+                    help='Attach an ISO 9660 image as CD-ROM')
+parser.add_argument('--virtio', type=str, default=None,
+                    help='Attach a raw disk image as virtio-blk device (vda)')
+parser.add_argument('--sata', type=str, default=None,
+                    help='Attach a raw disk image to an AHCI controller (sda)')
+parser.add_argument('--nvme', type=str, default=None,
+                    help='Attach a raw disk image as NVMe namespace (nvme0n1)')
+parser.add_argument('--com2', type=str, default=None,
+                    help='Attach COM2 to a QEMU character device, e.g. pty or tcp::4444,server,nowait')
 parser.add_argument('--debug-listen', type=str, default='0.0.0.0:1234',
                     help='Set listen address for gdbserver')
 
@@ -87,6 +97,25 @@ def qemu(output: str = '-nographic', memory: str = '96M', debug: bool = False, i
     qemu_args = [qemu_exe, '-bios', args.bios, '-net', 'none', *output.split(),
                  '-m', memory, '-drive', 'format=raw,file=fat:esp', '-snapshot']
 
+    if args.cdrom:
+        qemu_args += ['-cdrom', args.cdrom]
+
+    if args.virtio:
+        qemu_args += ['-drive', f'if=virtio,format=raw,file={args.virtio}']
+
+    if args.sata:
+        qemu_args += ['-device', 'ahci,id=ahci',
+                      '-drive', f'id=sata0,if=none,format=raw,file={args.sata}',
+                      '-device', 'ide-hd,drive=sata0,bus=ahci.0']
+
+    if args.nvme:
+        qemu_args += ['-drive', f'id=nvme0,if=none,format=raw,file={args.nvme}',
+                      '-device', 'nvme,serial=ysos0,drive=nvme0']
+
+    if args.com2:
+        # COM1 must be given first, it is no longer the default one
+        qemu_args += ['-serial', 'mon:stdio', '-serial', args.com2]
+
     if debug:
         qemu_args += ['-gdb', f'tcp:{args.debug_listen}', '-S']
     elif intdbg: